@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::{env, path::Path, process};
+
+use futures_util::StreamExt;
+use serde_json::json;
+use tokio::time::{interval, Duration};
+use zbus::Connection;
+
+use flow_lib::client::{
+    format::{
+        download_to_json, format_duration, format_size, format_speed, get_file_name, get_file_path,
+        get_percent, progress_to_json,
+    },
+    get_error_message, FlowdProxy, NotifyDownloadDeleteStream, NotifyDownloadErrorStream,
+    NotifyDownloadProgressStream, NotifyDownloadUpdateStream,
+};
+use flow_lib::core::{
+    config::{self, SettingType},
+    dbus::values::{to_dbus_value, to_toml_table},
+    download::{Download, DownloadFilter, DownloadStatus, ProgressInfo},
+};
+
+const USAGE: &str = "Usage: flowctl [--json] <command>
+
+Commands:
+  add <url> [--out <name>] [--dir <directory>] [--wait]
+  ls [--status <status>[,<status>...]] [--category <name>] [--host <host>]
+     [--search <text>] [--sort <key>] [--desc] [--limit <count>]
+  pause | resume | cancel | restart | rm <id>...
+  watch [<id>...]
+  config get [<key>]
+  config set <key> <value>";
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    let json = take_flag(&mut args, "--json");
+    if args.is_empty() || take_flag(&mut args, "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let command = args.remove(0);
+
+    let connection = Connection::session()
+        .await
+        .unwrap_or_else(|e| fail(&format!("Could not connect to the session bus: {}", e)));
+    let proxy = FlowdProxy::new(&connection)
+        .await
+        .unwrap_or_else(|e| fail(&get_error_message(&e)));
+
+    match command.as_str() {
+        "add" => add(&proxy, args, json).await,
+        "ls" => list(&proxy, args, json).await,
+        "pause" | "resume" | "cancel" | "restart" | "rm" => {
+            apply_action(&proxy, &command, args).await
+        }
+        "watch" => watch(&proxy, args, json).await,
+        "config" => edit_config(&proxy, args, json).await,
+        _ => usage_error(&format!("Unknown command: {}", command)),
+    }
+}
+
+/**
+ * This function queues a download and prints its id, or waits for it
+ * to finish and exits with an error if it did not complete.
+ */
+async fn add(proxy: &FlowdProxy<'_>, mut args: Vec<String>, json: bool) {
+    let out = take_option(&mut args, "--out").unwrap_or_default();
+    // The daemon does not run in the directory of the client
+    let dir = take_option(&mut args, "--dir")
+        .map(|dir| get_absolute_path(&dir))
+        .unwrap_or_default();
+    let wait = take_flag(&mut args, "--wait");
+    let Ok([url]) = <[String; 1]>::try_from(get_arguments(args)) else {
+        usage_error("add takes one URL");
+    };
+
+    // Subscribe first so that no signal is missed between the call and the wait
+    let streams = match wait {
+        true => Some(subscribe(proxy).await),
+        false => None,
+    };
+    let id = proxy
+        .add_download(&url, &out, &dir)
+        .await
+        .unwrap_or_else(|e| fail(&get_error_message(&e)));
+    let Some(streams) = streams else {
+        match json {
+            true => println!("{}", json!({ "id": id })),
+            false => println!("{}", id),
+        }
+        return;
+    };
+
+    let (download, error) = wait_for_download(proxy, id, streams, !json).await;
+    if json {
+        let mut value = download_to_json(&download);
+        value["error"] = json!(error);
+        println!("{}", value);
+    } else {
+        let path = get_file_path(&download).unwrap_or(&download.url);
+        match (&download.status, error) {
+            (DownloadStatus::Completed, _) => println!("{}", path),
+            (status, Some(error)) => eprintln!("#{} {}: {}", id, status.get_description(), error),
+            (status, None) => eprintln!("#{} {}", id, status.get_description()),
+        }
+    }
+    if !matches!(download.status, DownloadStatus::Completed) {
+        process::exit(1);
+    }
+}
+
+type SignalStreams = (
+    NotifyDownloadUpdateStream<'static>,
+    NotifyDownloadProgressStream<'static>,
+    NotifyDownloadErrorStream<'static>,
+    NotifyDownloadDeleteStream<'static>,
+);
+
+async fn subscribe(proxy: &FlowdProxy<'_>) -> SignalStreams {
+    let streams = tokio::try_join!(
+        proxy.receive_notify_download_update(),
+        proxy.receive_notify_download_progress(),
+        proxy.receive_notify_download_error(),
+        proxy.receive_notify_download_delete(),
+    );
+    streams.unwrap_or_else(|e| fail(&get_error_message(&e)))
+}
+
+/**
+ * This function waits until a download stops, showing its progress on
+ * the terminal, and returns it with the last error reported for it.
+ */
+async fn wait_for_download(
+    proxy: &FlowdProxy<'_>,
+    id: i64,
+    (mut updates, mut progress, mut errors, mut deletes): SignalStreams,
+    show_progress: bool,
+) -> (Download, Option<String>) {
+    let show_progress = show_progress && io::stderr().is_terminal();
+    let mut last_error = None;
+    // Status changes are not all signaled, so the download is also polled
+    let mut poll = interval(Duration::from_secs(1));
+    let download = loop {
+        tokio::select! {
+            Some(signal) = updates.next() => {
+                let Ok(args) = signal.args() else { continue };
+                if args.download_info.id == id && is_stopped(&args.download_info.status) {
+                    break args.download_info;
+                }
+            }
+            Some(signal) = progress.next() => {
+                let Ok(args) = signal.args() else { continue };
+                if show_progress && args.id == id {
+                    let progress = ProgressInfo {
+                        id,
+                        downloaded: args.progress,
+                        size: args.content_length,
+                        speed: args.speed,
+                        eta: args.eta,
+                        ..Default::default()
+                    };
+                    eprint!("\r\x1b[K{}", get_progress_line(&progress));
+                    _ = io::stderr().flush();
+                }
+            }
+            Some(signal) = errors.next() => {
+                let Ok(args) = signal.args() else { continue };
+                if args.id == id {
+                    last_error = Some(args.error.to_string());
+                }
+            }
+            Some(signal) = deletes.next() => {
+                if signal.args().is_ok_and(|args| args.download_id == id) {
+                    fail(&format!("Download #{} was deleted", id));
+                }
+            }
+            _ = poll.tick() => {
+                let download = proxy
+                    .get_download(id)
+                    .await
+                    .unwrap_or_else(|e| fail(&get_error_message(&e)));
+                if is_stopped(&download.status) {
+                    break download;
+                }
+            }
+        }
+    };
+    if show_progress {
+        eprint!("\r\x1b[K");
+    }
+    (download, last_error)
+}
+
+/**
+ * This function returns whether no task works on a download anymore,
+ * downloads that are retried later are still running.
+ */
+fn is_stopped(status: &DownloadStatus) -> bool {
+    !matches!(
+        status,
+        DownloadStatus::Pending
+            | DownloadStatus::Starting
+            | DownloadStatus::InProgress
+            | DownloadStatus::Retrying
+    )
+}
+
+/**
+ * This function prints the downloads matching the filter options
+ * as a table, or as a JSON object with the total count.
+ */
+async fn list(proxy: &FlowdProxy<'_>, mut args: Vec<String>, json: bool) {
+    let statuses = take_option(&mut args, "--status").map(|statuses| {
+        let statuses = statuses
+            .split(',')
+            .map(str::to_string)
+            .collect::<Vec<String>>();
+        if let Some(status) = statuses
+            .iter()
+            .find(|status| DownloadStatus::from_string(status).is_none())
+        {
+            usage_error(&format!("Unknown status: {}", status));
+        }
+        statuses
+    });
+    let filter = DownloadFilter {
+        statuses,
+        category: take_option(&mut args, "--category"),
+        host: take_option(&mut args, "--host"),
+        text: take_option(&mut args, "--search"),
+        ..Default::default()
+    };
+    let sort = take_option(&mut args, "--sort").unwrap_or("date_added".to_string());
+    let descending = take_flag(&mut args, "--desc");
+    let limit = take_option(&mut args, "--limit").map_or(0, |limit| {
+        limit
+            .parse::<u32>()
+            .unwrap_or_else(|_| usage_error(&format!("Invalid count: {}", limit)))
+    });
+    if !get_arguments(args).is_empty() {
+        usage_error("ls takes no arguments");
+    }
+
+    let (downloads, total) = proxy
+        .list_downloads(filter, &sort, descending, 0, limit)
+        .await
+        .unwrap_or_else(|e| fail(&get_error_message(&e)));
+    if json {
+        let downloads = downloads.iter().map(download_to_json).collect::<Vec<_>>();
+        println!("{}", json!({ "total": total, "downloads": downloads }));
+        return;
+    }
+
+    let id_width = downloads
+        .iter()
+        .map(|download| download.id.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(2);
+    println!(
+        "{:>id_width$}  {:<12}  {:>5}  {:>10}  NAME",
+        "ID", "STATUS", "DONE", "SIZE"
+    );
+    for download in &downloads {
+        let percent = match download.status {
+            DownloadStatus::Completed => Some(100.0),
+            _ => get_percent(download.downloaded_bytes, download.size.unwrap_or(0)),
+        };
+        println!(
+            "{:>id_width$}  {:<12}  {:>5}  {:>10}  {}",
+            download.id,
+            download.status.get_string(),
+            percent.map_or("-".to_string(), |percent| format!("{:.0}%", percent)),
+            download.size.map_or("-".to_string(), format_size),
+            get_file_name(download)
+        );
+    }
+    if (downloads.len() as u32) < total {
+        println!("{} of {} downloads", downloads.len(), total);
+    }
+}
+
+/**
+ * This function sends an action to each download, a missing download
+ * does not stop the others but the exit status reports it.
+ */
+async fn apply_action(proxy: &FlowdProxy<'_>, action: &str, args: Vec<String>) {
+    let ids = get_ids(args);
+    if ids.is_empty() {
+        usage_error(&format!("{} takes at least one id", action));
+    }
+
+    let mut failed = false;
+    for id in ids {
+        // Actions are queued by the daemon, so a missing download would go unnoticed
+        let result = match proxy.get_download(id).await {
+            Ok(_) => match action {
+                "pause" => proxy.pause_download(id).await,
+                "resume" => proxy.resume_download(id).await,
+                "cancel" => proxy.cancel_download(id).await,
+                "restart" => proxy.restart_download(id).await,
+                _ => proxy.delete_download(id).await,
+            },
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(response) if response == "OK" => {}
+            Ok(_) => {
+                eprintln!("Could not {} download #{}", action, id);
+                failed = true;
+            }
+            Err(e) => {
+                eprintln!("{}", get_error_message(&e));
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/**
+ * This function prints the progress and the changes of the downloads
+ * until it is interrupted, one JSON object per line with --json.
+ */
+async fn watch(proxy: &FlowdProxy<'_>, args: Vec<String>, json: bool) {
+    let ids = get_ids(args);
+    let is_watched = |id: i64| ids.is_empty() || ids.contains(&id);
+
+    let (mut updates, mut progress, mut errors, mut deletes) = subscribe(proxy).await;
+    // Names are only in updates, the ones of running downloads are fetched once
+    let mut downloads = HashMap::<i64, (String, String)>::new();
+    loop {
+        let line = tokio::select! {
+            Some(signal) = updates.next() => {
+                let Ok(args) = signal.args() else { continue };
+                let download = args.download_info;
+                if !is_watched(download.id) {
+                    continue;
+                }
+                let status = download.status.get_string().to_string();
+                let name = get_file_name(&download);
+                let previous = downloads.insert(download.id, (name.clone(), status.clone()));
+                if json {
+                    json!({ "event": "update", "download": download_to_json(&download) })
+                        .to_string()
+                } else if previous.is_some_and(|(_, previous)| previous == status) {
+                    continue;
+                } else {
+                    format!("#{} {}: {}", download.id, name, download.status.get_description())
+                }
+            }
+            Some(signal) = progress.next() => {
+                let Ok(args) = signal.args() else { continue };
+                if !is_watched(args.id) {
+                    continue;
+                }
+                let progress = ProgressInfo {
+                    id: args.id,
+                    downloaded: args.progress,
+                    size: args.content_length,
+                    speed: args.speed,
+                    average_speed: args.average_speed,
+                    eta: args.eta,
+                    elapsed: args.elapsed,
+                };
+                if json {
+                    let mut value = progress_to_json(&progress);
+                    value["event"] = json!("progress");
+                    value.to_string()
+                } else {
+                    if !downloads.contains_key(&progress.id) {
+                        if let Ok(download) = proxy.get_download(progress.id).await {
+                            let status = download.status.get_string().to_string();
+                            downloads.insert(download.id, (get_file_name(&download), status));
+                        }
+                    }
+                    let name = downloads.get(&progress.id).map_or("", |(name, _)| name);
+                    format!("#{} {}: {}", progress.id, name, get_progress_line(&progress))
+                }
+            }
+            Some(signal) = errors.next() => {
+                let Ok(args) = signal.args() else { continue };
+                if !is_watched(args.id) {
+                    continue;
+                }
+                match json {
+                    true => json!({ "event": "error", "id": args.id, "error": args.error })
+                        .to_string(),
+                    false => format!("#{} error: {}", args.id, args.error),
+                }
+            }
+            Some(signal) = deletes.next() => {
+                let Ok(args) = signal.args() else { continue };
+                if !is_watched(args.download_id) {
+                    continue;
+                }
+                downloads.remove(&args.download_id);
+                match json {
+                    true => json!({ "event": "delete", "id": args.download_id }).to_string(),
+                    false => format!("#{} deleted", args.download_id),
+                }
+            }
+            else => break,
+        };
+        println!("{}", line);
+    }
+}
+
+fn get_progress_line(progress: &ProgressInfo) -> String {
+    let percent = get_percent(progress.downloaded, progress.size)
+        .map_or("-".to_string(), |percent| format!("{:.1}%", percent));
+    let size = match progress.size {
+        0 => format_size(progress.downloaded),
+        size => format!(
+            "{} / {}",
+            format_size(progress.downloaded),
+            format_size(size)
+        ),
+    };
+    format!(
+        "{}  {}  {}  ETA {}",
+        percent,
+        size,
+        format_speed(progress.speed),
+        format_duration(progress.eta)
+    )
+}
+
+/**
+ * This function prints the config or one of its settings as TOML,
+ * or sets a setting in the user config through the daemon.
+ */
+async fn edit_config(proxy: &FlowdProxy<'_>, mut args: Vec<String>, json: bool) {
+    if args.is_empty() {
+        usage_error("config takes get or set");
+    }
+    let action = args.remove(0);
+    match (action.as_str(), get_arguments(args).as_slice()) {
+        ("get", []) => {
+            let config = get_config_table(proxy).await;
+            match json {
+                true => println!("{}", json!(config)),
+                false => print!("{}", toml::to_string(&config).unwrap_or_default()),
+            }
+        }
+        ("get", [key]) => {
+            let config = get_config_table(proxy).await;
+            let mut keys = key.split('.');
+            let first = keys.next().unwrap_or_default();
+            if config::get_setting(first).is_none() {
+                fail(&format!("Unknown setting: {}", first));
+            }
+            let value = keys.fold(config.get(first), |value, key| {
+                value.and_then(|value| value.get(key))
+            });
+            match (value, json) {
+                (value, true) => println!("{}", json!(value)),
+                (Some(toml::Value::String(value)), false) => println!("{}", value),
+                (Some(toml::Value::Table(table)), false) => {
+                    print!("{}", toml::to_string(table).unwrap_or_default())
+                }
+                (Some(value), false) => println!("{}", value),
+                // Optional settings that are not set have no value
+                (None, false) => {}
+            }
+        }
+        ("set", [key, value]) => {
+            let Some(setting) = config::get_setting(key) else {
+                fail(&format!("Unknown setting: {}", key));
+            };
+            let value = parse_config_value(&setting.setting_type, value);
+            proxy
+                .set_config_value(key, &to_dbus_value(&value))
+                .await
+                .unwrap_or_else(|e| fail(&get_error_message(&e)));
+        }
+        _ => usage_error("Usage: config get [<key>] | config set <key> <value>"),
+    }
+}
+
+async fn get_config_table(proxy: &FlowdProxy<'_>) -> toml::Table {
+    let config = proxy
+        .get_config()
+        .await
+        .unwrap_or_else(|e| fail(&get_error_message(&e)));
+    to_toml_table(&config)
+}
+
+/**
+ * This function reads a value typed on the command line: text settings
+ * take it as is, the others take a TOML value like 5, true or ["a", "b"].
+ */
+fn parse_config_value(setting_type: &SettingType, value: &str) -> toml::Value {
+    if matches!(setting_type, SettingType::Text | SettingType::Choice(_)) {
+        return toml::Value::from(value);
+    }
+    format!("value = {}", value)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        // The daemon reports the type the setting expects
+        .unwrap_or_else(|| toml::Value::from(value))
+}
+
+fn get_absolute_path(path: &str) -> String {
+    if path.starts_with('~') || Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    env::current_dir()
+        .map(|dir| dir.join(path).to_string_lossy().to_string())
+        .unwrap_or(path.to_string())
+}
+
+/**
+ * This function removes a flag from the arguments and returns whether
+ * it was there.
+ */
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let position = args.iter().position(|arg| arg == flag);
+    if let Some(position) = position {
+        args.remove(position);
+    }
+    position.is_some()
+}
+
+/**
+ * This function removes an option and its value from the arguments.
+ */
+fn take_option(args: &mut Vec<String>, option: &str) -> Option<String> {
+    let position = args.iter().position(|arg| arg == option)?;
+    if position + 1 >= args.len() {
+        usage_error(&format!("{} takes a value", option));
+    }
+    args.remove(position);
+    Some(args.remove(position))
+}
+
+/**
+ * This function returns the arguments left once the options are taken,
+ * any other option is an error.
+ */
+fn get_arguments(args: Vec<String>) -> Vec<String> {
+    if let Some(option) = args.iter().find(|arg| arg.starts_with("--")) {
+        usage_error(&format!("Unknown option: {}", option));
+    }
+    args
+}
+
+fn get_ids(args: Vec<String>) -> Vec<i64> {
+    get_arguments(args)
+        .iter()
+        .map(|id| {
+            id.parse::<i64>()
+                .unwrap_or_else(|_| usage_error(&format!("Invalid id: {}", id)))
+        })
+        .collect()
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
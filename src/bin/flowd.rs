@@ -1,11 +1,16 @@
-use std::{error::Error, sync::Arc};
-use tokio::{sync::broadcast, time::Duration};
+use std::{env, error::Error, path::Path, process, sync::Arc};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::Duration,
+};
 
 use flow_lib::core::{
     config,
     db::{self, DBError},
     dbus::FlowListener,
-    download::{DownloadEvent, Downloader},
+    download::{DownloadEvent, DownloadStatus, Downloader},
+    import::{self, ImportOptions},
+    store::{DownloadStore, SqliteStore},
 };
 use zbus::{self, ConnectionBuilder, SignalContext};
 
@@ -13,12 +18,47 @@ use zbus::{self, ConnectionBuilder, SignalContext};
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
-    db::init().await?;
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    apply_path_options(&mut args);
+    if args.first().is_some_and(|arg| arg == "--check-db") {
+        check_db().await;
+        return Ok(());
+    }
+    if args.first().is_some_and(|arg| arg == "--check-config") {
+        check_config().await;
+        return Ok(());
+    }
+
+    let store: Arc<dyn DownloadStore> = match SqliteStore::open(&db::get_db_path()).await {
+        Ok(store) => Arc::new(store),
+        Err(e) => {
+            eprintln!("Could not open the database: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if args.first().is_some_and(|arg| arg == "--migrate-only") {
+        println!("Database schema is at version {}", db::SCHEMA_VERSION);
+        return Ok(());
+    }
+    if args.first().is_some_and(|arg| arg == "--import") {
+        import_from_args(&*store, &args[1..]).await;
+        return Ok(());
+    }
 
     let (tx, _) = broadcast::channel::<DownloadEvent>(32);
 
     // Initialize downloads controller
-    let downloader_arc = Arc::new(Downloader::new(tx.clone(), tx.subscribe()));
+    let downloader_arc = Arc::new(Downloader::new(
+        tx.clone(),
+        tx.subscribe(),
+        Arc::clone(&store),
+    ));
+    match downloader_arc.requeue_retrying_downloads().await {
+        Ok(0) => {}
+        Ok(count) => log::info!("Queued {} downloads that were waiting to be retried", count),
+        Err(e) => log::error!("Error queuing downloads waiting to be retried: {}", e),
+    }
 
     // Listen to events from DBus
     let events_listener_arc = Arc::clone(&downloader_arc);
@@ -26,12 +66,37 @@ async fn main() -> Result<(), Box<dyn Error>> {
         events_listener_arc.listen_to_dbus_events().await;
     });
 
+    // Report the overall progress of downloads
+    let global_progress_arc = Arc::clone(&downloader_arc);
+    tokio::spawn(async move {
+        global_progress_arc.report_global_progress().await;
+    });
+
+    // Reload the config when its files change, running downloads read the new limits
+    let config_tx = tx.clone();
+    tokio::spawn(async move {
+        let result = config::watch_config(|changed_keys| {
+            if let Err(e) = config_tx.send(DownloadEvent::ConfigChanged(changed_keys)) {
+                log::error!("Could not notify the config change: {}", e);
+            }
+        })
+        .await;
+        if let Err(e) = result {
+            log::error!("Could not watch the config files: {}", e);
+        }
+    });
+
     // Initialize DBus connection
     let con = ConnectionBuilder::session()?
         .name("com.github.essmehdi.Flowd")?
         .serve_at(
             "/com/github/essmehdi/Flowd/Listener",
-            FlowListener::new(tx.subscribe(), tx.clone()),
+            FlowListener::new(
+                tx.subscribe(),
+                tx.clone(),
+                downloader_arc.get_progress_tracker(),
+                Arc::clone(&store),
+            ),
         )?
         .build()
         .await?;
@@ -46,32 +111,106 @@ async fn main() -> Result<(), Box<dyn Error>> {
         listener.get().await.listen_to_events(signal_ctx).await;
     });
 
+    // Sort downloads again when the categories change
+    refresh_categories(&*store).await;
+    let categories_store = Arc::clone(&store);
+    let mut categories_rx = tx.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match categories_rx.recv().await {
+                Ok(DownloadEvent::ConfigChanged(changed_keys))
+                    if changed_keys.iter().any(|key| key == "categories") =>
+                {
+                    refresh_categories(&*categories_store).await;
+                }
+                // A missed event may have been a change of categories
+                Err(RecvError::Lagged(_)) => refresh_categories(&*categories_store).await,
+                Err(RecvError::Closed) => break,
+                Ok(_) => {}
+            }
+        }
+    });
+
     loop {
-        // TODO: Reload config only when needed by watching the config file
+        // Cached until the config files change
         let config = config::get_config().await;
-        let _ = pending_downloads_checker(Arc::clone(&downloader_arc), config.max_sim_downloads)
-            .await
-            .map_err(|e| {
-                log::error!(
-                    "Pending downloads checker: Error checking for pending downloads: {:?}",
-                    e
-                );
-            });
+        let _ = pending_downloads_checker(
+            Arc::clone(&downloader_arc),
+            &*store,
+            config.max_sim_downloads,
+        )
+        .await
+        .map_err(|e| {
+            log::error!(
+                "Pending downloads checker: Error checking for pending downloads: {:?}",
+                e
+            );
+        });
         tokio::time::sleep(Duration::from_millis(500)).await;
     }
 }
 
+const USAGE: &str = "Usage: flowd [--config <file>] [--data-dir <dir>] \
+[--check-db | --check-config | --migrate-only | --import <file or URL> ...]";
+
+/**
+ * This function applies the leading options that change where the config
+ * is read from and the data is kept, then removes them from the arguments.
+ */
+fn apply_path_options(args: &mut Vec<String>) {
+    while let Some(option) = args
+        .first()
+        .filter(|arg| matches!(arg.as_str(), "--config" | "--data-dir"))
+        .cloned()
+    {
+        let Some(value) = args.get(1).cloned() else {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        };
+        if option == "--config" {
+            // A missing file is fine, it is created once a setting is changed
+            if Path::new(&value).is_dir() {
+                eprintln!("Config path is a directory: {}", value);
+                process::exit(1);
+            }
+            config::set_user_config_path(&value);
+        } else {
+            db::set_data_dir(&value);
+        }
+        args.drain(..2);
+    }
+}
+
+/**
+ * Moves the downloads to the category they fall in with the current config.
+ * Does nothing when the categories did not change since the last run.
+ */
+async fn refresh_categories(store: &dyn DownloadStore) {
+    let config = config::get_config().await;
+    match store.refresh_categories(&config).await {
+        Ok(0) => {}
+        Ok(changed) => log::info!("Moved {} downloads to another category", changed),
+        Err(e) => log::error!("Error refreshing download categories: {}", e),
+    }
+}
+
 /**
  * This function is used to check for pending downloads
  * and start them.
  */
 async fn pending_downloads_checker(
     controller: Arc<Downloader>,
+    store: &dyn DownloadStore,
     max_downloads: u16,
 ) -> Result<(), DBError> {
-    let mut in_progress_downloads_count = db::get_in_progress_downloads().await?.len();
+    let mut in_progress_downloads_count = store
+        .get_downloads_by_status(&DownloadStatus::InProgress)
+        .await?
+        .len();
 
-    let downloads = db::get_pending_downloads().await?;
+    let downloads = store
+        .get_downloads_by_status(&DownloadStatus::Pending)
+        .await?;
     for download in downloads {
         // Check if we reached the maximum number of downloads
         if in_progress_downloads_count >= max_downloads.into() {
@@ -86,3 +225,117 @@ async fn pending_downloads_checker(
 
     Ok(())
 }
+
+const IMPORT_USAGE: &str = "Usage: flowd --import <file or URL> [--pattern <regex>] \
+[--extensions <ext,...>] [--directory <dir>] [--base-url <url>] [--group <name>]";
+
+/**
+ * This function queues the downloads listed in a file or page
+ * passed on the command line, the running daemon picks them up.
+ */
+async fn import_from_args(store: &dyn DownloadStore, args: &[String]) {
+    let Some(source) = args.first() else {
+        eprintln!("{}", IMPORT_USAGE);
+        process::exit(2);
+    };
+
+    let mut options = ImportOptions::default();
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        let Some(value) = args.next() else {
+            eprintln!("{}", IMPORT_USAGE);
+            process::exit(2);
+        };
+        match arg.as_str() {
+            "--pattern" => options.pattern = Some(value.clone()),
+            "--extensions" => {
+                options.extensions = Some(value.split(',').map(str::to_string).collect())
+            }
+            "--directory" => options.directory = Some(value.clone()),
+            "--base-url" => options.base_url = Some(value.clone()),
+            "--group" => options.group = Some(value.clone()),
+            _ => {
+                eprintln!("{}", IMPORT_USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    match import::import_downloads(store, source, &options).await {
+        Ok(ids) => println!("Queued {} downloads", ids.len()),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/**
+ * This function reports the schema version and integrity of the database
+ * without changing it, and exits with an error if it needs attention.
+ */
+async fn check_db() {
+    let check = match db::check().await {
+        Ok(check) => check,
+        Err(e) => {
+            eprintln!("Could not check the database: {}", e);
+            process::exit(1);
+        }
+    };
+
+    println!("Database: {}", check.path);
+    if !check.exists {
+        println!("Not created yet, it will be created on start");
+        return;
+    }
+    println!(
+        "Schema version: {} (this build: {})",
+        check.version,
+        db::SCHEMA_VERSION
+    );
+    for problem in &check.problems {
+        println!("Integrity problem: {}", problem);
+    }
+
+    if check.version > db::SCHEMA_VERSION {
+        println!("The database is newer than this build");
+        process::exit(1);
+    }
+    if !check.problems.is_empty() {
+        process::exit(1);
+    }
+    if check.version < db::SCHEMA_VERSION {
+        println!(
+            "{} migrations are pending, run flowd --migrate-only to apply them",
+            db::SCHEMA_VERSION - check.version
+        );
+        process::exit(1);
+    }
+    println!("The database is up to date");
+}
+
+/**
+ * This function reports the problems of the config files without
+ * starting the daemon, and exits with an error if there are any.
+ */
+async fn check_config() {
+    for path in config::get_config_paths() {
+        if Path::new(&path).exists() {
+            println!("Config file: {}", path);
+        }
+    }
+    let problems = config::check_config().await;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        let plural = if problems.len() > 1 { "s" } else { "" };
+        println!(
+            "{} problem{} found, the daemon skips the invalid settings",
+            problems.len(),
+            plural
+        );
+        process::exit(1);
+    }
+    println!("The config is valid");
+}
@@ -0,0 +1,476 @@
+use std::{env, error::Error, process, thread};
+
+use futures_util::StreamExt;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc;
+use zbus::Connection;
+
+use flow_lib::client::{
+    format::{
+        format_bar, format_duration, format_size, format_speed, get_file_name, get_file_path,
+        get_percent,
+    },
+    get_error_message,
+    list::DownloadList,
+    FlowdProxy,
+};
+use flow_lib::core::download::{Download, DownloadFilter, DownloadStatus, ProgressInfo, SortKey};
+
+const USAGE: &str = "Usage: flowtui
+
+Keys:
+  up/down, j/k   select a download
+  a              add a URL
+  p / r / c      pause, resume or cancel the download
+  R              restart the download
+  d              delete the download from the list
+  K / J          move a pending download up or down the queue
+  s              change the order
+  q              quit";
+
+/// Orders of the table, `s` goes to the next one
+const SORTS: [(SortKey, bool, &str); 3] = [
+    (SortKey::Queue, false, "queue"),
+    (SortKey::DateAdded, true, "newest"),
+    (SortKey::Status, false, "status"),
+];
+
+const BAR_WIDTH: usize = 20;
+
+enum InputMode {
+    Normal,
+    /// Typing the URL of a new download
+    AddUrl(String),
+    /// Waiting for the deletion of a download to be confirmed
+    ConfirmDelete(i64),
+}
+
+struct App<'a> {
+    proxy: FlowdProxy<'a>,
+    list: DownloadList,
+    table_state: TableState,
+    mode: InputMode,
+    /// Index in `SORTS`
+    sort: usize,
+    /// Result of the last action, shown in place of the keys until the next one
+    message: Option<(String, bool)>,
+    quit: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    if env::args().nth(1).is_some() {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let connection = Connection::session().await.unwrap_or_else(|e| {
+        eprintln!("Could not connect to the session bus: {}", e);
+        process::exit(1);
+    });
+    let mut app = App {
+        proxy: FlowdProxy::new(&connection).await?,
+        list: DownloadList::new(),
+        table_state: TableState::default(),
+        mode: InputMode::Normal,
+        sort: 0,
+        message: None,
+        quit: false,
+    };
+    // Fail before taking over the terminal if the daemon is not running
+    if let Err(e) = app.load_downloads().await {
+        eprintln!("{}", get_error_message(&e));
+        process::exit(1);
+    }
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal).await;
+    ratatui::restore();
+    result
+}
+
+impl App<'_> {
+    async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        let mut updates = self.proxy.receive_notify_download_update().await?;
+        let mut progress = self.proxy.receive_notify_download_progress().await?;
+        let mut errors = self.proxy.receive_notify_download_error().await?;
+        let mut deletes = self.proxy.receive_notify_download_delete().await?;
+        let mut config_changes = self.proxy.receive_config_changed().await?;
+        // Signals sent while the daemon was away are missed
+        let proxy = self.proxy.clone();
+        let mut owner_changes = proxy.inner().receive_owner_changed().await?;
+
+        // Reading the terminal blocks, so it has its own thread
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        thread::spawn(move || {
+            while let Ok(event) = event::read() {
+                if events_tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            tokio::select! {
+                Some(event) = events_rx.recv() => {
+                    if let Event::Key(key) = event {
+                        self.handle_key(key).await;
+                    }
+                }
+                Some(signal) = updates.next() => {
+                    let Ok(args) = signal.args() else { continue };
+                    let reorders = self.changes_order(&args.download_info);
+                    if !self.list.update_download(args.download_info) || reorders {
+                        self.refresh().await;
+                    }
+                }
+                Some(signal) = progress.next() => {
+                    let Ok(args) = signal.args() else { continue };
+                    self.list.update_progress(ProgressInfo {
+                        id: args.id,
+                        downloaded: args.progress,
+                        size: args.content_length,
+                        speed: args.speed,
+                        average_speed: args.average_speed,
+                        eta: args.eta,
+                        elapsed: args.elapsed,
+                    });
+                }
+                Some(signal) = errors.next() => {
+                    let Ok(args) = signal.args() else { continue };
+                    self.list.set_error(args.id, args.error);
+                }
+                Some(signal) = deletes.next() => {
+                    let Ok(args) = signal.args() else { continue };
+                    self.list.remove_download(args.download_id);
+                }
+                Some(_) = config_changes.next() => self.refresh().await,
+                Some(Some(_)) = owner_changes.next() => self.refresh().await,
+            }
+        }
+        Ok(())
+    }
+
+    async fn load_downloads(&mut self) -> zbus::Result<()> {
+        let (sort, descending, _) = SORTS[self.sort];
+        let (downloads, _) = self
+            .proxy
+            .list_downloads(
+                DownloadFilter::default(),
+                sort.get_string(),
+                descending,
+                0,
+                0,
+            )
+            .await?;
+        self.list.set_downloads(downloads);
+        Ok(())
+    }
+
+    /// Returns `true` if an update moves the download to another place in the current order
+    fn changes_order(&self, download: &Download) -> bool {
+        let Some(listed) = self.list.downloads().iter().find(|d| d.id == download.id) else {
+            return false;
+        };
+        match SORTS[self.sort].0 {
+            SortKey::Queue => listed.queue_position != download.queue_position,
+            SortKey::Status => listed.status.get_string() != download.status.get_string(),
+            _ => false,
+        }
+    }
+
+    async fn refresh(&mut self) {
+        if let Err(e) = self.load_downloads().await {
+            self.message = Some((get_error_message(&e), true));
+        }
+    }
+
+    async fn handle_key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        match &mut self.mode {
+            InputMode::Normal => self.handle_normal_key(key).await,
+            InputMode::AddUrl(url) => match key.code {
+                KeyCode::Char(c) => url.push(c),
+                KeyCode::Backspace => {
+                    url.pop();
+                }
+                KeyCode::Enter => {
+                    let url = url.trim().to_string();
+                    self.mode = InputMode::Normal;
+                    if !url.is_empty() {
+                        let result = self.proxy.add_download(&url, "", "").await;
+                        self.message = Some(match result {
+                            Ok(id) => (format!("Added download #{}", id), false),
+                            Err(e) => (get_error_message(&e), true),
+                        });
+                        self.refresh().await;
+                    }
+                }
+                KeyCode::Esc => self.mode = InputMode::Normal,
+                _ => {}
+            },
+            InputMode::ConfirmDelete(id) => {
+                let id = *id;
+                self.mode = InputMode::Normal;
+                if key.code == KeyCode::Char('y') {
+                    let result = self.proxy.delete_download(id).await;
+                    self.show_response(result, "delete", id);
+                }
+            }
+        }
+    }
+
+    async fn handle_normal_key(&mut self, key: KeyEvent) {
+        self.message = None;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.list.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.list.move_selection(-1),
+            KeyCode::PageDown => self.list.move_selection(10),
+            KeyCode::PageUp => self.list.move_selection(-10),
+            KeyCode::Home | KeyCode::Char('g') => self.list.select_index(0),
+            KeyCode::End | KeyCode::Char('G') => self.list.select_index(usize::MAX),
+            KeyCode::Char('a') => self.mode = InputMode::AddUrl(String::new()),
+            KeyCode::Char('s') => {
+                self.sort = (self.sort + 1) % SORTS.len();
+                self.refresh().await;
+            }
+            code => {
+                let Some(download) = self.list.selected_download() else {
+                    return;
+                };
+                let id = download.id;
+                let deletable = download.status.is_idle()
+                    || matches!(download.status, DownloadStatus::Completed);
+                match code {
+                    KeyCode::Char('p') => {
+                        let result = self.proxy.pause_download(id).await;
+                        self.show_response(result, "pause", id);
+                    }
+                    KeyCode::Char('r') => {
+                        let result = self.proxy.resume_download(id).await;
+                        self.show_response(result, "resume", id);
+                    }
+                    KeyCode::Char('c') => {
+                        let result = self.proxy.cancel_download(id).await;
+                        self.show_response(result, "cancel", id);
+                    }
+                    KeyCode::Char('R') => {
+                        let result = self.proxy.restart_download(id).await;
+                        self.show_response(result, "restart", id);
+                    }
+                    KeyCode::Char('d') | KeyCode::Delete if deletable => {
+                        self.mode = InputMode::ConfirmDelete(id);
+                    }
+                    KeyCode::Char('d') | KeyCode::Delete => {
+                        let message = format!("Pause or cancel download #{} to delete it", id);
+                        self.message = Some((message, true));
+                    }
+                    KeyCode::Char('K') | KeyCode::Char('J') => {
+                        let steps = if code == KeyCode::Char('K') { -1 } else { 1 };
+                        let result = self.proxy.move_download(id, steps).await;
+                        if let Err(e) = result {
+                            self.message = Some((get_error_message(&e), true));
+                        }
+                        self.refresh().await;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Shows the error of an action, the daemon answers `ERROR` if it could not queue it
+    fn show_response(&mut self, result: zbus::Result<String>, action: &str, id: i64) {
+        self.message = match result {
+            Ok(response) if response == "OK" => None,
+            Ok(_) => Some((format!("Could not {} download #{}", action, id), true)),
+            Err(e) => Some((get_error_message(&e), true)),
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, details_area, footer_area] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        self.draw_table(frame, table_area);
+        self.draw_details(frame, details_area);
+        self.draw_footer(frame, footer_area);
+    }
+
+    fn draw_table(&mut self, frame: &mut Frame, area: Rect) {
+        let downloads = self.list.downloads();
+        let id_width = downloads
+            .iter()
+            .map(|download| download.id.to_string().len())
+            .max()
+            .unwrap_or(0)
+            .max(2);
+        let header = Row::new(["ID", "NAME", "STATUS", "PROGRESS", "SPEED", "ETA", "SIZE"])
+            .style(Style::new().add_modifier(Modifier::BOLD));
+        let rows = downloads.iter().map(|download| {
+            let (downloaded, size) = self.list.get_downloaded(download);
+            let progress = self.list.get_progress(download);
+            let progress_text = match get_percent(downloaded, size) {
+                Some(percent) => format!("{} {:>3.0}%", format_bar(percent, BAR_WIDTH), percent),
+                None if downloaded > 0 => format_size(downloaded),
+                None => String::new(),
+            };
+            let color = get_status_color(&download.status);
+            Row::new([
+                Cell::new(download.id.to_string()),
+                Cell::new(get_file_name(download)),
+                Cell::new(download.status.get_description().to_string())
+                    .style(Style::new().fg(color)),
+                Cell::new(progress_text).style(Style::new().fg(color)),
+                Cell::new(progress.map_or(String::new(), |p| format_speed(p.speed))),
+                Cell::new(progress.map_or(String::new(), |p| format_duration(p.eta))),
+                Cell::new(match size {
+                    0 => String::new(),
+                    size => format_size(size),
+                }),
+            ])
+        });
+        let widths = [
+            Constraint::Length(id_width as u16),
+            Constraint::Fill(1),
+            Constraint::Length(12),
+            Constraint::Length(BAR_WIDTH as u16 + 5),
+            Constraint::Length(12),
+            Constraint::Length(6),
+            Constraint::Length(10),
+        ];
+        let (_, _, sort_name) = SORTS[self.sort];
+        let title = format!(" Downloads ({}) by {} ", downloads.len(), sort_name);
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+        self.table_state.select(self.list.selected_index());
+        frame.render_stateful_widget(table, area, &mut self.table_state);
+    }
+
+    fn draw_details(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Details ");
+        let Some(download) = self.list.selected_download() else {
+            let text = "No downloads, press a to add one";
+            frame.render_widget(Paragraph::new(text).block(block), area);
+            return;
+        };
+        let (downloaded, size) = self.list.get_downloaded(download);
+        let size = match size {
+            0 => format!("{} of unknown size", format_size(downloaded)),
+            size => format!("{} of {}", format_size(downloaded), format_size(size)),
+        };
+        let category = match download.category.as_str() {
+            "" => "None",
+            category => category,
+        };
+        let mut lines = vec![
+            get_detail_line("URL", &download.url, None),
+            get_detail_line(
+                "Path",
+                get_file_path(download).unwrap_or("Not known yet"),
+                None,
+            ),
+            get_detail_line("Size", size, None),
+            get_detail_line("Category", category, None),
+            get_detail_line(
+                "Status",
+                download.status.get_description(),
+                Some(get_status_color(&download.status)),
+            ),
+        ];
+        if let Some(error) = get_download_error(&self.list, download) {
+            lines.push(get_detail_line("Error", error, Some(Color::Red)));
+        }
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn draw_footer(&self, frame: &mut Frame, area: Rect) {
+        let line = match (&self.mode, &self.message) {
+            (InputMode::AddUrl(url), _) => Line::from(vec![
+                Span::styled("URL: ", Style::new().add_modifier(Modifier::BOLD)),
+                Span::raw(url.as_str()),
+                Span::styled(" ", Style::new().add_modifier(Modifier::REVERSED)),
+                Span::styled(
+                    "  enter to add, esc to cancel",
+                    Style::new().fg(Color::DarkGray),
+                ),
+            ]),
+            (InputMode::ConfirmDelete(id), _) => Line::styled(
+                format!("Delete download #{} from the list? (y/n)", id),
+                Style::new().fg(Color::Yellow),
+            ),
+            (InputMode::Normal, Some((message, true))) => {
+                Line::styled(message.as_str(), Style::new().fg(Color::Red))
+            }
+            (InputMode::Normal, Some((message, false))) => Line::raw(message.as_str()),
+            (InputMode::Normal, None) => Line::styled(
+                "a add  p pause  r resume  c cancel  R restart  d delete  \
+K/J move  s sort  q quit",
+                Style::new().fg(Color::DarkGray),
+            ),
+        };
+        frame.render_widget(Paragraph::new(line), area);
+    }
+}
+
+fn get_detail_line(label: &str, value: impl Into<String>, color: Option<Color>) -> Line<'static> {
+    let style = color.map_or(Style::new(), |color| Style::new().fg(color));
+    Line::from(vec![
+        Span::styled(
+            format!("{:<10}", label),
+            Style::new().add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(value.into(), style),
+    ])
+}
+
+/**
+ * This function returns the error reported for a failed download, the
+ * description of its status if it failed before the client started.
+ */
+fn get_download_error(list: &DownloadList, download: &Download) -> Option<String> {
+    if let Some(error) = list.get_error(download.id) {
+        return Some(error.to_string());
+    }
+    match download.status {
+        DownloadStatus::ServerError
+        | DownloadStatus::ClientError
+        | DownloadStatus::UnknownError
+        | DownloadStatus::InsufficientSpace => Some(download.status.get_description().to_string()),
+        _ => None,
+    }
+}
+
+fn get_status_color(status: &DownloadStatus) -> Color {
+    match status {
+        DownloadStatus::Pending => Color::Blue,
+        DownloadStatus::Starting | DownloadStatus::InProgress => Color::Cyan,
+        DownloadStatus::Paused => Color::Yellow,
+        DownloadStatus::Canceled => Color::DarkGray,
+        DownloadStatus::Completed => Color::Green,
+        DownloadStatus::Retrying | DownloadStatus::AwaitingConflictResolution => Color::Magenta,
+        DownloadStatus::ServerError
+        | DownloadStatus::ClientError
+        | DownloadStatus::UnknownError
+        | DownloadStatus::InsufficientSpace => Color::Red,
+    }
+}
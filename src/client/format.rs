@@ -0,0 +1,113 @@
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::core::download::{Download, ProgressInfo};
+
+const SIZE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// Formats a size in bytes with binary units, like `1.5 MiB`
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, SIZE_UNITS[unit]),
+    }
+}
+
+/// Formats a speed in bytes per second, like `1.5 MiB/s`
+pub fn format_speed(bytes_per_second: u64) -> String {
+    format!("{}/s", format_size(bytes_per_second))
+}
+
+/// Formats a count of seconds, like `1h05m` or `3m20s`
+///
+/// # Returns
+///
+/// * `String` - The duration, `-` if it is negative as for an unknown ETA
+pub fn format_duration(seconds: i64) -> String {
+    match seconds {
+        ..=-1 => "-".to_string(),
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/// Returns the share of a file that is downloaded, `None` if its size is unknown
+pub fn get_percent(downloaded: u64, size: u64) -> Option<f64> {
+    match size {
+        0 => None,
+        size => Some((downloaded as f64 / size as f64 * 100.0).min(100.0)),
+    }
+}
+
+/// Draws a progress bar of `width` characters with eighths of a character
+pub fn format_bar(percent: f64, width: usize) -> String {
+    const PARTS: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+    let eighths = (percent.clamp(0.0, 100.0) / 100.0 * (width * 8) as f64).round() as usize;
+    let mut bar = "█".repeat(eighths / 8);
+    bar.push_str(PARTS[eighths % 8]);
+    let filled = bar.chars().count();
+    bar.push_str(&" ".repeat(width - filled));
+    bar
+}
+
+/// Returns the path of the file of a download once it is known
+pub fn get_file_path(download: &Download) -> Option<&str> {
+    download
+        .output_file
+        .as_deref()
+        .or(download.detected_output_file.as_deref())
+}
+
+/// Returns the name of the file of a download, the end of its URL until the name is known
+pub fn get_file_name(download: &Download) -> String {
+    if let Some(name) = get_file_path(download)
+        .and_then(|path| Path::new(path).file_name())
+        .and_then(|name| name.to_str())
+    {
+        return name.to_string();
+    }
+    let url = download.url.split(['?', '#']).next().unwrap_or_default();
+    url.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(&download.url)
+        .to_string()
+}
+
+/// Returns the fields of a download that scripts use
+pub fn download_to_json(download: &Download) -> serde_json::Value {
+    json!({
+        "id": download.id,
+        "url": download.url,
+        "status": download.status.get_string(),
+        "name": get_file_name(download),
+        "path": get_file_path(download),
+        "size": download.size,
+        "downloaded_bytes": download.downloaded_bytes,
+        "category": download.category,
+        "group_id": download.group_id,
+        "date_added": download.date_added,
+        "date_completed": download.date_completed,
+    })
+}
+
+pub fn progress_to_json(progress: &ProgressInfo) -> serde_json::Value {
+    json!({
+        "id": progress.id,
+        "downloaded": progress.downloaded,
+        "size": progress.size,
+        "speed": progress.speed,
+        "average_speed": progress.average_speed,
+        "eta": progress.eta,
+        "elapsed": progress.elapsed,
+    })
+}
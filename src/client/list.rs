@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use crate::core::download::{Download, DownloadStatus, ProgressInfo};
+
+/// The downloads shown by a client with their live progress and errors, kept up to date
+/// from the signals of the daemon
+#[derive(Default)]
+pub struct DownloadList {
+    downloads: Vec<Download>,
+    progress: HashMap<i64, ProgressInfo>,
+    errors: HashMap<i64, String>,
+    /// Id of the selected download, so the selection follows it when the list changes
+    selected: Option<i64>,
+}
+
+impl DownloadList {
+    pub fn new() -> DownloadList {
+        DownloadList::default()
+    }
+
+    pub fn downloads(&self) -> &[Download] {
+        &self.downloads
+    }
+
+    /// Replaces the downloads, the selection stays on the same download or at the same
+    /// place if it is gone
+    pub fn set_downloads(&mut self, downloads: Vec<Download>) {
+        let index = self.selected_index();
+        self.downloads = downloads;
+        self.progress.retain(|id, _| {
+            self.downloads
+                .iter()
+                .any(|download| download.id == *id && is_running(&download.status))
+        });
+        if self.selected_index().is_none() {
+            self.select_index(index.unwrap_or(0));
+        }
+    }
+
+    /// Applies an update signal
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `false` if the download is not listed, the list should be fetched again
+    pub fn update_download(&mut self, download: Download) -> bool {
+        let Some(listed) = self.downloads.iter_mut().find(|d| d.id == download.id) else {
+            return false;
+        };
+        if !is_running(&download.status) {
+            self.progress.remove(&download.id);
+        }
+        if is_running(&download.status) || matches!(download.status, DownloadStatus::Pending) {
+            self.errors.remove(&download.id);
+        }
+        *listed = download;
+        true
+    }
+
+    pub fn update_progress(&mut self, progress: ProgressInfo) {
+        self.progress.insert(progress.id, progress);
+    }
+
+    pub fn set_error(&mut self, id: i64, error: &str) {
+        self.errors.insert(id, error.to_string());
+    }
+
+    /// Removes a deleted download, the next one is selected if it was
+    pub fn remove_download(&mut self, id: i64) {
+        let index = self.selected_index();
+        self.downloads.retain(|download| download.id != id);
+        self.progress.remove(&id);
+        self.errors.remove(&id);
+        if self.selected_index().is_none() {
+            self.select_index(index.unwrap_or(0));
+        }
+    }
+
+    /// Returns the last progress of a running download, signals that come after it stopped
+    /// are ignored
+    pub fn get_progress(&self, download: &Download) -> Option<&ProgressInfo> {
+        self.progress
+            .get(&download.id)
+            .filter(|_| is_running(&download.status))
+    }
+
+    /// Returns the last error reported for a download since the client started
+    pub fn get_error(&self, id: i64) -> Option<&str> {
+        self.errors.get(&id).map(String::as_str)
+    }
+
+    /// Returns the downloaded bytes and the size of a download, 0 if unknown
+    pub fn get_downloaded(&self, download: &Download) -> (u64, u64) {
+        let size = download.size.unwrap_or(0);
+        match (self.get_progress(download), &download.status) {
+            (Some(progress), _) => (progress.downloaded, progress.size),
+            (None, DownloadStatus::Completed) => (size, size),
+            (None, _) => (download.downloaded_bytes, size),
+        }
+    }
+
+    pub fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected?;
+        self.downloads
+            .iter()
+            .position(|download| download.id == selected)
+    }
+
+    pub fn selected_download(&self) -> Option<&Download> {
+        self.downloads.get(self.selected_index()?)
+    }
+
+    /// Selects the download at an index, the last one if it is past the end
+    pub fn select_index(&mut self, index: usize) {
+        self.selected = self
+            .downloads
+            .get(index.min(self.downloads.len().saturating_sub(1)))
+            .map(|download| download.id);
+    }
+
+    /// Moves the selection by `offset` downloads, it stops at both ends
+    pub fn move_selection(&mut self, offset: isize) {
+        let index = self.selected_index().unwrap_or(0);
+        self.select_index(index.saturating_add_signed(offset));
+    }
+}
+
+/// Whether the daemon reports the progress of a download with this status
+fn is_running(status: &DownloadStatus) -> bool {
+    matches!(
+        status,
+        DownloadStatus::Starting | DownloadStatus::InProgress
+    )
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use zbus::proxy;
+use zbus::zvariant::{OwnedValue, Value};
+
+use crate::core::config::ConfigProblem;
+use crate::core::download::{Download, DownloadFilter, ProgressInfo};
+
+pub mod format;
+pub mod list;
+
+#[cfg(test)]
+mod tests;
+
+/// The methods and signals of the daemon used by the clients
+#[proxy(
+    interface = "com.github.essmehdi.Flowd",
+    default_service = "com.github.essmehdi.Flowd",
+    default_path = "/com/github/essmehdi/Flowd/Listener"
+)]
+pub trait Flowd {
+    fn add_download(&self, url: &str, output_file_name: &str, directory: &str)
+        -> zbus::Result<i64>;
+
+    fn get_download(&self, id: i64) -> zbus::Result<Download>;
+
+    fn list_downloads(
+        &self,
+        filter: DownloadFilter,
+        sort: &str,
+        descending: bool,
+        offset: u32,
+        limit: u32,
+    ) -> zbus::Result<(Vec<Download>, u32)>;
+
+    fn get_progress(&self, id: i64) -> zbus::Result<ProgressInfo>;
+
+    fn pause_download(&self, id: i64) -> zbus::Result<String>;
+
+    fn resume_download(&self, id: i64) -> zbus::Result<String>;
+
+    fn cancel_download(&self, id: i64) -> zbus::Result<String>;
+
+    fn restart_download(&self, id: i64) -> zbus::Result<String>;
+
+    fn delete_download(&self, id: i64) -> zbus::Result<String>;
+
+    fn move_download(&self, id: i64, steps: i32) -> zbus::Result<()>;
+
+    fn get_config(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+
+    fn get_config_problems(&self) -> zbus::Result<Vec<ConfigProblem>>;
+
+    fn set_config_value(&self, key: &str, value: &Value<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notify_download_error(&self, id: i64, error: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notify_download_update(&self, download_info: Download) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notify_download_delete(&self, download_id: i64) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn config_changed(&self, changed_keys: Vec<String>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    #[allow(clippy::too_many_arguments)]
+    fn notify_download_progress(
+        &self,
+        id: i64,
+        progress: u64,
+        content_length: u64,
+        speed: u64,
+        average_speed: u64,
+        eta: i64,
+        elapsed: u64,
+    ) -> zbus::Result<()>;
+}
+
+/// Returns the message of an error returned by the daemon without the D-Bus error name
+pub fn get_error_message(error: &zbus::Error) -> String {
+    match error {
+        zbus::Error::MethodError(name, _, _)
+            if name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown" =>
+        {
+            "flowd is not running".to_string()
+        }
+        zbus::Error::MethodError(_, Some(message), _) => message.clone(),
+        error => error.to_string(),
+    }
+}
@@ -0,0 +1,127 @@
+use crate::core::config::{Config, DEFAULT_CONFIG};
+use crate::core::dbus::values::{to_dbus_dict, to_toml_table};
+use crate::core::download::{Download, DownloadStatus, ProgressInfo};
+
+use super::format::{format_bar, format_duration, format_size, get_file_name, get_percent};
+use super::list::DownloadList;
+
+fn test_download(url: &str) -> Download {
+    Download {
+        id: 1,
+        url: url.to_string(),
+        data_confirmed: true,
+        temp_file: "/tmp/test".to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_format_values() {
+    assert_eq!(format_size(0), "0 B");
+    assert_eq!(format_size(1023), "1023 B");
+    assert_eq!(format_size(1536), "1.5 KiB");
+    assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+
+    assert_eq!(format_duration(-1), "-");
+    assert_eq!(format_duration(42), "42s");
+    assert_eq!(format_duration(200), "3m20s");
+    assert_eq!(format_duration(3900), "1h05m");
+
+    assert_eq!(get_percent(50, 0), None);
+    assert_eq!(get_percent(50, 200), Some(25.0));
+    assert_eq!(get_percent(300, 200), Some(100.0));
+
+    assert_eq!(format_bar(0.0, 4), "    ");
+    assert_eq!(format_bar(50.0, 4), "██  ");
+    assert_eq!(format_bar(31.25, 2), "▋ ");
+    assert_eq!(format_bar(150.0, 4), "████");
+}
+
+#[test]
+fn test_get_file_name() {
+    let mut download = test_download("https://example.com/files/archive.tar.gz?token=abc");
+    assert_eq!(get_file_name(&download), "archive.tar.gz");
+
+    download.detected_output_file = Some("/home/user/Downloads/detected.tar.gz".to_string());
+    assert_eq!(get_file_name(&download), "detected.tar.gz");
+
+    download.output_file = Some("/home/user/Downloads/renamed.tar.gz".to_string());
+    assert_eq!(get_file_name(&download), "renamed.tar.gz");
+
+    let download = test_download("https://example.com/");
+    assert_eq!(get_file_name(&download), "example.com");
+}
+
+#[test]
+fn test_config_dict_round_trip() {
+    let content = format!(
+        "{}\n[categories.videos]\ndirectory = \"~/Videos\"\nextensions = [\"mp4\", \"mkv\"]\n",
+        DEFAULT_CONFIG
+    );
+    let config: Config = toml::from_str(&content).unwrap();
+    let table = config.to_table();
+    let dict = to_dbus_dict(&table);
+    let round_trip = to_toml_table(&dict);
+
+    for (key, value) in &table {
+        assert_eq!(round_trip.get(key), Some(value), "{}", key);
+    }
+    assert_eq!(round_trip.len(), table.len());
+    assert_eq!(
+        round_trip["categories"]["videos"]["extensions"],
+        toml::Value::from(vec!["mp4", "mkv"])
+    );
+}
+
+#[test]
+fn test_download_list() {
+    let download = |id: i64, status: DownloadStatus| Download {
+        id,
+        status,
+        size: Some(1000),
+        downloaded_bytes: 100,
+        ..test_download("https://example.com/file.zip")
+    };
+    let mut list = DownloadList::new();
+    list.set_downloads(vec![
+        download(1, DownloadStatus::InProgress),
+        download(2, DownloadStatus::Pending),
+        download(3, DownloadStatus::Completed),
+    ]);
+    assert_eq!(list.selected_index(), Some(0));
+
+    // Progress is only shown while the download runs
+    list.update_progress(ProgressInfo {
+        id: 1,
+        downloaded: 500,
+        size: 1000,
+        ..Default::default()
+    });
+    assert_eq!(list.get_downloaded(&list.downloads()[0]), (500, 1000));
+    assert_eq!(list.get_downloaded(&list.downloads()[1]), (100, 1000));
+    assert_eq!(list.get_downloaded(&list.downloads()[2]), (1000, 1000));
+    list.set_error(1, "Connection reset");
+    assert!(list.update_download(download(1, DownloadStatus::ServerError)));
+    assert!(list.get_progress(&list.downloads()[0]).is_none());
+    assert_eq!(list.get_error(1), Some("Connection reset"));
+    assert!(list.update_download(download(1, DownloadStatus::Pending)));
+    assert_eq!(list.get_error(1), None);
+    assert!(!list.update_download(download(4, DownloadStatus::Pending)));
+
+    // The selection follows the download, then stays in place once it is gone
+    list.move_selection(1);
+    list.set_downloads(vec![
+        download(2, DownloadStatus::Pending),
+        download(1, DownloadStatus::Pending),
+        download(3, DownloadStatus::Completed),
+    ]);
+    assert_eq!(list.selected_download().map(|d| d.id), Some(2));
+    list.remove_download(2);
+    assert_eq!(list.selected_download().map(|d| d.id), Some(1));
+    list.move_selection(5);
+    assert_eq!(list.selected_download().map(|d| d.id), Some(3));
+    list.move_selection(-5);
+    assert_eq!(list.selected_index(), Some(0));
+    list.set_downloads(vec![]);
+    assert_eq!(list.selected_download().map(|d| d.id), None);
+}
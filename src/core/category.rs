@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use regex::Regex;
+use reqwest::Url;
+use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
+
+use super::config::{Category, Config};
+use super::download::Download;
+
+/// The data a category is matched against
+pub struct CategoryQuery<'a> {
+    pub url: &'a str,
+    pub file_name: &'a str,
+    pub content_type: Option<&'a str>,
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchRule {
+    UrlPattern,
+    Domain,
+    MimeType,
+    Extension,
+    Size,
+    Default,
+}
+
+impl MatchRule {
+    pub fn get_string(&self) -> &str {
+        match self {
+            MatchRule::UrlPattern => "url_pattern",
+            MatchRule::Domain => "domain",
+            MatchRule::MimeType => "mime_type",
+            MatchRule::Extension => "extension",
+            MatchRule::Size => "size",
+            MatchRule::Default => "default",
+        }
+    }
+}
+
+/// Describes which category a download falls in and the rule that selected it
+#[derive(Debug, Clone, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct CategoryMatch {
+    /// Name of the category, empty when the default directory is used
+    pub category: String,
+    pub directory: String,
+    pub rule: String,
+    /// The configured value that matched (extension, glob, domain or regex)
+    pub pattern: String,
+}
+
+/// Finds the category of a file. Categories are checked by priority and the first one
+/// matching wins. Falls back to the default directory if none matches.
+///
+/// # Arguments
+///
+/// * `query` - The data of the file to categorize
+/// * `config` - The config holding the categories
+///
+/// # Returns
+///
+/// * `CategoryMatch` - The matched category and rule
+pub fn find_category(query: &CategoryQuery, config: &Config) -> CategoryMatch {
+    let guessed_content_type = mime_guess::from_path(query.file_name).first_raw();
+    let content_type = query.content_type.or(guessed_content_type);
+
+    for (name, category) in config.get_sorted_categories() {
+        if let Some((rule, pattern)) = match_category(category, query, content_type) {
+            return CategoryMatch {
+                category: name.clone(),
+                directory: category.directory.clone(),
+                rule: rule.get_string().to_string(),
+                pattern,
+            };
+        }
+    }
+
+    CategoryMatch {
+        category: String::new(),
+        directory: config.default_directory.clone(),
+        rule: MatchRule::Default.get_string().to_string(),
+        pattern: String::new(),
+    }
+}
+
+/// Explains which category an existing download falls in using the data stored for it
+pub fn explain_download_category(download: &Download, config: &Config) -> CategoryMatch {
+    let file_name = download
+        .output_file
+        .as_ref()
+        .or(download.detected_output_file.as_ref())
+        .and_then(|path| Path::new(path).file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .or_else(|| {
+            Url::parse(&download.url)
+                .ok()
+                .and_then(|url| url.path_segments()?.next_back().map(str::to_string))
+        })
+        .unwrap_or_default();
+
+    find_category(
+        &CategoryQuery {
+            url: &download.url,
+            file_name: &file_name,
+            content_type: download.content_type.as_deref(),
+            size: download.size,
+        },
+        config,
+    )
+}
+
+fn match_category(
+    category: &Category,
+    query: &CategoryQuery,
+    content_type: Option<&str>,
+) -> Option<(MatchRule, String)> {
+    if !matches_size(category, query.size) {
+        return None;
+    }
+
+    for pattern in &category.url_patterns {
+        if is_regex_match(pattern, query.url) {
+            return Some((MatchRule::UrlPattern, pattern.clone()));
+        }
+    }
+
+    let host = Url::parse(query.url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_lowercase));
+    if let Some(host) = host {
+        for domain in &category.domains {
+            if matches_domain(&host, domain) {
+                return Some((MatchRule::Domain, domain.clone()));
+            }
+        }
+    }
+
+    if let Some(content_type) = content_type {
+        for glob in &category.mime_types {
+            if matches_glob(glob, content_type) {
+                return Some((MatchRule::MimeType, glob.clone()));
+            }
+        }
+    }
+
+    let file_name = query.file_name.to_lowercase();
+    for extension in &category.extensions {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        if !extension.is_empty() && file_name.ends_with(&format!(".{}", extension)) {
+            return Some((MatchRule::Extension, extension));
+        }
+    }
+
+    // A category with only size bounds matches on size alone
+    let has_selectors = !category.url_patterns.is_empty()
+        || !category.domains.is_empty()
+        || !category.mime_types.is_empty()
+        || !category.extensions.is_empty();
+    let has_size_bounds = category.min_size.is_some() || category.max_size.is_some();
+    if !has_selectors && has_size_bounds {
+        let pattern = format!(
+            "{}..{}",
+            category.min_size.map(|s| s.to_string()).unwrap_or_default(),
+            category.max_size.map(|s| s.to_string()).unwrap_or_default()
+        );
+        return Some((MatchRule::Size, pattern));
+    }
+
+    None
+}
+
+/// Size bounds are only satisfied by a known size
+fn matches_size(category: &Category, size: Option<u64>) -> bool {
+    if category.min_size.is_none() && category.max_size.is_none() {
+        return true;
+    }
+    match size {
+        Some(size) => {
+            category.min_size.is_none_or(|min| size >= min)
+                && category.max_size.is_none_or(|max| size <= max)
+        }
+        None => false,
+    }
+}
+
+fn matches_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches("*.").to_lowercase();
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn matches_glob(glob: &str, value: &str) -> bool {
+    let pattern = glob
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<String>>()
+        .join(".*");
+    is_regex_match(&format!("(?i)^{}$", pattern), value)
+}
+
+/// Compiled category patterns, `None` for invalid ones. They only change with the config,
+/// so each one is compiled the first time it is used.
+static REGEX_CACHE: OnceLock<Mutex<HashMap<String, Option<Regex>>>> = OnceLock::new();
+
+fn is_regex_match(pattern: &str, value: &str) -> bool {
+    let mut cache = REGEX_CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    cache
+        .entry(pattern.to_string())
+        .or_insert_with(|| {
+            Regex::new(pattern)
+                .inspect_err(|e| log::warn!("Invalid pattern `{}`: {}", pattern, e))
+                .ok()
+        })
+        .as_ref()
+        .is_some_and(|regex| regex.is_match(value))
+}
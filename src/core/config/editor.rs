@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use tokio::fs;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use super::{Category, ConfigError, ConfigProblem, SettingType};
+
+/// Edits the user config file while keeping its comments and the order of its settings
+pub struct ConfigEditor {
+    document: DocumentMut,
+}
+
+impl ConfigEditor {
+    /// Opens the user config file, an empty config is edited if it does not exist
+    pub async fn open() -> Result<ConfigEditor, ConfigError> {
+        let path = super::get_user_config_path();
+        let content = match fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(ConfigError::ReadError(path, error)),
+        };
+        content.parse::<ConfigEditor>().map_err(|error| {
+            ConfigError::Problem(ConfigProblem::from_syntax_error(&path, &content, &error))
+        })
+    }
+
+    /// Sets a setting, the comments around it are kept
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - One of `SETTINGS`, categories have their own methods
+    /// * `value` - The new value, it is checked when saving
+    pub fn set_value(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match super::get_setting(key) {
+            None => return Err(format!("Unknown setting: {}", key)),
+            Some(setting) if matches!(setting.setting_type, SettingType::Categories) => {
+                return Err(format!("{} cannot be set as a value", key));
+            }
+            Some(_) => {}
+        }
+        match self.document.get_mut(key).and_then(Item::as_value_mut) {
+            Some(current) => {
+                let decor = current.decor().clone();
+                *current = value;
+                *current.decor_mut() = decor;
+            }
+            None => {
+                self.document.insert(key, Item::Value(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a category or replaces the category with the same name
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the category
+    /// * `category` - The properties of the category, their types are checked when saving
+    /// * `categories` - The categories of the current config
+    pub fn set_category(
+        &mut self,
+        name: &str,
+        category: Table,
+        categories: &HashMap<String, Category>,
+    ) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("The category name is empty".to_string());
+        }
+        self.get_categories_table(categories)?
+            .insert(name, Item::Table(category));
+        Ok(())
+    }
+
+    /// Removes a category
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the category
+    /// * `categories` - The categories of the current config
+    pub fn remove_category(
+        &mut self,
+        name: &str,
+        categories: &HashMap<String, Category>,
+    ) -> Result<(), String> {
+        match self.get_categories_table(categories)?.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("Unknown category: {}", name)),
+        }
+    }
+
+    /// Returns the categories of the user config, the categories of a config replace all the
+    /// categories of the configs before it so the current ones are copied if there are none
+    fn get_categories_table(
+        &mut self,
+        categories: &HashMap<String, Category>,
+    ) -> Result<&mut Table, String> {
+        if !self.document.contains_key("categories") {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            let mut names = categories.keys().collect::<Vec<&String>>();
+            names.sort();
+            for name in names {
+                let content = toml::to_string(&categories[name]).map_err(|e| e.to_string())?;
+                let category = content.parse::<DocumentMut>().map_err(|e| e.to_string())?;
+                table.insert(name, Item::Table(category.as_table().clone()));
+            }
+            self.document.insert("categories", Item::Table(table));
+        }
+        self.document["categories"]
+            .as_table_mut()
+            .ok_or_else(|| "categories is not a table".to_string())
+    }
+
+    /// Writes the user config if the config stays valid, see `write_user_config`
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, ConfigError>` - The names of the changed settings
+    pub async fn save(&self) -> Result<Vec<String>, ConfigError> {
+        super::write_user_config(Some(&self.to_string())).await
+    }
+}
+
+impl FromStr for ConfigEditor {
+    type Err = toml_edit::TomlError;
+
+    fn from_str(content: &str) -> Result<ConfigEditor, Self::Err> {
+        Ok(ConfigEditor {
+            document: content.parse::<DocumentMut>()?,
+        })
+    }
+}
+
+impl fmt::Display for ConfigEditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
@@ -0,0 +1,659 @@
+use futures_util::StreamExt;
+use inotify::{Inotify, WatchMask, Watches};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zbus::zvariant::Type;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::{collections::HashMap, path::Path};
+use tokio::fs;
+use tokio::sync::{OnceCell, RwLock};
+use tokio::time::{sleep, timeout, Duration};
+use crate::utils::{self, xdg};
+
+mod editor;
+mod schema;
+
+pub use editor::ConfigEditor;
+pub use schema::{
+    get_category_property, get_setting, ConfigProblem, Setting, SettingType, CATEGORY_PROPERTIES,
+    SETTINGS,
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Deserialize, Serialize, Type, Clone)]
+#[zvariant(signature = "dict")]
+pub struct Config {
+    /// Defaults to the download directory of the user
+    #[serde(default = "default_download_directory")]
+    pub default_directory: String,
+    /// Where downloads are written until completed, defaults to the cache directory
+    #[serde(default = "default_temp_directory")]
+    pub temp_directory: String,
+    pub user_agent: String,
+    pub categories: HashMap<String, Category>,
+    pub max_sim_downloads: u16,
+    /// Output path template, relative to the category directory unless absolute
+    pub path_template: Option<String>,
+    /// What to do when the output file already exists
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+    /// Reserve disk space for downloads of known size before writing them
+    #[serde(default)]
+    pub preallocate: bool,
+    /// Seconds to wait for the connection to the server
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Seconds to wait for the response once connected
+    #[serde(default = "default_first_byte_timeout")]
+    pub first_byte_timeout: u64,
+    /// Seconds without receiving data before the download is retried
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Speed in bytes per second under which a download is stalled, 0 to disable
+    #[serde(default = "default_low_speed_limit")]
+    pub low_speed_limit: u64,
+    /// Seconds the speed must stay under `low_speed_limit` before the download is retried
+    #[serde(default = "default_low_speed_time")]
+    pub low_speed_time: u64,
+    /// Times a stalled or disconnected download is retried before failing
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Seconds to wait before retrying a download
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+    /// Maximum speed of each download in bytes per second, 0 for no limit
+    #[serde(default)]
+    pub speed_limit: u64,
+}
+
+fn default_download_directory() -> String {
+    xdg::get_download_dir()
+}
+
+fn default_temp_directory() -> String {
+    Path::new(&xdg::get_cache_home())
+        .join("flowd")
+        .join("temp")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn default_connect_timeout() -> u64 {
+    30
+}
+
+fn default_first_byte_timeout() -> u64 {
+    60
+}
+
+fn default_idle_timeout() -> u64 {
+    60
+}
+
+fn default_low_speed_limit() -> u64 {
+    1024
+}
+
+fn default_low_speed_time() -> u64 {
+    60
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_retry_delay() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Serialize, Type, Clone)]
+#[zvariant(signature = "dict")]
+pub struct Category {
+    pub directory: String,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// MIME type globs, e.g. `video/*`
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    /// Source hosts, subdomains included
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Regular expressions matched against the download URL
+    #[serde(default)]
+    pub url_patterns: Vec<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Categories with a higher priority are checked first
+    #[serde(default)]
+    pub priority: i64,
+    /// Overrides the global `path_template` for this category
+    pub path_template: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Rename the new file to `name (N).ext`
+    #[default]
+    Rename,
+    /// Replace the existing file
+    Overwrite,
+    /// Keep the existing file if it has the same size and content, rename otherwise
+    SkipIdentical,
+    /// Keep the existing file if it is newer than the remote one, overwrite otherwise
+    KeepNewer,
+    /// Wait for the user to pick one of the other policies
+    Ask,
+}
+
+impl ConflictPolicy {
+    pub fn get_string(&self) -> &str {
+        match self {
+            ConflictPolicy::Rename => "rename",
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::SkipIdentical => "skip_identical",
+            ConflictPolicy::KeepNewer => "keep_newer",
+            ConflictPolicy::Ask => "ask",
+        }
+    }
+
+    pub fn from_string(value: &str) -> Option<ConflictPolicy> {
+        match value {
+            "rename" => Some(ConflictPolicy::Rename),
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            "skip_identical" => Some(ConflictPolicy::SkipIdentical),
+            "keep_newer" => Some(ConflictPolicy::KeepNewer),
+            "ask" => Some(ConflictPolicy::Ask),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    /// Overrides the settings set in `config`, the others are kept
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The content of a config file
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), toml::de::Error>` - An error if the content is not valid TOML or a setting
+    ///   has the wrong type, the config is unchanged in this case
+    pub fn update_from_map(&mut self, config: &str) -> Result<(), toml::de::Error> {
+        self.update_from_table(config.parse::<toml::Table>()?)
+    }
+
+    /// Overrides the settings of the table, see `update_from_map`
+    pub fn update_from_table(&mut self, table: toml::Table) -> Result<(), toml::de::Error> {
+        let mut merged = self.to_table();
+        merged.extend(table);
+        *self = toml::Value::Table(merged).try_into()?;
+        Ok(())
+    }
+
+    /// Checks every setting against the schema and the settings that depend on each other
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, value) in self.to_table() {
+            let Some(setting) = get_setting(&key) else {
+                continue;
+            };
+            setting
+                .check(&value)
+                .map_err(|message| format!("{}: {}", key, message))?;
+            if let toml::Value::Table(categories) = value {
+                for (name, category) in categories {
+                    schema::check_category(&category)
+                        .map_err(|message| format!("{}.{}: {}", key, name, message))?;
+                }
+            }
+        }
+        self.check_speed_limit()
+            .map_err(|message| format!("speed_limit: {}", message))
+    }
+
+    /// A download limited under `low_speed_limit` would be retried forever
+    fn check_speed_limit(&self) -> Result<(), String> {
+        if self.speed_limit > 0 && self.speed_limit <= self.low_speed_limit {
+            return Err(format!(
+                "must be greater than low_speed_limit ({})",
+                self.low_speed_limit
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the settings whose value differs in `other`, in alphabetical order
+    pub fn get_changed_keys(&self, other: &Config) -> Vec<String> {
+        let (table, other_table) = (self.to_table(), other.to_table());
+        let mut keys = table
+            .keys()
+            .chain(other_table.keys())
+            .filter(|key| table.get(*key) != other_table.get(*key))
+            .cloned()
+            .collect::<Vec<String>>();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    pub fn to_table(&self) -> toml::Table {
+        toml::Table::try_from(self).expect("The config can be represented in TOML")
+    }
+
+    /// Returns categories in the order they should be matched: by descending priority,
+    /// then by name so that the result does not depend on the map iteration order
+    pub fn get_sorted_categories(&self) -> Vec<(&String, &Category)> {
+        let mut categories = self.categories.iter().collect::<Vec<_>>();
+        categories.sort_by(|(a_name, a), (b_name, b)| {
+            b.priority.cmp(&a.priority).then_with(|| a_name.cmp(b_name))
+        });
+        categories
+    }
+}
+
+/// Config with every setting, the others only override some of them
+pub(crate) const DEFAULT_CONFIG: &str = include_str!("../../resources/config/config.toml");
+const ROOT_CONFIG_PATH: &str = "/etc/flowd/config.toml";
+const CONFIG_NAME: &str = "flowd/config.toml";
+
+static USER_CONFIG_PATH: OnceLock<String> = OnceLock::new();
+
+/// Reads the user config from this file instead of the one in `$XDG_CONFIG_HOME`,
+/// has no effect once the config was read
+pub fn set_user_config_path(path: &str) {
+    _ = USER_CONFIG_PATH.set(utils::path::expand(path));
+}
+
+pub fn get_user_config_path() -> String {
+    USER_CONFIG_PATH
+        .get_or_init(|| {
+            Path::new(&xdg::get_config_home())
+                .join(CONFIG_NAME)
+                .to_string_lossy()
+                .to_string()
+        })
+        .clone()
+}
+
+/// Returns the config files overriding the default config, the most important last
+pub fn get_config_paths() -> Vec<String> {
+    let mut paths = vec![ROOT_CONFIG_PATH.to_string()];
+    for dir in xdg::get_config_dirs().iter().rev() {
+        paths.push(Path::new(dir).join(CONFIG_NAME).to_string_lossy().to_string());
+    }
+    paths.push(get_user_config_path());
+    paths
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Could not read {0}: {1}")]
+    ReadError(String, io::Error),
+
+    #[error("Could not write {0}: {1}")]
+    WriteError(String, io::Error),
+
+    #[error("{0}")]
+    Problem(ConfigProblem),
+}
+
+/// Time to wait after a config file changed, editors often write a file in several steps
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+static CONFIG: OnceCell<Arc<RwLock<Config>>> = OnceCell::const_new();
+
+/// Problems found the last time the config files were read
+static PROBLEMS: Mutex<Vec<ConfigProblem>> = Mutex::new(Vec::new());
+
+/// Reads the config files, the settings that are invalid are skipped
+///
+/// # Arguments
+///
+/// * `user_config` - Replaces the content of the user config file if set
+///
+/// # Returns
+///
+/// * `(Config, Vec<ConfigProblem>)` - The config and the problems of the skipped settings
+async fn load_config(user_config: Option<&str>) -> (Config, Vec<ConfigProblem>) {
+    let mut config: Config =
+        toml::from_str(DEFAULT_CONFIG).expect("The default config embedded in the binary is valid");
+    let mut problems = Vec::new();
+
+    // Update with the system configs, then the user config
+    let user_config_path = get_user_config_path();
+    for path in get_config_paths() {
+        let content = match user_config.filter(|_| path == user_config_path) {
+            Some(content) => Ok(content.to_string()),
+            None if !Path::new(&path).exists() => continue,
+            None => fs::read_to_string(&path).await,
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(error) => {
+                problems.push(ConfigProblem {
+                    file: path,
+                    line: None,
+                    setting: String::new(),
+                    message: format!("could not read the file: {}", error),
+                });
+                continue;
+            }
+        };
+        let (settings, file_problems) = match schema::check_config_file(&path, &content) {
+            Ok(checked) => checked,
+            Err(problem) => {
+                problems.push(problem);
+                continue;
+            }
+        };
+        let mut file_problems = file_problems;
+        config = apply_settings(config, settings, &path, &content, &mut file_problems);
+        file_problems.sort_by_key(|problem| problem.line);
+        problems.extend(file_problems);
+    }
+
+    (process_config(config), problems)
+}
+
+/// Applies the settings of a file that were checked against the schema, a speed limit that
+/// conflicts with the other settings is skipped
+fn apply_settings(
+    config: Config,
+    mut settings: toml::Table,
+    path: &str,
+    content: &str,
+    problems: &mut Vec<ConfigProblem>,
+) -> Config {
+    loop {
+        let mut updated = config.clone();
+        if let Err(error) = updated.update_from_table(settings.clone()) {
+            problems.push(ConfigProblem {
+                file: path.to_string(),
+                line: None,
+                setting: String::new(),
+                message: error.message().trim().to_string(),
+            });
+            return config;
+        }
+        let Err(message) = updated.check_speed_limit() else {
+            return updated;
+        };
+        // The previous config was valid so one of them is set in this file
+        let Some(key) = ["speed_limit", "low_speed_limit"]
+            .into_iter()
+            .find(|key| settings.contains_key(*key))
+        else {
+            return config;
+        };
+        settings.remove(key);
+        problems.push(ConfigProblem {
+            file: path.to_string(),
+            line: schema::find_line(content, &[key]),
+            setting: key.to_string(),
+            message,
+        });
+    }
+}
+
+/// Keeps the problems of the config files for `get_config_problems` and logs them
+fn set_problems(problems: Vec<ConfigProblem>) {
+    for problem in &problems {
+        log::error!("{problem}");
+    }
+    *PROBLEMS.lock().unwrap() = problems;
+}
+
+/// Returns the problems found the last time the config files were read
+pub fn get_config_problems() -> Vec<ConfigProblem> {
+    PROBLEMS.lock().unwrap().clone()
+}
+
+/// Reads the config files without changing the config of the daemon
+///
+/// # Returns
+///
+/// * `Vec<ConfigProblem>` - The problems of the config files, empty if they are valid
+pub async fn check_config() -> Vec<ConfigProblem> {
+    load_config(None).await.1
+}
+
+/// Returns the config shared by the whole daemon, read from the files on first use
+pub async fn get_shared_config() -> Arc<RwLock<Config>> {
+    CONFIG
+        .get_or_init(|| async {
+            let (config, problems) = load_config(None).await;
+            set_problems(problems);
+            Arc::new(RwLock::new(config))
+        })
+        .await
+        .clone()
+}
+
+pub async fn get_config() -> Config {
+    get_shared_config().await.read().await.clone()
+}
+
+/// Reads the config files again and replaces the shared config, the invalid settings are
+/// skipped but the previous config is kept if a file could not be read at all or if the
+/// settings do not fit together
+///
+/// # Returns
+///
+/// * `Vec<String>` - The names of the changed settings
+pub async fn reload_config() -> Vec<String> {
+    let (config, problems) = load_config(None).await;
+    let file_failed = problems.iter().any(|problem| problem.setting.is_empty());
+    set_problems(problems);
+    if file_failed {
+        log::error!("Keeping the previous config until the config files are fixed");
+        return vec![];
+    }
+    if let Err(message) = config.validate() {
+        log::error!("Keeping the previous config, the new one is invalid: {}", message);
+        return vec![];
+    }
+    replace_config(config).await
+}
+
+/// Replaces the shared config
+///
+/// # Returns
+///
+/// * `Vec<String>` - The names of the changed settings
+async fn replace_config(config: Config) -> Vec<String> {
+    let shared_config = get_shared_config().await;
+    let mut current = shared_config.write().await;
+    let changed_keys = current.get_changed_keys(&config);
+    if !changed_keys.is_empty() {
+        *current = config;
+    }
+    changed_keys
+}
+
+/// Numbers the temp files of the user config written by this process
+static TEMP_CONFIG_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks a new user config adds no problem, creating the directories it needs, then writes
+/// it and reloads the config
+///
+/// # Arguments
+///
+/// * `content` - The new content of the user config, `None` to remove the user config
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, ConfigError>` - The names of the changed settings, or the first
+///   new problem in which case nothing is written
+async fn write_user_config(content: Option<&str>) -> Result<Vec<String>, ConfigError> {
+    let path = get_user_config_path();
+    let (_, current_problems) = load_config(None).await;
+    let (config, problems) = load_config(Some(content.unwrap_or_default())).await;
+    if let Some(problem) = problems.iter().find(|problem| {
+        problem.file == path && !current_problems.iter().any(|current| current.is_same(problem))
+    }) {
+        return Err(ConfigError::Problem(problem.clone()));
+    }
+    create_directories(&config, &path)
+        .await
+        .map_err(ConfigError::Problem)?;
+
+    match content {
+        Some(content) => {
+            if let Some(dir) = Path::new(&path).parent() {
+                fs::create_dir_all(dir)
+                    .await
+                    .map_err(|error| ConfigError::WriteError(path.clone(), error))?;
+            }
+            // Written in another file first so that the config is never read half written,
+            // each write gets its own file so concurrent writers do not mix their content
+            let temp_path = format!(
+                "{}.{}-{}.tmp",
+                path,
+                std::process::id(),
+                TEMP_CONFIG_COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            fs::write(&temp_path, content)
+                .await
+                .map_err(|error| ConfigError::WriteError(temp_path.clone(), error))?;
+            fs::rename(&temp_path, &path)
+                .await
+                .map_err(|error| ConfigError::WriteError(path.clone(), error))?;
+        }
+        None if Path::new(&path).exists() => {
+            // Kept aside in case the user wants their settings back
+            fs::rename(&path, format!("{}.bak", path))
+                .await
+                .map_err(|error| ConfigError::WriteError(path.clone(), error))?;
+        }
+        None => {}
+    }
+    *PROBLEMS.lock().unwrap() = problems;
+    Ok(replace_config(config).await)
+}
+
+/// Removes the user config so that only the default and system configs apply, the file is
+/// renamed with a `.bak` extension
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, ConfigError>` - The names of the changed settings
+pub async fn reset_user_config() -> Result<Vec<String>, ConfigError> {
+    write_user_config(None).await
+}
+
+/// Makes sure the directories of the config exist or can be created
+async fn create_directories(config: &Config, path: &str) -> Result<(), ConfigProblem> {
+    let mut directories = vec![
+        ("default_directory".to_string(), config.default_directory.clone()),
+        ("temp_directory".to_string(), config.temp_directory.clone()),
+    ];
+    for (name, category) in &config.categories {
+        directories.push((format!("categories.{}", name), category.directory.clone()));
+    }
+    for (key, directory) in directories {
+        let directory = utils::path::expand(&directory);
+        if let Err(error) = fs::create_dir_all(&directory).await {
+            return Err(ConfigProblem {
+                file: path.to_string(),
+                line: None,
+                setting: key,
+                message: format!("could not create {}: {}", directory, error),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Reloads the config each time one of its files is written, created or removed
+///
+/// # Arguments
+///
+/// * `on_change` - Called with the names of the changed settings after the config was replaced
+pub async fn watch_config(on_change: impl Fn(Vec<String>)) -> io::Result<()> {
+    let paths = get_config_paths();
+    let names = get_watched_names(&paths);
+    let inotify = Inotify::init()?;
+    let mut watches = inotify.watches();
+    add_config_watches(&mut watches, &paths);
+    let mut events = inotify.into_event_stream([0; 4096])?;
+
+    while let Some(event) = events.next().await {
+        if !event?.name.is_some_and(|name| names.contains(&name)) {
+            continue;
+        }
+        sleep(RELOAD_DELAY).await;
+        while let Ok(Some(_)) = timeout(Duration::ZERO, events.next()).await {}
+        // The directory of a config file may have just been created
+        add_config_watches(&mut watches, &paths);
+
+        let changed_keys = reload_config().await;
+        if !changed_keys.is_empty() {
+            log::info!("Config reloaded, changed: {}", changed_keys.join(", "));
+            on_change(changed_keys);
+        }
+    }
+    Ok(())
+}
+
+/// Watches the directory of each config file, or its parent until the directory exists
+fn add_config_watches(watches: &mut Watches, paths: &[String]) {
+    let mask = WatchMask::CLOSE_WRITE
+        | WatchMask::MOVED_TO
+        | WatchMask::MOVED_FROM
+        | WatchMask::CREATE
+        | WatchMask::DELETE;
+    for path in paths {
+        let Some(dir) = Path::new(path).parent() else {
+            continue;
+        };
+        let dir = if dir.is_dir() {
+            Some(dir)
+        } else {
+            dir.parent()
+        };
+        if let Some(dir) = dir.filter(|dir| dir.is_dir()) {
+            if let Err(error) = watches.add(dir, mask) {
+                log::warn!("Could not watch {}: {}", dir.display(), error);
+            }
+        }
+    }
+}
+
+/// Returns the names of the config files and of their directories
+fn get_watched_names(paths: &[String]) -> HashSet<OsString> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            let path = Path::new(path);
+            [
+                path.file_name(),
+                path.parent().and_then(|parent| parent.file_name()),
+            ]
+        })
+        .flatten()
+        .map(|name| name.to_os_string())
+        .collect()
+}
+
+pub async fn get_default_directory() -> String {
+    let config = get_config().await;
+    utils::path::expand(&config.default_directory)
+}
+
+pub async fn get_categories() -> HashMap<String, Category> {
+    let config = get_config().await;
+    config.categories
+}
+
+fn process_config(mut config: Config) -> Config {
+    config.default_directory = utils::path::expand(&config.default_directory);
+    config.temp_directory = utils::path::expand(&config.temp_directory);
+    config
+}
\ No newline at end of file
@@ -0,0 +1,383 @@
+use std::fmt;
+
+use regex::Regex;
+use toml_edit::{ImDocument, TableLike, TomlError};
+use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
+
+/// Kind of value a setting accepts
+pub enum SettingType {
+    Text,
+    /// Integer within the bounds, both included
+    Integer(i64, i64),
+    Boolean,
+    TextList,
+    /// List of regular expressions
+    Patterns,
+    /// One of the listed strings
+    Choice(&'static [&'static str]),
+    /// Table of categories, each checked on its own
+    Categories,
+}
+
+pub struct Setting {
+    pub key: &'static str,
+    pub setting_type: SettingType,
+}
+
+/// Every setting a config file can contain
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "default_directory",
+        setting_type: SettingType::Text,
+    },
+    Setting {
+        key: "temp_directory",
+        setting_type: SettingType::Text,
+    },
+    Setting {
+        key: "user_agent",
+        setting_type: SettingType::Text,
+    },
+    Setting {
+        key: "categories",
+        setting_type: SettingType::Categories,
+    },
+    Setting {
+        key: "max_sim_downloads",
+        setting_type: SettingType::Integer(1, u16::MAX as i64),
+    },
+    Setting {
+        key: "path_template",
+        setting_type: SettingType::Text,
+    },
+    Setting {
+        key: "conflict_policy",
+        setting_type: SettingType::Choice(&[
+            "rename",
+            "overwrite",
+            "skip_identical",
+            "keep_newer",
+            "ask",
+        ]),
+    },
+    Setting {
+        key: "preallocate",
+        setting_type: SettingType::Boolean,
+    },
+    Setting {
+        key: "connect_timeout",
+        setting_type: SettingType::Integer(1, i64::MAX),
+    },
+    Setting {
+        key: "first_byte_timeout",
+        setting_type: SettingType::Integer(1, i64::MAX),
+    },
+    Setting {
+        key: "idle_timeout",
+        setting_type: SettingType::Integer(1, i64::MAX),
+    },
+    Setting {
+        key: "low_speed_limit",
+        setting_type: SettingType::Integer(0, i64::MAX),
+    },
+    Setting {
+        key: "low_speed_time",
+        setting_type: SettingType::Integer(0, i64::MAX),
+    },
+    Setting {
+        key: "max_retries",
+        setting_type: SettingType::Integer(0, u32::MAX as i64),
+    },
+    Setting {
+        key: "retry_delay",
+        setting_type: SettingType::Integer(0, i64::MAX),
+    },
+    Setting {
+        key: "speed_limit",
+        setting_type: SettingType::Integer(0, i64::MAX),
+    },
+];
+
+/// Every property a category can have
+pub const CATEGORY_PROPERTIES: &[Setting] = &[
+    Setting {
+        key: "directory",
+        setting_type: SettingType::Text,
+    },
+    Setting {
+        key: "extensions",
+        setting_type: SettingType::TextList,
+    },
+    Setting {
+        key: "mime_types",
+        setting_type: SettingType::TextList,
+    },
+    Setting {
+        key: "domains",
+        setting_type: SettingType::TextList,
+    },
+    Setting {
+        key: "url_patterns",
+        setting_type: SettingType::Patterns,
+    },
+    Setting {
+        key: "min_size",
+        setting_type: SettingType::Integer(0, i64::MAX),
+    },
+    Setting {
+        key: "max_size",
+        setting_type: SettingType::Integer(0, i64::MAX),
+    },
+    Setting {
+        key: "priority",
+        setting_type: SettingType::Integer(i64::MIN, i64::MAX),
+    },
+    Setting {
+        key: "path_template",
+        setting_type: SettingType::Text,
+    },
+];
+
+pub fn get_setting(key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.key == key)
+}
+
+pub fn get_category_property(key: &str) -> Option<&'static Setting> {
+    CATEGORY_PROPERTIES
+        .iter()
+        .find(|setting| setting.key == key)
+}
+
+impl Setting {
+    /// Checks the value has the type and the bounds of the setting
+    pub fn check(&self, value: &toml::Value) -> Result<(), String> {
+        match (&self.setting_type, value) {
+            (SettingType::Text, toml::Value::String(_)) => Ok(()),
+            (SettingType::Boolean, toml::Value::Boolean(_)) => Ok(()),
+            (SettingType::Integer(min, max), toml::Value::Integer(value)) => {
+                if value < min || value > max {
+                    Err(match *max {
+                        i64::MAX => format!("must be at least {}", min),
+                        _ => format!("must be between {} and {}", min, max),
+                    })
+                } else {
+                    Ok(())
+                }
+            }
+            (SettingType::Choice(choices), toml::Value::String(value)) => {
+                if choices.contains(&value.as_str()) {
+                    Ok(())
+                } else {
+                    Err(format!("must be one of {}", choices.join(", ")))
+                }
+            }
+            (SettingType::TextList | SettingType::Patterns, toml::Value::Array(values))
+                if values.iter().all(toml::Value::is_str) =>
+            {
+                match values
+                    .iter()
+                    .filter_map(toml::Value::as_str)
+                    .find(|pattern| Regex::new(pattern).is_err())
+                {
+                    Some(pattern) if matches!(self.setting_type, SettingType::Patterns) => {
+                        Err(format!("invalid pattern: {}", pattern))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            (SettingType::Categories, toml::Value::Table(_)) => Ok(()),
+            (setting_type, value) => Err(format!(
+                "expected {}, found {}",
+                setting_type.get_string(),
+                value.type_str()
+            )),
+        }
+    }
+}
+
+impl SettingType {
+    pub fn get_string(&self) -> &str {
+        match self {
+            SettingType::Text | SettingType::Choice(_) => "a string",
+            SettingType::Integer(..) => "an integer",
+            SettingType::Boolean => "a boolean",
+            SettingType::TextList | SettingType::Patterns => "a list of strings",
+            SettingType::Categories => "a table",
+        }
+    }
+}
+
+/// A setting of a config file that was skipped, or the whole file if `setting` is empty
+#[derive(Debug, Clone, PartialEq, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct ConfigProblem {
+    pub file: String,
+    /// Line of the setting in the file, starting at 1
+    pub line: Option<u32>,
+    /// The setting, `categories.<name>` for a category
+    pub setting: String,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    /// Returns the problem of a file that is not valid TOML
+    pub fn from_syntax_error(file: &str, content: &str, error: &TomlError) -> ConfigProblem {
+        ConfigProblem {
+            file: file.to_string(),
+            line: error.span().map(|span| get_line(content, span.start)),
+            setting: String::new(),
+            message: get_one_line(error.message()),
+        }
+    }
+
+    /// Whether the problem is the same, wherever it is in the file
+    pub fn is_same(&self, other: &ConfigProblem) -> bool {
+        (&self.file, &self.setting, &self.message) == (&other.file, &other.setting, &other.message)
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if !self.setting.is_empty() {
+            write!(f, ": {}", self.setting)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Checks a config file against the schema
+///
+/// # Arguments
+///
+/// * `file` - The path of the file, for the problems
+/// * `content` - The content of the file
+///
+/// # Returns
+///
+/// * `Result<(toml::Table, Vec<ConfigProblem>), ConfigProblem>` - The valid settings and the
+///   problems of the skipped ones, or the problem of a file that is not valid TOML
+pub fn check_config_file(
+    file: &str,
+    content: &str,
+) -> Result<(toml::Table, Vec<ConfigProblem>), ConfigProblem> {
+    let document = ImDocument::parse(content)
+        .map_err(|error| ConfigProblem::from_syntax_error(file, content, &error))?;
+    let table = toml::from_str::<toml::Table>(content).map_err(|error| ConfigProblem {
+        file: file.to_string(),
+        line: error.span().map(|span| get_line(content, span.start)),
+        setting: String::new(),
+        message: get_one_line(error.message()),
+    })?;
+
+    let mut settings = toml::Table::new();
+    let mut problems = Vec::new();
+    let mut add_problem = |keys: &[&str], message: String| {
+        problems.push(ConfigProblem {
+            file: file.to_string(),
+            line: get_key_line(&document, content, keys),
+            setting: keys.join("."),
+            message,
+        })
+    };
+    for (key, value) in table {
+        let Some(setting) = get_setting(&key) else {
+            add_problem(&[&key], "unknown setting".to_string());
+            continue;
+        };
+        if let Err(message) = setting.check(&value) {
+            add_problem(&[&key], message);
+            continue;
+        }
+        let value = match value {
+            toml::Value::Table(categories) => {
+                let mut valid_categories = toml::Table::new();
+                for (name, category) in categories {
+                    let toml::Value::Table(properties) = category else {
+                        let message = format!("expected a table, found {}", category.type_str());
+                        add_problem(&[&key, &name], message);
+                        continue;
+                    };
+                    let mut valid_properties = toml::Table::new();
+                    for (property, value) in properties {
+                        let Some(setting) = get_category_property(&property) else {
+                            add_problem(&[&key, &name, &property], "unknown property".to_string());
+                            continue;
+                        };
+                        match setting.check(&value) {
+                            Ok(()) => {
+                                valid_properties.insert(property, value);
+                            }
+                            Err(message) => add_problem(&[&key, &name, &property], message),
+                        }
+                    }
+                    if valid_properties.contains_key("directory") {
+                        valid_categories.insert(name, toml::Value::Table(valid_properties));
+                    } else {
+                        let message = "skipped without a valid directory".to_string();
+                        add_problem(&[&key, &name], message);
+                    }
+                }
+                toml::Value::Table(valid_categories)
+            }
+            value => value,
+        };
+        settings.insert(key, value);
+    }
+    Ok((settings, problems))
+}
+
+/// Checks the properties of a category, unknown properties are ignored
+pub fn check_category(category: &toml::Value) -> Result<(), String> {
+    let Some(properties) = category.as_table() else {
+        return Err(format!("expected a table, found {}", category.type_str()));
+    };
+    for (property, value) in properties {
+        if let Some(setting) = get_category_property(property) {
+            setting
+                .check(value)
+                .map_err(|message| format!("{}: {}", property, message))?;
+        }
+    }
+    if !properties.contains_key("directory") {
+        return Err("directory is missing".to_string());
+    }
+    Ok(())
+}
+
+/// Returns the line of a setting in the content of a config file
+pub fn find_line(content: &str, keys: &[&str]) -> Option<u32> {
+    let document = ImDocument::parse(content).ok()?;
+    get_key_line(&document, content, keys)
+}
+
+/// Returns the line of a setting, `keys` being the path to a nested setting
+fn get_key_line(document: &ImDocument<&str>, content: &str, keys: &[&str]) -> Option<u32> {
+    let mut table: &dyn TableLike = document.as_table();
+    let mut span = None;
+    for key in keys {
+        let (key, item) = table.get_key_value(key)?;
+        span = key.span().or_else(|| item.span());
+        if let Some(next) = item.as_table_like() {
+            table = next;
+        }
+    }
+    span.map(|span| get_line(content, span.start))
+}
+
+/// Joins the lines of an error message, `expected` lines follow the error
+fn get_one_line(message: &str) -> String {
+    message
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+fn get_line(content: &str, offset: usize) -> u32 {
+    content[..offset.min(content.len())].matches('\n').count() as u32 + 1
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use toml_edit::{Table, Value};
+
+use super::schema::check_config_file;
+use super::{Category, Config, ConfigEditor, ConfigProblem, DEFAULT_CONFIG};
+
+const USER_CONFIG: &str = r#"# Downloads of the whole family
+max_sim_downloads = 2 # The connection is slow
+user_agent = "flowd"
+
+[categories.videos]
+# Only the big ones
+directory = "~/Videos"
+min_size = 1048576
+"#;
+
+fn test_category(directory: &str) -> Category {
+    toml::from_str(&format!("directory = \"{}\"", directory)).unwrap()
+}
+
+#[test]
+fn test_set_config_value() {
+    let mut editor = USER_CONFIG.parse::<ConfigEditor>().unwrap();
+    editor
+        .set_value("max_sim_downloads", Value::from(4))
+        .unwrap();
+    editor
+        .set_value("speed_limit", Value::from(100000))
+        .unwrap();
+    assert!(editor
+        .set_value("max_sim_download", Value::from(4))
+        .is_err());
+    assert!(editor.set_value("categories", Value::from(4)).is_err());
+
+    // Comments and order are kept, new settings come before the tables
+    assert_eq!(
+        editor.to_string(),
+        r#"# Downloads of the whole family
+max_sim_downloads = 4 # The connection is slow
+user_agent = "flowd"
+speed_limit = 100000
+
+[categories.videos]
+# Only the big ones
+directory = "~/Videos"
+min_size = 1048576
+"#
+    );
+
+    let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+    config.update_from_map(&editor.to_string()).unwrap();
+    assert_eq!((config.max_sim_downloads, config.speed_limit), (4, 100000));
+}
+
+#[test]
+fn test_edit_categories() {
+    let mut editor = USER_CONFIG.parse::<ConfigEditor>().unwrap();
+    let mut category = Table::new();
+    category.insert("directory", toml_edit::value("~/Music"));
+    editor
+        .set_category("music", category, &HashMap::new())
+        .unwrap();
+    assert!(editor.remove_category("books", &HashMap::new()).is_err());
+    editor.remove_category("videos", &HashMap::new()).unwrap();
+    assert_eq!(
+        editor.to_string(),
+        r#"# Downloads of the whole family
+max_sim_downloads = 2 # The connection is slow
+user_agent = "flowd"
+
+[categories.music]
+directory = "~/Music"
+"#
+    );
+
+    // Categories of the user config replace the others, so these are copied first
+    let mut editor = "max_retries = 3\n".parse::<ConfigEditor>().unwrap();
+    let categories = HashMap::from([
+        ("videos".to_string(), test_category("/videos")),
+        ("books".to_string(), test_category("/books")),
+    ]);
+    editor.remove_category("videos", &categories).unwrap();
+    let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+    config.update_from_map(&editor.to_string()).unwrap();
+    assert_eq!(config.categories.len(), 1);
+    assert_eq!(config.categories["books"].directory, "/books");
+    assert_eq!(config.max_retries, 3);
+}
+
+#[test]
+fn test_check_config_file() {
+    let content = r#"max_sim_downloads = "3"
+max_retries = 2
+colour = true
+
+[categories.videos]
+directory = "~/Videos"
+url_patterns = ["("]
+
+[categories.books]
+extensions = ["epub"]
+"#;
+    let (settings, problems) = check_config_file("config.toml", content).unwrap();
+    let problems = problems
+        .iter()
+        .map(ConfigProblem::to_string)
+        .collect::<Vec<String>>();
+    assert_eq!(
+        problems,
+        vec![
+            "config.toml:1: max_sim_downloads: expected an integer, found string",
+            "config.toml:3: colour: unknown setting",
+            "config.toml:7: categories.videos.url_patterns: invalid pattern: (",
+            "config.toml:9: categories.books: skipped without a valid directory",
+        ]
+    );
+
+    // Only the invalid settings are skipped
+    let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+    config.update_from_table(settings).unwrap();
+    assert_eq!((config.max_sim_downloads, config.max_retries), (5, 2));
+    assert_eq!(config.categories.len(), 1);
+    assert!(config.categories["videos"].url_patterns.is_empty());
+
+    let problem =
+        check_config_file("config.toml", "max_retries = 2\nidle_timeout =\n").unwrap_err();
+    assert_eq!((problem.line, problem.setting.as_str()), (Some(2), ""));
+}
+
+#[test]
+fn test_default_config_is_embedded() {
+    let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+    assert!(config.categories.is_empty());
+    assert!(config.temp_directory.ends_with("flowd/temp"));
+    assert!(!config.default_directory.is_empty());
+    assert_eq!(config.max_retries, 5);
+}
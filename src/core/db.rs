@@ -1,108 +1,828 @@
 use rusqlite;
-use rusqlite::{params_from_iter, Connection, Params};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Value, ValueRef};
+use rusqlite::ToSql;
+use rusqlite::{params_from_iter, Connection, OpenFlags, OptionalExtension, Params};
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
 use thiserror::Error;
-use tokio::fs::{self, File};
+use tokio::fs;
 use tokio::io;
+use tokio::sync::Semaphore;
+use tokio::task;
 
-use crate::{core::download::DownloadStatus, utils};
+use crate::core::download::DownloadStatus;
+use crate::utils::{self, xdg};
 
-use super::{config, download::Download};
+use async_trait::async_trait;
+
+use super::{
+    category,
+    config::{Config, ConflictPolicy},
+    download::{BandwidthUsage, Download, DownloadFilter, SortKey, SpeedSample},
+    group::DownloadGroup,
+    store::DownloadStore,
+};
 
 #[derive(Error, Debug)]
 pub enum DBError {
     #[error("Download #{0} not found")]
     DownloadNotFound(i64),
 
+    #[error("Group #{0} not found")]
+    GroupNotFound(i64),
+
     #[error("Rusqlite error: {0}")]
     RusqliteError(#[from] rusqlite::Error),
 
     #[error("IO error: {0}")]
     IOError(#[from] io::Error),
+
+    #[error("Database schema version {0} is newer than version {1} supported by this build")]
+    NewerSchema(u32, u32),
+
+    #[error("Migration to schema version {0} failed: {1}")]
+    MigrationFailed(u32, rusqlite::Error),
+
+    #[error("Database task failed: {0}")]
+    TaskError(#[from] task::JoinError),
 }
 
-const DB_DIR: &str = "~/.local/share/flowd/";
 const DB_NAME: &str = "downloads.db";
 
-const FLOWD_MIGRATIONS_DIR: &str = "/usr/share/flowd/migrations";
+static DATA_DIR: OnceLock<String> = OnceLock::new();
+
+/// Keeps the database in this directory instead of `$XDG_DATA_HOME/flowd`, has no effect
+/// once the database path was read
+pub fn set_data_dir(dir: &str) {
+    _ = DATA_DIR.set(utils::path::expand(dir));
+}
 
-fn get_db_path() -> String {
-    let db_path = Path::new(DB_DIR).join(DB_NAME);
-    let db_path_string = db_path.to_string_lossy().to_string();
-    utils::path::expand(&db_path_string)
+pub fn get_data_dir() -> String {
+    DATA_DIR
+        .get_or_init(|| {
+            Path::new(&xdg::get_data_home())
+                .join("flowd")
+                .to_string_lossy()
+                .to_string()
+        })
+        .clone()
 }
 
-async fn get_migrations(from_version: u16) -> Result<String, io::Error> {
-    let mut migrations = String::new();
+/// Returns the path of the database of the user
+pub fn get_db_path() -> String {
+    Path::new(&get_data_dir())
+        .join(DB_NAME)
+        .to_string_lossy()
+        .to_string()
+}
 
-    let mut version = from_version;
-    loop {
-        let file = Path::new(FLOWD_MIGRATIONS_DIR).join(format!("{}.sql", version));
+/// Migrations embedded in the binary, the one at index `i` upgrades the schema to version `i + 1`
+const MIGRATIONS: [&str; 11] = [
+    include_str!("../resources/db/migrations/1.sql"),
+    include_str!("../resources/db/migrations/2.sql"),
+    include_str!("../resources/db/migrations/3.sql"),
+    include_str!("../resources/db/migrations/4.sql"),
+    include_str!("../resources/db/migrations/5.sql"),
+    include_str!("../resources/db/migrations/6.sql"),
+    include_str!("../resources/db/migrations/7.sql"),
+    include_str!("../resources/db/migrations/8.sql"),
+    include_str!("../resources/db/migrations/9.sql"),
+    include_str!("../resources/db/migrations/10.sql"),
+    include_str!("../resources/db/migrations/11.sql"),
+];
 
-        if !file.as_path().exists() {
-            break;
-        }
+/// Version of the schema this build works with
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
 
-        let sql_script = fs::read(&file).await?;
-        migrations.push_str(&String::from_utf8(sql_script).unwrap());
+fn get_schema_version(connection: &Connection) -> Result<u32, DBError> {
+    Ok(connection.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
 
-        version += 1;
+/// Upgrades the schema to the version of this build, each migration in its own transaction
+///
+/// # Returns
+///
+/// * `u32` - The count of applied migrations
+pub(crate) fn migrate(connection: &mut Connection) -> Result<u32, DBError> {
+    let version = get_schema_version(connection)?;
+    if version > SCHEMA_VERSION {
+        return Err(DBError::NewerSchema(version, SCHEMA_VERSION));
     }
 
-    Ok(migrations)
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let target_version = index as u32 + 1;
+        log::info!("Upgrading database schema to version {}", target_version);
+        let transaction = connection.transaction()?;
+        transaction
+            .execute_batch(migration)
+            .and_then(|_| transaction.pragma_update(None, "user_version", target_version))
+            .map_err(|e| DBError::MigrationFailed(target_version, e))?;
+        transaction.commit()?;
+    }
+    Ok(SCHEMA_VERSION - version)
 }
 
-async fn verify_and_update_schema(init: bool) -> Result<(), DBError> {
-    log::debug!("Verifying and updating database schema...");
+/// Copies the database next to it before its schema is upgraded
+///
+/// # Returns
+///
+/// * `String` - The path of the backup
+fn backup_database(connection: &Connection, path: &str, version: u32) -> Result<String, DBError> {
+    let backup_path = format!("{}.v{}.bak", path, version);
+    if Path::new(&backup_path).exists() {
+        std::fs::remove_file(&backup_path)?;
+    }
+    connection.execute("VACUUM INTO ?1", [&backup_path])?;
+    Ok(backup_path)
+}
 
-    let next_version = if init {
-        1
-    } else {
-        let connection = connect().await?;
+/// State of the database file compared to the schema of this build
+pub struct DBCheck {
+    pub path: String,
+    pub exists: bool,
+    pub version: u32,
+    /// Problems reported by the SQLite integrity check, empty if none
+    pub problems: Vec<String>,
+}
 
-        connection.pragma_query_value(None, "user_version", |row| Ok(row.get::<_, u16>(0)))?? + 1
-        // Start from next version
-    };
-    
-    let migrations = get_migrations(next_version).await?;
-    let connection = connect().await?;
-    connection.execute_batch(&migrations)?;
+/// Inspects the database without creating or upgrading it
+pub async fn check() -> Result<DBCheck, DBError> {
+    let path = get_db_path();
+    if !Path::new(&path).exists() {
+        return Ok(DBCheck {
+            path,
+            exists: false,
+            version: 0,
+            problems: vec![],
+        });
+    }
 
-    Ok(())
+    let connection = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let version = get_schema_version(&connection)?;
+    let mut stmt = connection.prepare("PRAGMA integrity_check")?;
+    let problems = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?
+        .into_iter()
+        .filter(|result| result != "ok")
+        .collect();
+    Ok(DBCheck {
+        path,
+        exists: true,
+        version,
+        problems,
+    })
 }
 
-pub async fn init() -> Result<(), DBError> {
-    let db_path = get_db_path();
-    let db_exists = Path::new(&db_path).exists();
-    if db_exists {
-        verify_and_update_schema(false).await?;
-        return Ok(());
+/// Connections kept open between queries, enough to serve many downloads at once
+const POOL_SIZE: usize = 8;
+/// How long a query waits for another connection to release a lock before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Open connections to the database shared by all tasks
+struct ConnectionPool {
+    path: String,
+    idle: std::sync::Mutex<Vec<Connection>>,
+    permits: Semaphore,
+}
+
+impl ConnectionPool {
+    fn new(path: &str) -> ConnectionPool {
+        ConnectionPool {
+            path: path.to_string(),
+            idle: std::sync::Mutex::new(vec![]),
+            permits: Semaphore::new(POOL_SIZE),
+        }
     }
 
-    // Create DB directory and file
-    fs::create_dir_all(utils::path::expand(DB_DIR))
+    fn take(&self) -> Result<Connection, DBError> {
+        let connection = self.idle.lock().unwrap().pop();
+        match connection {
+            Some(connection) => Ok(connection),
+            None => Ok(open_connection(&self.path)?),
+        }
+    }
+
+    fn give_back(&self, connection: Connection) {
+        self.idle.lock().unwrap().push(connection);
+    }
+
+    /// Runs the work on a pooled connection in a blocking thread, so the async executor keeps
+    /// running while SQLite waits on the disk or a lock
+    async fn run<T, F>(&self, work: F) -> Result<T, DBError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, DBError> + Send + 'static,
+    {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("The pool is never closed");
+        let mut connection = self.take()?;
+        let (connection, result) = task::spawn_blocking(move || {
+            let result = work(&mut connection);
+            (connection, result)
+        })
         .await?;
-    File::create(&db_path).await?;
+        self.give_back(connection);
+        result
+    }
+}
 
-    verify_and_update_schema(true).await?;
+pub(crate) fn open_connection(path: &str) -> rusqlite::Result<Connection> {
+    let connection = Connection::open(path)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    // Readers do not block the writer and the other way around
+    connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+    connection.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(connection)
+}
 
-    Ok(())
+/// Downloads stored in an SQLite database
+pub struct SqliteStore {
+    pool: ConnectionPool,
 }
 
-async fn connect() -> rusqlite::Result<Connection> {
-    let db_path = get_db_path();
-    Connection::open(&db_path)
+impl SqliteStore {
+    /// Opens the database, creating it or upgrading its schema to the version of this build
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the database file, its directory is created if missing
+    pub async fn open(path: &str) -> Result<SqliteStore, DBError> {
+        if let Some(directory) = Path::new(path).parent() {
+            fs::create_dir_all(directory).await?;
+        }
+        let store = SqliteStore {
+            pool: ConnectionPool::new(path),
+        };
+        let path = path.to_string();
+        store
+            .pool
+            .run(move |connection| {
+                let version = get_schema_version(connection)?;
+                if version > SCHEMA_VERSION {
+                    return Err(DBError::NewerSchema(version, SCHEMA_VERSION));
+                }
+                if version > 0 && version < SCHEMA_VERSION {
+                    let backup_path = backup_database(connection, &path, version)?;
+                    log::info!("Saved a backup of the database to {}", backup_path);
+                }
+                migrate(connection)?;
+                Ok(())
+            })
+            .await?;
+        Ok(store)
+    }
+
+    async fn get_downloads_from_query<P>(
+        &self,
+        query: &'static str,
+        params: P,
+    ) -> Result<Vec<Download>, DBError>
+    where
+        P: Params + Send + 'static,
+    {
+        self.pool
+            .run(move |connection| query_downloads(connection, query, params))
+            .await
+    }
+
+    async fn get_groups_from_query<P>(
+        &self,
+        query: &'static str,
+        params: P,
+    ) -> Result<Vec<DownloadGroup>, DBError>
+    where
+        P: Params + Send + 'static,
+    {
+        self.pool
+            .run(move |connection| {
+                let mut stmt = connection.prepare_cached(query)?;
+                let groups = stmt
+                    .query_map(params, |row| {
+                        Ok(DownloadGroup {
+                            id: row.get(0)?,
+                            name: row.get(1)?,
+                            directory: row.get(2)?,
+                            date_added: row.get(3)?,
+                            date_completed: row.get(4)?,
+                            ..Default::default()
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<DownloadGroup>>>()?;
+                Ok(groups)
+            })
+            .await
+    }
 }
 
-pub async fn new_download(download: &Download) -> Result<i64, DBError> {
-    let completed_date = download
-        .date_completed
-        .and_then(|d| Some(d.to_string()))
-        .or(Some("NULL".to_string()))
-        .unwrap();
-    let connection = connect().await?;
-    connection.execute(
-        "
+#[async_trait]
+impl DownloadStore for SqliteStore {
+    async fn new_download(&self, download: &Download) -> Result<i64, DBError> {
+        let download = download.clone();
+        self.pool
+            .run(move |connection| insert_download(connection, &download))
+            .await
+    }
+
+    async fn new_downloads(&self, downloads: &[Download]) -> Result<Vec<i64>, DBError> {
+        let downloads = downloads.to_vec();
+        self.pool
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                let mut ids = vec![];
+                for download in &downloads {
+                    ids.push(insert_download(&transaction, download)?);
+                }
+                transaction.commit()?;
+                Ok(ids)
+            })
+            .await
+    }
+
+    async fn new_downloads_in_group(
+        &self,
+        group: &DownloadGroup,
+        downloads: &[Download],
+    ) -> Result<(i64, Vec<i64>), DBError> {
+        let group = group.clone();
+        let downloads = downloads.to_vec();
+        self.pool
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                let group_id = insert_group(&transaction, &group)?;
+                let mut ids = vec![];
+                for mut download in downloads {
+                    download.group_id = Some(group_id);
+                    ids.push(insert_download(&transaction, &download)?);
+                }
+                transaction.commit()?;
+                Ok((group_id, ids))
+            })
+            .await
+    }
+
+    async fn get_all_downloads(&self) -> Result<Vec<Download>, DBError> {
+        self.get_downloads_from_query("SELECT * FROM downloads", [])
+            .await
+    }
+
+    async fn get_download_by_id(&self, id: i64) -> Result<Download, DBError> {
+        let download = self
+            .get_downloads_from_query("SELECT * FROM downloads WHERE id = ?1", [id])
+            .await?
+            .pop();
+
+        if let Some(download) = download {
+            return Ok(download);
+        }
+
+        Err(DBError::DownloadNotFound(id))
+    }
+
+    async fn get_downloads_by_status(
+        &self,
+        status: &DownloadStatus,
+    ) -> Result<Vec<Download>, DBError> {
+        self.get_downloads_from_query(
+            "SELECT * FROM downloads WHERE status = ?1 ORDER BY queue_position, id",
+            [status.clone()],
+        )
+        .await
+    }
+
+    async fn update_download(&self, download: &Download) -> Result<(), DBError> {
+        let download = download.clone();
+        self.pool
+            .run(move |connection| {
+                let mut stmt = connection.prepare_cached(
+                    "
+            UPDATE downloads
+            SET
+                url = ?1,
+                status = ?2,
+                data_confirmed = ?3,
+                detected_output_file = ?4,
+                output_file = ?5,
+                temp_file = ?6,
+                resumable = ?7,
+                date_added = ?8,
+                date_completed = ?9,
+                size = ?10,
+                conflict_policy = ?11,
+                etag = ?12,
+                last_modified = ?13,
+                downloaded_bytes = ?14,
+                output_directory = ?15,
+                group_id = ?16,
+                category = ?17,
+                content_type = ?18
+            WHERE id = ?19
+            ",
+                )?;
+                stmt.execute(rusqlite::params![
+                    download.url,
+                    download.status,
+                    download.data_confirmed,
+                    download.detected_output_file,
+                    download.output_file,
+                    download.temp_file,
+                    download.resumable,
+                    download.date_added,
+                    download.date_completed,
+                    download.size,
+                    download.conflict_policy,
+                    download.etag,
+                    download.last_modified,
+                    download.downloaded_bytes,
+                    download.output_directory,
+                    download.group_id,
+                    download.category,
+                    download.content_type,
+                    download.id,
+                ])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn delete_download(&self, download_id: i64) -> Result<(), DBError> {
+        self.pool
+            .run(move |connection| {
+                connection
+                    .prepare_cached("DELETE FROM downloads WHERE id = ?1")?
+                    .execute([download_id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn change_download_status(
+        &self,
+        download_id: i64,
+        status: &DownloadStatus,
+    ) -> Result<(), DBError> {
+        let status = status.clone();
+        self.pool
+            .run(move |connection| {
+                connection
+                    .prepare_cached("UPDATE downloads SET status = ?1 WHERE id = ?2")?
+                    .execute(rusqlite::params![status, download_id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn change_download_downloaded_bytes(
+        &self,
+        download_id: i64,
+        downloaded_bytes: u64,
+    ) -> Result<(), DBError> {
+        self.pool
+            .run(move |connection| {
+                connection
+                    .prepare_cached("UPDATE downloads SET downloaded_bytes = ?1 WHERE id = ?2")?
+                    .execute(rusqlite::params![downloaded_bytes, download_id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn change_queue_positions(&self, positions: &[(i64, i64)]) -> Result<(), DBError> {
+        let positions = positions.to_vec();
+        self.pool
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                for (download_id, position) in &positions {
+                    transaction
+                        .prepare_cached("UPDATE downloads SET queue_position = ?1 WHERE id = ?2")?
+                        .execute(rusqlite::params![position, download_id])?;
+                }
+                transaction.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_category_counts(&self) -> Result<HashMap<String, u32>, DBError> {
+        self.pool
+            .run(move |connection| {
+                let mut counts = HashMap::new();
+                let mut stmt = connection
+                    .prepare_cached("SELECT category, COUNT(*) FROM downloads GROUP BY category")?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                for row in rows {
+                    let (category, count): (String, u32) = row?;
+                    counts.insert(category, count);
+                }
+                Ok(counts)
+            })
+            .await
+    }
+
+    async fn refresh_categories(&self, config: &Config) -> Result<usize, DBError> {
+        let config = config.clone();
+        self.pool
+            .run(move |connection| sync_download_categories(connection, &config))
+            .await
+    }
+
+    async fn list_downloads(
+        &self,
+        filter: &DownloadFilter,
+        sort: SortKey,
+        descending: bool,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<Download>, u64), DBError> {
+        let filter = filter.clone();
+        self.pool
+            .run(move |connection| {
+                query_download_list(connection, &filter, sort, descending, offset, limit)
+            })
+            .await
+    }
+
+    async fn add_speed_sample(&self, host: &str, sample: &SpeedSample) -> Result<(), DBError> {
+        let host = host.to_string();
+        let sample = sample.clone();
+        self.pool
+            .run(move |connection| {
+                connection
+                    .prepare_cached(
+                        "
+                INSERT INTO download_speed_samples (download_id, host, timestamp, speed, bytes)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+                ",
+                    )?
+                    .execute(rusqlite::params![
+                        sample.download_id,
+                        host,
+                        sample.timestamp,
+                        sample.speed,
+                        sample.bytes
+                    ])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_speed_samples(&self, download_id: i64) -> Result<Vec<SpeedSample>, DBError> {
+        self.pool
+            .run(move |connection| {
+                let mut stmt = connection.prepare_cached(
+                    "
+                SELECT download_id, timestamp, speed, bytes
+                FROM download_speed_samples
+                WHERE download_id = ?1
+                ORDER BY timestamp
+                ",
+                )?;
+                let samples = stmt
+                    .query_map([download_id], |row| {
+                        Ok(SpeedSample {
+                            download_id: row.get(0)?,
+                            timestamp: row.get(1)?,
+                            speed: row.get(2)?,
+                            bytes: row.get(3)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<SpeedSample>>>()?;
+                Ok(samples)
+            })
+            .await
+    }
+
+    async fn get_bandwidth_usage(
+        &self,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<BandwidthUsage>, DBError> {
+        self.pool
+            .run(move |connection| {
+                let mut stmt = connection.prepare_cached(
+                    "
+                SELECT date(timestamp, 'unixepoch', 'localtime') AS day, host, SUM(bytes)
+                FROM download_speed_samples
+                WHERE timestamp BETWEEN ?1 AND ?2
+                GROUP BY day, host
+                ORDER BY day, host
+                ",
+                )?;
+                let usage = stmt
+                    .query_map([from, to], |row| {
+                        Ok(BandwidthUsage {
+                            day: row.get(0)?,
+                            host: row.get(1)?,
+                            bytes: row.get(2)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<BandwidthUsage>>>()?;
+                Ok(usage)
+            })
+            .await
+    }
+
+    async fn new_group(&self, group: &DownloadGroup) -> Result<i64, DBError> {
+        let group = group.clone();
+        self.pool
+            .run(move |connection| insert_group(connection, &group))
+            .await
+    }
+
+    async fn get_all_groups(&self) -> Result<Vec<DownloadGroup>, DBError> {
+        self.get_groups_from_query(
+            "SELECT id, name, directory, date_added, date_completed FROM groups",
+            [],
+        )
+        .await
+    }
+
+    async fn get_group_by_id(&self, group_id: i64) -> Result<DownloadGroup, DBError> {
+        let group = self
+            .get_groups_from_query(
+                "SELECT id, name, directory, date_added, date_completed FROM groups WHERE id = ?1",
+                [group_id],
+            )
+            .await?
+            .pop();
+
+        group.ok_or(DBError::GroupNotFound(group_id))
+    }
+
+    async fn get_group_downloads(&self, group_id: i64) -> Result<Vec<Download>, DBError> {
+        self.get_downloads_from_query("SELECT * FROM downloads WHERE group_id = ?1", [group_id])
+            .await
+    }
+
+    async fn change_group_date_completed(
+        &self,
+        group_id: i64,
+        date_completed: Option<i64>,
+    ) -> Result<(), DBError> {
+        self.pool
+            .run(move |connection| {
+                connection
+                    .prepare_cached("UPDATE groups SET date_completed = ?1 WHERE id = ?2")?
+                    .execute(rusqlite::params![date_completed, group_id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn change_downloads_group(
+        &self,
+        ids: &[i64],
+        group_id: Option<i64>,
+    ) -> Result<(), DBError> {
+        let ids = ids.to_vec();
+        self.pool
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                for id in ids {
+                    let changed = transaction
+                        .prepare_cached("UPDATE downloads SET group_id = ?1 WHERE id = ?2")?
+                        .execute(rusqlite::params![group_id, id])?;
+                    if changed == 0 {
+                        return Err(DBError::DownloadNotFound(id));
+                    }
+                }
+                transaction.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn delete_group(&self, group_id: i64) -> Result<(), DBError> {
+        self.pool
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                transaction.execute(
+                    "UPDATE downloads SET group_id = NULL WHERE group_id = ?1",
+                    [group_id],
+                )?;
+                transaction.execute("DELETE FROM groups WHERE id = ?1", [group_id])?;
+                transaction.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_download_tags(&self, download_id: i64) -> Result<Vec<String>, DBError> {
+        self.pool
+            .run(move |connection| {
+                let mut stmt = connection.prepare_cached(
+                    "SELECT tag FROM download_tags WHERE download_id = ?1 ORDER BY tag",
+                )?;
+                let tags = stmt
+                    .query_map([download_id], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<String>>>()?;
+                Ok(tags)
+            })
+            .await
+    }
+
+    async fn set_download_tags(&self, download_id: i64, tags: &[String]) -> Result<(), DBError> {
+        self.get_download_by_id(download_id).await?;
+
+        let tags = tags.to_vec();
+        self.pool
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                transaction.execute(
+                    "DELETE FROM download_tags WHERE download_id = ?1",
+                    [download_id],
+                )?;
+                for tag in tags {
+                    transaction
+                    .prepare_cached(
+                        "INSERT OR IGNORE INTO download_tags (download_id, tag) VALUES (?1, ?2)",
+                    )?
+                    .execute(rusqlite::params![download_id, tag])?;
+                }
+                transaction.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn add_download_tag(&self, download_id: i64, tag: &str) -> Result<(), DBError> {
+        self.get_download_by_id(download_id).await?;
+
+        let tag = tag.to_string();
+        self.pool
+            .run(move |connection| {
+                connection
+                    .prepare_cached(
+                        "INSERT OR IGNORE INTO download_tags (download_id, tag) VALUES (?1, ?2)",
+                    )?
+                    .execute(rusqlite::params![download_id, tag])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn remove_download_tag(&self, download_id: i64, tag: &str) -> Result<(), DBError> {
+        let tag = tag.to_string();
+        self.pool
+            .run(move |connection| {
+                connection
+                    .prepare_cached(
+                        "DELETE FROM download_tags WHERE download_id = ?1 AND tag = ?2",
+                    )?
+                    .execute(rusqlite::params![download_id, tag])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_download_note(&self, download_id: i64) -> Result<Option<String>, DBError> {
+        self.pool
+            .run(move |connection| {
+                let note = connection
+                    .prepare_cached("SELECT note FROM download_notes WHERE download_id = ?1")?
+                    .query_row([download_id], |row| row.get(0))
+                    .optional()?;
+                Ok(note)
+            })
+            .await
+    }
+
+    async fn set_download_note(&self, download_id: i64, note: &str) -> Result<(), DBError> {
+        self.get_download_by_id(download_id).await?;
+
+        let note = note.to_string();
+        self.pool
+            .run(move |connection| {
+                if note.is_empty() {
+                    connection
+                        .prepare_cached("DELETE FROM download_notes WHERE download_id = ?1")?
+                        .execute([download_id])?;
+                } else {
+                    connection
+                        .prepare_cached(
+                            "
+                    INSERT INTO download_notes (download_id, note) VALUES (?1, ?2)
+                    ON CONFLICT (download_id) DO UPDATE SET note = excluded.note
+                    ",
+                        )?
+                        .execute(rusqlite::params![download_id, note])?;
+                }
+                Ok(())
+            })
+            .await
+    }
+}
+
+fn insert_download(connection: &Connection, download: &Download) -> Result<i64, DBError> {
+    connection
+        .prepare_cached(
+            "
         INSERT INTO downloads (
             url,
             status,
@@ -113,7 +833,16 @@ pub async fn new_download(download: &Download) -> Result<i64, DBError> {
             resumable,
             date_added,
             date_completed,
-            size
+            size,
+            conflict_policy,
+            etag,
+            last_modified,
+            downloaded_bytes,
+            output_directory,
+            group_id,
+            category,
+            content_type,
+            queue_position
         )
         VALUES (
             ?1,
@@ -125,53 +854,98 @@ pub async fn new_download(download: &Download) -> Result<i64, DBError> {
             ?7,
             ?8,
             ?9,
-            ?10
+            ?10,
+            ?11,
+            ?12,
+            ?13,
+            ?14,
+            ?15,
+            ?16,
+            ?17,
+            ?18,
+            (SELECT coalesce(max(queue_position), 0) + 1 FROM downloads)
         )
         ",
-        &[
-            &download.url,
-            download.status.get_string(),
-            &download.data_confirmed.to_string(),
-            download
-                .detected_output_file
-                .as_deref()
-                .or(Some("NULL"))
-                .unwrap(),
-            download.output_file.as_deref().or(Some("NULL")).unwrap(),
-            &download.temp_file,
-            &download.resumable.to_string(),
-            &download.date_added.to_string(),
-            &completed_date,
-            &download
-                .size
-                .and_then(|size| Some(size.to_string()))
-                .unwrap_or("NULL".to_string()),
-        ],
-    )?;
+        )?
+        .execute(rusqlite::params![
+            download.url,
+            download.status,
+            download.data_confirmed,
+            download.detected_output_file,
+            download.output_file,
+            download.temp_file,
+            download.resumable,
+            download.date_added,
+            download.date_completed,
+            download.size,
+            download.conflict_policy,
+            download.etag,
+            download.last_modified,
+            download.downloaded_bytes,
+            download.output_directory,
+            download.group_id,
+            download.category,
+            download.content_type,
+        ])?;
     Ok(connection.last_insert_rowid())
 }
 
-async fn get_downloads_from_query(
+impl ToSql for DownloadStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.get_string()))
+    }
+}
+
+impl FromSql for DownloadStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let status = value.as_str()?;
+        DownloadStatus::from_string(status)
+            .ok_or_else(|| FromSqlError::Other(format!("Invalid download status: {status}").into()))
+    }
+}
+
+impl ToSql for ConflictPolicy {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.get_string()))
+    }
+}
+
+impl FromSql for ConflictPolicy {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let policy = value.as_str()?;
+        ConflictPolicy::from_string(policy)
+            .ok_or_else(|| FromSqlError::Other(format!("Invalid conflict policy: {policy}").into()))
+    }
+}
+
+pub(crate) fn query_downloads(
+    connection: &Connection,
     query: &str,
     params: impl Params,
 ) -> Result<Vec<Download>, DBError> {
-    let connection = connect().await?;
-
-    let mut stmt = connection.prepare(query)?;
+    let mut stmt = connection.prepare_cached(query)?;
     let downloads_iter = stmt.query_map(params, |row| {
-        let status: String = row.get(2).unwrap();
         Ok(Download {
-            id: row.get(0)?,
-            url: row.get(1)?,
-            status: DownloadStatus::from_string(&status),
-            data_confirmed: row.get::<usize, String>(3)?.parse::<bool>().unwrap(),
-            detected_output_file: string_to_option(row.get(4)?),
-            output_file: string_to_option(row.get(5)?),
-            temp_file: row.get(6)?,
-            resumable: row.get::<usize, String>(7)?.parse::<bool>().unwrap(),
-            date_added: row.get::<usize, i64>(8)?,
-            date_completed: row.get::<usize, i64>(9).ok(),
-            size: row.get(10).ok(),
+            id: row.get("id")?,
+            url: row.get("url")?,
+            status: row.get("status")?,
+            data_confirmed: row.get("data_confirmed")?,
+            detected_output_file: row.get("detected_output_file")?,
+            output_file: row.get("output_file")?,
+            output_directory: row.get("output_directory")?,
+            temp_file: row.get("temp_file")?,
+            resumable: row.get("resumable")?,
+            date_added: row.get("date_added")?,
+            date_completed: row.get("date_completed")?,
+            size: row.get("size")?,
+            conflict_policy: row.get("conflict_policy")?,
+            etag: row.get("etag")?,
+            last_modified: row.get("last_modified")?,
+            downloaded_bytes: row.get("downloaded_bytes")?,
+            group_id: row.get("group_id")?,
+            category: row.get("category")?,
+            queue_position: row.get("queue_position")?,
+            content_type: row.get("content_type")?,
         })
     })?;
 
@@ -182,195 +956,223 @@ async fn get_downloads_from_query(
     Ok(downloads)
 }
 
-pub async fn get_all_downloads() -> Result<Vec<Download>, DBError> {
-    get_downloads_from_query("SELECT * FROM downloads", []).await
-}
-
-pub async fn get_download_by_id(id: i64) -> Result<Download, DBError> {
-    let download = get_downloads_from_query("SELECT * FROM downloads WHERE id = ?1", [id])
-        .await?
-        .pop();
-
-    if let Some(download) = download {
-        return Ok(download);
+/// Re-evaluates the category of every download when the categories in config differ from
+/// the ones the stored categories were computed with
+///
+/// # Returns
+///
+/// * `usize` - The count of downloads whose category changed
+pub(crate) fn sync_download_categories(
+    connection: &mut Connection,
+    config: &Config,
+) -> Result<usize, DBError> {
+    let fingerprint = get_categories_fingerprint(config);
+    let stored_fingerprint = connection
+        .prepare_cached("SELECT value FROM settings WHERE key = ?1")?
+        .query_row([CATEGORIES_SETTING], |row| row.get::<usize, String>(0))
+        .optional()?;
+    if stored_fingerprint.as_ref() == Some(&fingerprint) {
+        return Ok(0);
     }
-    
-    return Err(DBError::DownloadNotFound(id));
-}
 
-pub async fn get_pending_downloads() -> Result<Vec<Download>, DBError> {
-    get_downloads_from_query(
-        "SELECT * FROM downloads WHERE status = ?1",
-        [DownloadStatus::Pending.get_string()],
-    )
-    .await
-}
-
-pub async fn get_sorted_downloads() -> Result<Vec<Download>, DBError> {
-    get_downloads_from_query(
-        "SELECT * FROM downloads ORDER BY CASE WHEN status = ?1 THEN 1 WHEN status = ?2 THEN 2 WHEN status = ?3 THEN 3 WHEN status = ?4 THEN 4 WHEN status = ?5 THEN 6 ELSE 5 END, date_added DESC",
-        [
-            DownloadStatus::InProgress.get_string(),
-            DownloadStatus::Starting.get_string(),
-            DownloadStatus::Pending.get_string(),
-            DownloadStatus::Paused.get_string(),
-            DownloadStatus::Completed.get_string(),
-        ],
-    )
-    .await
+    let downloads = query_downloads(connection, "SELECT * FROM downloads", [])?;
+    let transaction = connection.transaction()?;
+    let mut changed = 0;
+    for download in downloads {
+        let category = category::explain_download_category(&download, config).category;
+        if category != download.category {
+            transaction.execute(
+                "UPDATE downloads SET category = ?1 WHERE id = ?2",
+                rusqlite::params![category, download.id],
+            )?;
+            changed += 1;
+        }
+    }
+    transaction.execute(
+        "INSERT INTO settings (key, value) VALUES (?1, ?2)
+        ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+        [CATEGORIES_SETTING, &fingerprint],
+    )?;
+    transaction.commit()?;
+    Ok(changed)
 }
 
-pub async fn get_in_progress_downloads() -> Result<Vec<Download>, DBError> {
-    get_downloads_from_query(
-        "SELECT * FROM downloads WHERE status = ?1",
-        [DownloadStatus::InProgress.get_string()],
-    )
-    .await
-}
+const CATEGORIES_SETTING: &str = "categories";
 
-pub async fn get_completed_downloads() -> Result<Vec<Download>, DBError> {
-    get_downloads_from_query(
-        "SELECT * FROM downloads WHERE status = ?1",
-        [DownloadStatus::Completed.get_string()],
-    )
-    .await
+/// Serializes the categories in a stable order, so any change to them changes the result
+pub(crate) fn get_categories_fingerprint(config: &Config) -> String {
+    let categories = config.categories.iter().collect::<BTreeMap<_, _>>();
+    toml::to_string(&categories).unwrap_or_default()
 }
 
-pub async fn get_uncompleted_downloads() -> Result<Vec<Download>, DBError> {
-    get_downloads_from_query(
-        "SELECT * FROM downloads WHERE status != ?1",
-        [DownloadStatus::Completed.get_string()],
-    )
-    .await
+fn insert_group(connection: &Connection, group: &DownloadGroup) -> Result<i64, DBError> {
+    connection
+        .prepare_cached(
+            "
+        INSERT INTO groups (name, directory, date_added, date_completed)
+        VALUES (?1, ?2, ?3, ?4)
+        ",
+        )?
+        .execute(rusqlite::params![
+            group.name,
+            group.directory,
+            group.date_added,
+            group.date_completed
+        ])?;
+    Ok(connection.last_insert_rowid())
 }
 
-pub async fn get_downloads_by_category(category: &str) -> Result<Vec<Download>, DBError> {
-    let categories = config::get_categories().await;
-    let the_category = categories.get(category);
+pub(crate) fn query_download_list(
+    connection: &Connection,
+    filter: &DownloadFilter,
+    sort: SortKey,
+    descending: bool,
+    offset: u64,
+    limit: u64,
+) -> Result<(Vec<Download>, u64), DBError> {
+    let mut params: Vec<Value> = vec![];
+    let mut from = String::from("downloads");
+    let mut conditions: Vec<String> = vec![];
 
-    if let None = the_category {
-        log::error!("Category `{}` does not exist", category);
-        return Ok(vec![]);
+    let fts_query = get_fts_query(filter.text.as_deref().unwrap_or_default());
+    let searching = !fts_query.is_empty();
+    if searching {
+        from.push_str(" JOIN downloads_search ON downloads_search.rowid = downloads.id");
+        params.push(Value::Text(fts_query));
+        conditions.push(format!("downloads_search MATCH ?{}", params.len()));
     }
-
-    let category = the_category.unwrap();
-
-    let mut conditions: Vec<String> = vec![];
-    for i in 0..category.extensions.len() {
-        conditions.push(format!(
-            "output_file LIKE %?{} OR detected_output_file LIKE %?{}",
-            i, i
-        ));
+    if let Some(status) = &filter.status {
+        params.push(Value::Text(status.clone()));
+        conditions.push(format!("downloads.status = ?{}", params.len()));
+    }
+    if let Some(statuses) = &filter.statuses {
+        let mut placeholders = vec![];
+        for status in statuses {
+            params.push(Value::Text(status.clone()));
+            placeholders.push(format!("?{}", params.len()));
+        }
+        conditions.push(format!("downloads.status IN ({})", placeholders.join(", ")));
+    }
+    if let Some(category) = &filter.category {
+        params.push(Value::Text(category.clone()));
+        conditions.push(format!("downloads.category = ?{}", params.len()));
+    }
+    if let Some(host) = &filter.host {
+        // The host ends at the path, the port or the end of the URL
+        let host = host
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let mut patterns = vec![];
+        for pattern in [
+            format!("%://{}", host),
+            format!("%://{}/%", host),
+            format!("%://{}:%", host),
+            format!("%://{}?%", host),
+        ] {
+            params.push(Value::Text(pattern));
+            patterns.push(format!("downloads.url LIKE ?{} ESCAPE '\\'", params.len()));
+        }
+        conditions.push(format!("({})", patterns.join(" OR ")));
+    }
+    if let Some(date_from) = filter.date_from {
+        params.push(Value::Integer(date_from));
+        conditions.push(format!("downloads.date_added >= ?{}", params.len()));
+    }
+    if let Some(date_to) = filter.date_to {
+        params.push(Value::Integer(date_to));
+        conditions.push(format!("downloads.date_added <= ?{}", params.len()));
+    }
+    if let Some(min_size) = filter.min_size {
+        params.push(Value::Integer(min_size as i64));
+        conditions.push(format!("downloads.size >= ?{}", params.len()));
+    }
+    if let Some(max_size) = filter.max_size {
+        params.push(Value::Integer(max_size as i64));
+        conditions.push(format!("downloads.size <= ?{}", params.len()));
     }
 
-    let query = format!("SELECT * FROM downloads WHERE {}", conditions.join(" OR "));
-    // let extensions = category.extensions.iter().map(|x| x.as_str()).collect::<[&str]>()
-    let downloads =
-        get_downloads_from_query(query.as_str(), params_from_iter(category.extensions.iter()))
-            .await;
-
-    downloads
-}
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    };
 
-pub async fn update_download(download: &Download) -> Result<usize, DBError> {
-    let completed_date = download
-        .date_completed
-        .and_then(|d| Some(d.to_string()))
-        .or(Some("NULL".to_string()))
-        .unwrap();
-    let connection = connect().await?;
-    connection
-        .execute(
-            "
-        UPDATE downloads
-        SET
-            url = ?1,
-            status = ?2,
-            data_confirmed = ?3,
-            detected_output_file = ?4,
-            output_file = ?5,
-            temp_file = ?6,
-            resumable = ?7,
-            date_added = ?8,
-            date_completed = ?9,
-            size = ?10
-        WHERE id = ?11
-        ",
-            &[
-                &download.url,
-                download.status.get_string(),
-                &download.data_confirmed.to_string(),
-                download
-                    .detected_output_file
-                    .as_deref()
-                    .or(Some("NULL"))
-                    .unwrap(),
-                download.output_file.as_deref().or(Some("NULL")).unwrap(),
-                &download.temp_file,
-                &download.resumable.to_string(),
-                &download.date_added.to_string(),
-                &completed_date,
-                &download
-                    .size
-                    .and_then(|size| Some(size.to_string()))
-                    .unwrap_or("NULL".to_string()),
-                &download.id.to_string(),
-            ],
-        )
-        .map_err(|e| DBError::RusqliteError(e))
-}
+    let direction = if descending { "DESC" } else { "ASC" };
+    let order = match sort {
+        SortKey::DateAdded => format!("downloads.date_added {}", direction),
+        SortKey::DateCompleted => format!("downloads.date_completed {}", direction),
+        SortKey::Size => format!("downloads.size {}", direction),
+        SortKey::Url => format!("downloads.url {}", direction),
+        SortKey::Status => {
+            let mut cases = vec![];
+            for (rank, status) in STATUS_ORDER.iter().enumerate() {
+                params.push(Value::Text(status.get_string().to_string()));
+                cases.push(format!("WHEN ?{} THEN {}", params.len(), rank));
+            }
+            format!(
+                "CASE downloads.status {} ELSE {} END {}",
+                cases.join(" "),
+                STATUS_ORDER.len(),
+                direction
+            )
+        }
+        SortKey::Relevance if searching => "downloads_search.rank".to_string(),
+        SortKey::Relevance => "downloads.date_added DESC".to_string(),
+        SortKey::Queue => format!("downloads.queue_position {}", direction),
+    };
 
-pub async fn delete_download(download_id: i64) -> Result<usize, DBError> {
-    let connection = connect().await?;
-    connection
-        .execute(
-            "
-        DELETE FROM downloads
-        WHERE id = ?1
-        ",
-            &[&download_id.to_string()],
-        )
-        .map_err(|e| DBError::RusqliteError(e))
-}
+    let query = format!(
+        "SELECT downloads.* FROM {}{} ORDER BY {}, downloads.date_added DESC, downloads.id DESC",
+        from, where_clause, order
+    );
 
-pub async fn change_download_status(
-    download_id: &i64,
-    status: &DownloadStatus,
-) -> Result<usize, DBError> {
-    let connection = connect().await?;
-    connection
-        .execute(
-            "
-        UPDATE downloads
-        SET status = ?1
-        WHERE id = ?2
-        ",
-            &[status.get_string(), &download_id.to_string()],
-        )
-        .map_err(|e| DBError::RusqliteError(e))
-}
+    // The count does not depend on the sort parameters, which come last
+    let sort_params = match sort {
+        SortKey::Status => STATUS_ORDER.len(),
+        _ => 0,
+    };
+    let count_params = params.len() - sort_params;
+    let total = connection
+        .prepare_cached(&format!("SELECT COUNT(*) FROM {}{}", from, where_clause))?
+        .query_row(params_from_iter(params[..count_params].iter()), |row| {
+            row.get::<usize, u64>(0)
+        })?;
 
-pub async fn change_download_output_file_path(
-    download_id: i64,
-    output_file: &str,
-) -> Result<(), DBError> {
-    let mut download = get_download_by_id(download_id).await?;
-    download.output_file = Some(output_file.to_string());
-    update_download(&download).await?;
-    Ok(())
+    params.push(Value::Integer(if limit == 0 { -1 } else { limit as i64 }));
+    params.push(Value::Integer(offset as i64));
+    let query = format!(
+        "{} LIMIT ?{} OFFSET ?{}",
+        query,
+        params.len() - 1,
+        params.len()
+    );
+    let downloads = query_downloads(connection, &query, params_from_iter(params.iter()))?;
+    Ok((downloads, total))
 }
 
-pub async fn confirm_download_data(download_id: i64) -> Result<(), DBError> {
-    let mut download = get_download_by_id(download_id).await?;
-    download.data_confirmed = true;
-    update_download(&download).await?;
-    Ok(())
-}
+/// Order of statuses when sorting by status: running downloads, the ones waiting to run,
+/// stopped and failed ones, then canceled and completed ones
+pub(crate) const STATUS_ORDER: [DownloadStatus; 12] = [
+    DownloadStatus::InProgress,
+    DownloadStatus::Starting,
+    DownloadStatus::Retrying,
+    DownloadStatus::AwaitingConflictResolution,
+    DownloadStatus::Pending,
+    DownloadStatus::Paused,
+    DownloadStatus::InsufficientSpace,
+    DownloadStatus::ServerError,
+    DownloadStatus::ClientError,
+    DownloadStatus::UnknownError,
+    DownloadStatus::Canceled,
+    DownloadStatus::Completed,
+];
 
-fn string_to_option(string: String) -> Option<String> {
-    if string == "NULL" {
-        None
-    } else {
-        Some(string)
-    }
+/// Turns the words of a user query into an FTS5 query matching all of them as prefixes
+pub(crate) fn get_fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
 }
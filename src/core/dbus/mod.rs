@@ -1,43 +1,252 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use tokio::sync::{
-    broadcast::{Receiver, Sender},
+    broadcast::{error::RecvError, Receiver, Sender},
     Mutex,
 };
-use zbus::{Result, SignalContext};
+use zbus::zvariant::OwnedValue;
+use zbus::{fdo, Result, SignalContext};
 
-use crate::core::db;
+use crate::core::{
+    category::{self, CategoryMatch},
+    config::{self, ConfigEditor, ConfigError, ConfigProblem, ConflictPolicy},
+    db::DBError,
+    group::{self, DownloadGroup},
+    import::{self, ImportEntry, ImportError, ImportOptions},
+    queue::{self, QueueError},
+    store::DownloadStore,
+};
+use crate::utils;
+
+use super::download::{
+    BandwidthUsage, BulkAction, BulkReply, BulkResult, Download, DownloadEvent, DownloadFilter,
+    DownloadStatus, ProgressInfo, ProgressTracker, SortKey, SpeedSample,
+};
 
-use super::download::{Download, DownloadEvent};
+pub mod values;
 
 pub struct FlowListener {
     events_rx: Arc<Mutex<Receiver<DownloadEvent>>>,
     events_tx: Sender<DownloadEvent>,
+    progress_tracker: ProgressTracker,
+    store: Arc<dyn DownloadStore>,
+    /// Held from reading the user config to writing it, so concurrent edits are not lost
+    config_lock: Mutex<()>,
 }
 
 impl FlowListener {
     pub fn new(
         events_rx: Receiver<DownloadEvent>,
         events_tx: Sender<DownloadEvent>,
+        progress_tracker: ProgressTracker,
+        store: Arc<dyn DownloadStore>,
     ) -> FlowListener {
         FlowListener {
             events_rx: Arc::new(Mutex::new(events_rx)),
             events_tx,
+            progress_tracker,
+            store,
+            config_lock: Mutex::new(()),
         }
     }
 
     pub async fn listen_to_events(&self, ctx: SignalContext<'_>) {
-        while let Ok(event) = self.events_rx.lock().await.recv().await {
-            _ = self.handle_event(&ctx, event).await.map_err(|e| {
-                log::error!("Error while processing event: {e}");
-            });
+        loop {
+            let event = self.events_rx.lock().await.recv().await;
+            match event {
+                Ok(event) => {
+                    _ = self.handle_event(&ctx, event).await.map_err(|e| {
+                        log::error!("Error while processing event: {e}");
+                    });
+                }
+                Err(RecvError::Lagged(count)) => {
+                    log::warn!("Missed {count} events");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Sends a single event applying the action to the downloads it applies to and waits for
+    /// the downloader to apply it. The changed downloads are signaled once it is done with all
+    /// of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `action` - The action to apply
+    /// * `downloads` - The downloads to apply the action to
+    /// * `report_skipped` - Whether downloads the action does not apply to get an error result,
+    ///   otherwise they are left out of the results
+    async fn send_bulk_action(
+        &self,
+        action: BulkAction,
+        downloads: Vec<Download>,
+        report_skipped: bool,
+    ) -> fdo::Result<Vec<BulkResult>> {
+        let mut results = vec![];
+        let mut ids = vec![];
+        for download in downloads {
+            if action.applies_to(&download.status) {
+                ids.push(download.id);
+            } else if report_skipped {
+                results.push(BulkResult::skipped(download.id, action, &download.status));
+            }
+        }
+
+        if !ids.is_empty() {
+            let (reply, applied) = BulkReply::new();
+            self.events_tx
+                .send(DownloadEvent::Bulk(action, ids, reply))
+                .map_err(|e| {
+                    log::error!("Error sending bulk event: {}", e);
+                    fdo::Error::Failed(e.to_string())
+                })?;
+            let applied = applied.await.map_err(|e| {
+                log::error!("Bulk action was not applied: {}", e);
+                fdo::Error::Failed(e.to_string())
+            })?;
+            results.extend(applied);
+        }
+        Ok(results)
+    }
+
+    /// Applies the action to all members of the group
+    async fn apply_to_group(
+        &self,
+        action: BulkAction,
+        group_id: i64,
+    ) -> fdo::Result<Vec<BulkResult>> {
+        let downloads = self
+            .store
+            .get_group_downloads(group_id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        self.send_bulk_action(action, downloads, false).await
+    }
+
+    /// Returns the distinct groups the downloads belong to
+    async fn get_groups_of(&self, ids: &[i64]) -> Vec<i64> {
+        let mut group_ids = vec![];
+        for id in ids {
+            if let Ok(Some(group_id)) = self
+                .store
+                .get_download_by_id(*id)
+                .await
+                .map(|download| download.group_id)
+            {
+                if !group_ids.contains(&group_id) {
+                    group_ids.push(group_id);
+                }
+            }
+        }
+        group_ids
+    }
+
+    /// Sends the aggregate state of the group to be notified in DBus
+    async fn send_group_update(&self, group_id: i64) {
+        match group::get_group(&*self.store, group_id).await {
+            Ok(group) => {
+                _ = self
+                    .events_tx
+                    .send(DownloadEvent::GroupUpdate(group))
+                    .map_err(|e| {
+                        log::error!("Error sending group update event: {}", e);
+                    });
+            }
+            Err(e) => log::error!("Group #{}: {}", group_id, e),
+        }
+    }
+
+    /// Sorts the downloads again if the categories changed in config since the last time
+    async fn refresh_categories(&self) -> std::result::Result<(), DBError> {
+        let config = config::get_config().await;
+        let changed = self.store.refresh_categories(&config).await?;
+        if changed > 0 {
+            log::info!("Moved {} downloads to another category", changed);
+        }
+        Ok(())
+    }
+
+    /// Lists the downloads of the store, with up to date categories if filtering by category
+    async fn find_downloads(
+        &self,
+        filter: &DownloadFilter,
+        sort: SortKey,
+        descending: bool,
+        offset: u64,
+        limit: u64,
+    ) -> std::result::Result<(Vec<Download>, u64), DBError> {
+        if filter.category.is_some() {
+            self.refresh_categories().await?;
         }
+        self.store
+            .list_downloads(filter, sort, descending, offset, limit)
+            .await
+    }
+
+    /// Sets the output file of the download and the category its new name falls in
+    async fn set_output_file_path(
+        &self,
+        id: i64,
+        output_file: &str,
+    ) -> std::result::Result<(), DBError> {
+        let config = config::get_config().await;
+        let mut download = self.store.get_download_by_id(id).await?;
+        download.output_file = Some(output_file.to_string());
+        download.category = category::explain_download_category(&download, &config).category;
+        self.store.update_download(&download).await
+    }
+
+    /// Saves the edited user config and notifies the changed settings
+    async fn save_config(&self, editor: &ConfigEditor) -> fdo::Result<()> {
+        let changed_keys = editor.save().await.map_err(to_dbus_error)?;
+        self.send_config_changed(changed_keys);
+        Ok(())
+    }
+
+    fn send_config_changed(&self, changed_keys: Vec<String>) {
+        if !changed_keys.is_empty() {
+            log::info!("Config changed: {}", changed_keys.join(", "));
+            if let Err(e) = self
+                .events_tx
+                .send(DownloadEvent::ConfigChanged(changed_keys))
+            {
+                log::error!("Error sending config changed event: {}", e);
+            }
+        }
+    }
+
+    /// Applies the action to all downloads matching the filter
+    async fn apply_to_filtered(
+        &self,
+        action: BulkAction,
+        filter: &DownloadFilter,
+    ) -> fdo::Result<Vec<BulkResult>> {
+        let (downloads, _) = self
+            .find_downloads(filter, SortKey::DateAdded, true, 0, 0)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        self.send_bulk_action(action, downloads, false).await
     }
 
     pub async fn handle_event(&self, ctx: &SignalContext<'_>, event: DownloadEvent) -> Result<()> {
         match event {
-            DownloadEvent::DownloadProgress(id, progress, content_length) => {
-                Self::notify_download_progress(ctx, id, progress, content_length)
+            DownloadEvent::DownloadProgress(progress) => {
+                Self::notify_download_progress(
+                    ctx,
+                    progress.id,
+                    progress.downloaded,
+                    progress.size,
+                    progress.speed,
+                    progress.average_speed,
+                    progress.eta,
+                    progress.elapsed,
+                )
+                .await
+            }
+            DownloadEvent::GlobalProgress(total_speed, active_count) => {
+                Self::notify_global_progress(ctx, total_speed, active_count)
                     .await
             }
             DownloadEvent::DownloadUpdate(download_info) => {
@@ -48,6 +257,35 @@ impl FlowListener {
                 Self::notify_download_delete(ctx, download_id)
                     .await
             }
+            DownloadEvent::BulkUpdate(changes) => {
+                for download_info in changes.updated {
+                    Self::notify_download_update(ctx, download_info).await?;
+                }
+                for download_id in changes.deleted {
+                    Self::notify_download_delete(ctx, download_id).await?;
+                }
+                Ok(())
+            }
+            DownloadEvent::DownloadConflict(download_id, existing_file) => {
+                Self::notify_download_conflict(ctx, download_id, &existing_file)
+                    .await
+            }
+            DownloadEvent::DownloadError(download_id, error) => {
+                Self::notify_download_error(ctx, download_id.unwrap_or_default(), &error)
+                    .await
+            }
+            DownloadEvent::MoveProgress(id, copied, total) => {
+                Self::notify_move_progress(ctx, id, copied, total)
+                    .await
+            }
+            DownloadEvent::GroupUpdate(group) => {
+                Self::notify_group_update(ctx, group)
+                    .await
+            }
+            DownloadEvent::ConfigChanged(changed_keys) => {
+                Self::config_changed(ctx, changed_keys)
+                    .await
+            }
             _ => {
                 log::debug!("Unhandled event received: {event:?}");
                 Ok(())
@@ -65,34 +303,244 @@ impl FlowListener {
 
     async fn get_all_downloads(&self) -> Vec<Download> {
         log::info!("Getting all downloads");
-        db::get_all_downloads()
+        self.store
+            .get_all_downloads()
             .await
-            .map_err(|e| {
+            .inspect_err(|_| {
                 log::error!("Error getting all downloads");
-                e
             })
             .unwrap_or(vec![])
     }
 
     async fn get_downloads_by_completed_status(&self, completed: bool) -> Vec<Download> {
         log::info!("Getting downloads by completed status: {}", completed);
-        if completed {
-            db::get_completed_downloads().await.unwrap_or(vec![])
-        } else {
-            db::get_uncompleted_downloads().await.unwrap_or(vec![])
-        }
+        let statuses = DownloadStatus::ALL
+            .iter()
+            .filter(|status| matches!(status, DownloadStatus::Completed) == completed)
+            .map(|status| status.get_string().to_string())
+            .collect();
+        let filter = DownloadFilter {
+            statuses: Some(statuses),
+            ..Default::default()
+        };
+        self.find_downloads(&filter, SortKey::DateAdded, true, 0, 0)
+            .await
+            .map(|(downloads, _)| downloads)
+            .unwrap_or(vec![])
     }
 
     async fn get_downloads_by_category(&self, category: &str) -> Vec<Download> {
         log::info!("Getting downloads by category: {}", category);
-        db::get_downloads_by_category(category)
+        let filter = DownloadFilter {
+            category: Some(category.to_string()),
+            ..Default::default()
+        };
+        self.find_downloads(&filter, SortKey::DateAdded, true, 0, 0)
             .await
+            .map(|(downloads, _)| downloads)
             .unwrap_or(vec![])
     }
 
+    /// Counts the downloads of each category, the default directory is the empty name
+    async fn get_category_counts(&self) -> fdo::Result<HashMap<String, u32>> {
+        log::info!("Getting category counts");
+        self.refresh_categories()
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        let config = config::get_config().await;
+        let mut counts = self
+            .store
+            .get_category_counts()
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        // Configured categories are listed even without downloads
+        for name in config.categories.keys() {
+            counts.entry(name.clone()).or_insert(0);
+        }
+        Ok(counts)
+    }
+
     async fn get_sorted_downloads(&self) -> Vec<Download> {
         log::info!("Getting sorted downloads");
-        db::get_sorted_downloads().await.unwrap_or(vec![])
+        self.find_downloads(&DownloadFilter::default(), SortKey::Status, false, 0, 0)
+            .await
+            .map(|(downloads, _)| downloads)
+            .unwrap_or(vec![])
+    }
+
+    /// Lists the downloads matching a filter, page by page
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The statuses, category, host, date and size ranges and text to match
+    /// * `sort` - One of `date_added`, `date_completed`, `size`, `url`, `status`, `relevance`
+    ///   and `queue`
+    /// * `descending` - Whether the order is reversed
+    /// * `offset` - The count of matching downloads to skip
+    /// * `limit` - The maximum count of downloads to return, all if 0
+    ///
+    /// # Returns
+    ///
+    /// * `(Vec<Download>, u32)` - The page of downloads and the total count of matching downloads
+    async fn list_downloads(
+        &self,
+        filter: DownloadFilter,
+        sort: &str,
+        descending: bool,
+        offset: u32,
+        limit: u32,
+    ) -> fdo::Result<(Vec<Download>, u32)> {
+        log::info!("Listing downloads sorted by: {}", sort);
+        let sort = SortKey::from_string(sort)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Invalid sort key: {}", sort)))?;
+        self.find_downloads(&filter, sort, descending, offset as u64, limit as u64)
+            .await
+            .map(|(downloads, total)| (downloads, total as u32))
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn search_downloads(
+        &self,
+        query: &str,
+        filters: DownloadFilter,
+        offset: u32,
+        limit: u32,
+    ) -> fdo::Result<(Vec<Download>, u32)> {
+        log::info!("Searching downloads: {}", query);
+        let filter = DownloadFilter {
+            text: Some(query.to_string()),
+            ..filters
+        };
+        self.find_downloads(
+            &filter,
+            SortKey::Relevance,
+            false,
+            offset as u64,
+            limit as u64,
+        )
+        .await
+        .map(|(downloads, total)| (downloads, total as u32))
+        .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn get_download_tags(&self, id: i64) -> fdo::Result<Vec<String>> {
+        log::info!("Getting tags of download with id: {}", id);
+        self.store
+            .get_download_tags(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn set_download_tags(&self, id: i64, tags: Vec<String>) -> &str {
+        log::info!("Setting tags of download with id: {}", id);
+        let tags = tags
+            .iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect::<Vec<String>>();
+        match self.store.set_download_tags(id, &tags).await {
+            Ok(_) => "OK",
+            Err(e) => {
+                log::error!("Error setting download tags: {}", e);
+                "ERROR"
+            }
+        }
+    }
+
+    async fn add_download_tag(&self, id: i64, tag: &str) -> &str {
+        log::info!("Adding tag {} to download with id: {}", tag, id);
+        if tag.trim().is_empty() {
+            return "ERROR";
+        }
+        match self.store.add_download_tag(id, tag.trim()).await {
+            Ok(_) => "OK",
+            Err(e) => {
+                log::error!("Error adding download tag: {}", e);
+                "ERROR"
+            }
+        }
+    }
+
+    async fn remove_download_tag(&self, id: i64, tag: &str) -> &str {
+        log::info!("Removing tag {} from download with id: {}", tag, id);
+        match self.store.remove_download_tag(id, tag.trim()).await {
+            Ok(_) => "OK",
+            Err(e) => {
+                log::error!("Error removing download tag: {}", e);
+                "ERROR"
+            }
+        }
+    }
+
+    async fn get_download_note(&self, id: i64) -> fdo::Result<String> {
+        log::info!("Getting note of download with id: {}", id);
+        self.store
+            .get_download_note(id)
+            .await
+            .map(Option::unwrap_or_default)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn set_download_note(&self, id: i64, note: &str) -> &str {
+        log::info!("Setting note of download with id: {}", id);
+        match self.store.set_download_note(id, note.trim()).await {
+            Ok(_) => "OK",
+            Err(e) => {
+                log::error!("Error setting download note: {}", e);
+                "ERROR"
+            }
+        }
+    }
+
+    async fn get_progress(&self, id: i64) -> fdo::Result<ProgressInfo> {
+        if let Some(progress) = self.progress_tracker.get(id).await {
+            return Ok(progress);
+        }
+
+        // The download is not running, report what is on disk
+        let download = self
+            .store
+            .get_download_by_id(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        let downloaded = match download.status {
+            DownloadStatus::Completed => download.size.unwrap_or(download.downloaded_bytes),
+            _ => download.downloaded_bytes,
+        };
+        Ok(ProgressInfo {
+            id,
+            downloaded,
+            size: download.size.unwrap_or(0),
+            eta: -1,
+            ..Default::default()
+        })
+    }
+
+    async fn get_speed_samples(&self, id: i64) -> fdo::Result<Vec<SpeedSample>> {
+        log::info!("Getting speed samples of download with id: {}", id);
+        self.store
+            .get_speed_samples(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn get_bandwidth_usage(&self, from: i64, to: i64) -> fdo::Result<Vec<BandwidthUsage>> {
+        log::info!("Getting bandwidth usage from {} to {}", from, to);
+        self.store
+            .get_bandwidth_usage(from, to)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn explain_download_category(&self, id: i64) -> fdo::Result<CategoryMatch> {
+        log::info!("Explaining category of download with id: {}", id);
+        let download = self
+            .store
+            .get_download_by_id(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        let config = config::get_config().await;
+        Ok(category::explain_download_category(&download, &config))
     }
 
     async fn new_download_wait_confirm(&self, url: &str) -> &str {
@@ -123,6 +571,54 @@ impl FlowListener {
         }
     }
 
+    /// Adds a confirmed download and returns its id
+    ///
+    /// # Arguments
+    ///
+    /// * `output_file_name` - The name of the file, detected from the response if empty
+    /// * `directory` - Where the file is saved, the directory of its category if empty
+    async fn add_download(
+        &self,
+        url: &str,
+        output_file_name: &str,
+        directory: &str,
+    ) -> fdo::Result<i64> {
+        log::info!("Adding download: {}", url);
+        let non_empty = |value: &str| Some(value.to_string()).filter(|value| !value.is_empty());
+        let entry = ImportEntry {
+            url: url.to_string(),
+            out: non_empty(output_file_name),
+            dir: non_empty(directory),
+        };
+        import::add_download(&*self.store, entry)
+            .await
+            .map_err(|e| match e {
+                ImportError::InvalidUrl(_) | ImportError::InvalidFileName(_) => {
+                    fdo::Error::InvalidArgs(e.to_string())
+                }
+                e => fdo::Error::Failed(e.to_string()),
+            })
+    }
+
+    async fn get_download(&self, id: i64) -> fdo::Result<Download> {
+        log::info!("Getting download with id: {}", id);
+        self.store
+            .get_download_by_id(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn import_downloads(
+        &self,
+        source: &str,
+        options: ImportOptions,
+    ) -> fdo::Result<Vec<i64>> {
+        log::info!("Importing downloads from: {}", source);
+        import::import_downloads(&*self.store, source, &options)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
     async fn pause_download(&self, id: i64) -> &str {
         log::info!("Pausing download with id: {}", id);
         match self.events_tx.send(DownloadEvent::PauseDownload(id)) {
@@ -178,28 +674,343 @@ impl FlowListener {
         }
     }
 
+    /// Moves a pending download in the queue
+    ///
+    /// # Arguments
+    ///
+    /// * `steps` - The count of pending downloads it passes, toward the start if negative
+    async fn move_download(&self, id: i64, steps: i32) -> fdo::Result<()> {
+        log::info!("Moving download with id {} by {} in the queue", id, steps);
+        let moved = queue::move_download(&*self.store, id, steps.into())
+            .await
+            .map_err(|e| match e {
+                QueueError::NotPending(_) => fdo::Error::InvalidArgs(e.to_string()),
+                e => fdo::Error::Failed(e.to_string()),
+            })?;
+        for download in moved {
+            if let Err(e) = self.events_tx.send(DownloadEvent::DownloadUpdate(download)) {
+                log::error!("Error sending download update event: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn bulk_action(&self, action: &str, ids: Vec<i64>) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Applying {} to downloads: {:?}", action, ids);
+        let action = BulkAction::from_string(action)
+            .ok_or(fdo::Error::InvalidArgs(format!("Invalid action: {}", action)))?;
+
+        let mut results = vec![];
+        let mut downloads = vec![];
+        for id in ids {
+            match self.store.get_download_by_id(id).await {
+                Ok(download) => downloads.push(download),
+                Err(e) => results.push(BulkResult::error(id, &e.to_string())),
+            }
+        }
+        results.extend(self.send_bulk_action(action, downloads, true).await?);
+        Ok(results)
+    }
+
+    async fn bulk_action_filtered(
+        &self,
+        action: &str,
+        filter: DownloadFilter,
+    ) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Applying {} to downloads matching: {:?}", action, filter);
+        let action = BulkAction::from_string(action)
+            .ok_or(fdo::Error::InvalidArgs(format!("Invalid action: {}", action)))?;
+        self.apply_to_filtered(action, &filter).await
+    }
+
+    async fn pause_all(&self) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Pausing all downloads");
+        self.apply_to_filtered(BulkAction::Pause, &DownloadFilter::default())
+            .await
+    }
+
+    async fn resume_all(&self) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Resuming all downloads");
+        let paused = self
+            .store
+            .get_all_downloads()
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?
+            .into_iter()
+            .filter(|download| download.status.is_resumable())
+            .collect();
+        self.send_bulk_action(BulkAction::Resume, paused, false)
+            .await
+    }
+
+    async fn cancel_all(&self) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Cancelling all downloads");
+        self.apply_to_filtered(BulkAction::Cancel, &DownloadFilter::default())
+            .await
+    }
+
+    async fn retry_failed(&self) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Retrying failed downloads");
+        let failed = self
+            .store
+            .get_all_downloads()
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?
+            .into_iter()
+            .filter(|download| download.status.is_failed())
+            .collect();
+        self.send_bulk_action(BulkAction::Retry, failed, false)
+            .await
+    }
+
+    async fn clear_completed(&self) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Clearing completed downloads");
+        let filter = DownloadFilter {
+            status: Some(DownloadStatus::Completed.get_string().to_string()),
+            ..Default::default()
+        };
+        self.apply_to_filtered(BulkAction::Delete, &filter).await
+    }
+
+    async fn create_group(&self, name: &str, directory: &str) -> fdo::Result<i64> {
+        log::info!("Creating group: {}", name);
+        let directory = Some(directory)
+            .filter(|directory| !directory.is_empty())
+            .map(utils::path::expand);
+        self.store
+            .new_group(&DownloadGroup::new(name, directory))
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn get_all_groups(&self) -> fdo::Result<Vec<DownloadGroup>> {
+        log::info!("Getting all groups");
+        group::get_all_groups(&*self.store)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn get_group(&self, id: i64) -> fdo::Result<DownloadGroup> {
+        log::info!("Getting group with id: {}", id);
+        group::get_group(&*self.store, id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn get_group_downloads(&self, id: i64) -> fdo::Result<Vec<Download>> {
+        log::info!("Getting downloads of group with id: {}", id);
+        self.store
+            .get_group_downloads(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn add_to_group(&self, group_id: i64, ids: Vec<i64>) -> &str {
+        log::info!("Adding downloads {:?} to group with id: {}", ids, group_id);
+        if let Err(e) = self.store.get_group_by_id(group_id).await {
+            log::error!("{e}");
+            return "ERROR";
+        }
+        let previous_groups = self.get_groups_of(&ids).await;
+        match self
+            .store
+            .change_downloads_group(&ids, Some(group_id))
+            .await
+        {
+            Ok(_) => {
+                for previous_group in previous_groups {
+                    self.send_group_update(previous_group).await;
+                }
+                self.send_group_update(group_id).await;
+                "OK"
+            }
+            Err(e) => {
+                log::error!("Error adding downloads to group: {}", e);
+                "ERROR"
+            }
+        }
+    }
+
+    async fn remove_from_group(&self, ids: Vec<i64>) -> &str {
+        log::info!("Removing downloads {:?} from their group", ids);
+        let previous_groups = self.get_groups_of(&ids).await;
+        match self.store.change_downloads_group(&ids, None).await {
+            Ok(_) => {
+                for previous_group in previous_groups {
+                    self.send_group_update(previous_group).await;
+                }
+                "OK"
+            }
+            Err(e) => {
+                log::error!("Error removing downloads from group: {}", e);
+                "ERROR"
+            }
+        }
+    }
+
+    async fn pause_group(&self, id: i64) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Pausing group with id: {}", id);
+        self.apply_to_group(BulkAction::Pause, id).await
+    }
+
+    async fn resume_group(&self, id: i64) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Resuming group with id: {}", id);
+        self.apply_to_group(BulkAction::Resume, id).await
+    }
+
+    async fn cancel_group(&self, id: i64) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Cancelling group with id: {}", id);
+        self.apply_to_group(BulkAction::Cancel, id).await
+    }
+
+    async fn delete_group(&self, id: i64) -> fdo::Result<Vec<BulkResult>> {
+        log::info!("Deleting group with id: {}", id);
+        let downloads = self
+            .store
+            .get_group_downloads(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        if downloads
+            .iter()
+            .any(|download| !BulkAction::Delete.applies_to(&download.status))
+        {
+            return Err(fdo::Error::Failed(format!(
+                "Group #{} has downloads in progress",
+                id
+            )));
+        }
+        self.store
+            .delete_group(id)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        self.send_bulk_action(BulkAction::Delete, downloads, false)
+            .await
+    }
+
     async fn change_output_file_path(&self, id: i64, new_path: &str) -> &str {
         log::info!("Changing output file path for download with id: {}", id);
-        let _ = db::change_download_output_file_path(id, new_path)
+        let _ = self
+            .set_output_file_path(id, new_path)
             .await
-            .map_err(|e| {
+            .inspect_err(|_| {
                 log::error!(
                     "Error changing output file path for download with id: {}",
                     id
                 );
-                e
             });
         "OK"
     }
 
+    async fn change_conflict_policy(&self, id: i64, policy: &str) -> &str {
+        log::info!("Changing conflict policy for download with id: {}", id);
+        let Some(policy) = ConflictPolicy::from_string(policy) else {
+            log::error!("Invalid conflict policy: {}", policy);
+            return "ERROR";
+        };
+        match self.store.change_download_conflict_policy(id, policy).await {
+            Ok(_) => "OK",
+            Err(err) => {
+                log::error!(
+                    "Error changing conflict policy for download with id {}: {}",
+                    id,
+                    err
+                );
+                "ERROR"
+            }
+        }
+    }
+
+    async fn resolve_download_conflict(&self, id: i64, policy: &str) -> &str {
+        log::info!("Resolving conflict for download with id: {}", id);
+        let policy = match ConflictPolicy::from_string(policy) {
+            Some(ConflictPolicy::Ask) | None => {
+                log::error!("Invalid conflict resolution: {}", policy);
+                return "ERROR";
+            }
+            Some(policy) => policy,
+        };
+        match self
+            .events_tx
+            .send(DownloadEvent::ResolveConflict(id, policy))
+        {
+            Ok(_) => "OK",
+            Err(err) => {
+                log::error!("Error sending resolve conflict event: {}", err);
+                "ERROR"
+            }
+        }
+    }
+
+    /// Returns the settings of the current config, categories are a dict of dicts
+    async fn get_config(&self) -> HashMap<String, OwnedValue> {
+        log::info!("Getting config");
+        values::to_dbus_dict(&config::get_config().await.to_table())
+    }
+
+    /// Returns the settings of the config files that were skipped because they are invalid
+    async fn get_config_problems(&self) -> Vec<ConfigProblem> {
+        log::info!("Getting config problems");
+        config::get_config_problems()
+    }
+
+    /// Sets a setting in the user config, nothing is written if the value is invalid
+    async fn set_config_value(&self, key: &str, value: OwnedValue) -> fdo::Result<()> {
+        log::info!("Setting config value: {}", key);
+        let value = values::to_config_value(&value).map_err(fdo::Error::InvalidArgs)?;
+        let _config_guard = self.config_lock.lock().await;
+        let mut editor = ConfigEditor::open().await.map_err(to_dbus_error)?;
+        editor
+            .set_value(key, value)
+            .map_err(fdo::Error::InvalidArgs)?;
+        self.save_config(&editor).await
+    }
+
+    /// Adds a category to the user config or replaces the category with the same name
+    async fn add_category(
+        &self,
+        name: &str,
+        properties: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<()> {
+        log::info!("Adding category: {}", name);
+        let category = values::to_config_table(&properties).map_err(fdo::Error::InvalidArgs)?;
+        let _config_guard = self.config_lock.lock().await;
+        let config = config::get_config().await;
+        let mut editor = ConfigEditor::open().await.map_err(to_dbus_error)?;
+        editor
+            .set_category(name, category, &config.categories)
+            .map_err(fdo::Error::InvalidArgs)?;
+        self.save_config(&editor).await
+    }
+
+    async fn remove_category(&self, name: &str) -> fdo::Result<()> {
+        log::info!("Removing category: {}", name);
+        let _config_guard = self.config_lock.lock().await;
+        let config = config::get_config().await;
+        let mut editor = ConfigEditor::open().await.map_err(to_dbus_error)?;
+        editor
+            .remove_category(name, &config.categories)
+            .map_err(fdo::Error::InvalidArgs)?;
+        self.save_config(&editor).await
+    }
+
+    /// Moves the user config aside so that only the default and system configs apply
+    async fn reset_to_default(&self) -> fdo::Result<()> {
+        log::info!("Resetting config to default");
+        let _config_guard = self.config_lock.lock().await;
+        let changed_keys = config::reset_user_config()
+            .await
+            .map_err(to_dbus_error)?;
+        self.send_config_changed(changed_keys);
+        Ok(())
+    }
+
     async fn confirm_download_data(&self, id: i64) -> &str {
         log::info!("Confirming download data for download with id: {}", id);
-        let _ = db::confirm_download_data(id).await.map_err(|e| {
+        let _ = self.store.confirm_download_data(id).await.inspect_err(|_| {
             log::error!(
                 "Error confirming download data for download with id: {}",
                 id
             );
-            e
         });
         "OK"
     }
@@ -212,14 +1023,58 @@ impl FlowListener {
     async fn notify_download_update(ctx: &SignalContext<'_>, download_info: Download)
         -> Result<()>;
 
+    #[zbus(signal)]
+    async fn notify_group_update(ctx: &SignalContext<'_>, group: DownloadGroup) -> Result<()>;
+
     #[zbus(signal)]
     async fn notify_download_delete(ctx: &SignalContext<'_>, download_id: i64) -> Result<()>;
 
     #[zbus(signal)]
+    async fn notify_download_conflict(
+        ctx: &SignalContext<'_>,
+        id: i64,
+        existing_file: &str,
+    ) -> Result<()>;
+
+    #[zbus(signal)]
+    #[allow(clippy::too_many_arguments)]
     async fn notify_download_progress(
         ctx: &SignalContext<'_>,
         id: i64,
         progress: u64,
         content_length: u64,
+        speed: u64,
+        average_speed: u64,
+        eta: i64,
+        elapsed: u64,
     ) -> Result<()>;
+
+    #[zbus(signal)]
+    async fn notify_global_progress(
+        ctx: &SignalContext<'_>,
+        total_speed: u64,
+        active_count: u32,
+    ) -> Result<()>;
+
+    #[zbus(signal)]
+    async fn notify_move_progress(
+        ctx: &SignalContext<'_>,
+        id: i64,
+        copied: u64,
+        total: u64,
+    ) -> Result<()>;
+
+    /// Emitted after the config files changed, with the names of the changed settings
+    #[zbus(signal)]
+    async fn config_changed(ctx: &SignalContext<'_>, changed_keys: Vec<String>) -> Result<()>;
+}
+
+/// Invalid settings are the fault of the client, the other errors are not
+fn to_dbus_error(error: ConfigError) -> fdo::Error {
+    match error {
+        ConfigError::Problem(..) => fdo::Error::InvalidArgs(error.to_string()),
+        ConfigError::ReadError(..) | ConfigError::WriteError(..) => {
+            fdo::Error::Failed(error.to_string())
+        }
+    }
 }
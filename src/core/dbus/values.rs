@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Converts a config table to a dict, tables become nested dicts
+pub fn to_dbus_dict(table: &toml::Table) -> HashMap<String, OwnedValue> {
+    table
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = OwnedValue::try_from(to_dbus_value(value)).ok()?;
+            Some((key.clone(), value))
+        })
+        .collect()
+}
+
+/// Converts a config value, see `to_dbus_dict`
+pub fn to_dbus_value(value: &toml::Value) -> Value<'static> {
+    match value {
+        toml::Value::String(value) => Value::from(value.clone()),
+        toml::Value::Integer(value) => Value::from(*value),
+        toml::Value::Float(value) => Value::from(*value),
+        toml::Value::Boolean(value) => Value::from(*value),
+        toml::Value::Datetime(value) => Value::from(value.to_string()),
+        // Lists of strings keep their type so that clients do not have to unwrap each item
+        toml::Value::Array(values) => match values
+            .iter()
+            .map(|value| value.as_str().map(str::to_string))
+            .collect::<Option<Vec<String>>>()
+        {
+            Some(strings) => Value::from(strings),
+            None => Value::from(values.iter().map(to_dbus_value).collect::<Vec<Value>>()),
+        },
+        toml::Value::Table(table) => Value::from(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), to_dbus_value(value)))
+                .collect::<HashMap<String, Value>>(),
+        ),
+    }
+}
+
+/// Converts a dict returned by `GetConfig` back to a config table, sorted by key
+pub fn to_toml_table(dict: &HashMap<String, OwnedValue>) -> toml::Table {
+    let mut keys = dict.keys().collect::<Vec<&String>>();
+    keys.sort();
+    keys.into_iter()
+        .filter_map(|key| Some((key.clone(), to_toml_value(&dict[key])?)))
+        .collect()
+}
+
+/// Converts a value of a dict returned by `GetConfig` back to a config value
+///
+/// # Returns
+///
+/// * `Option<toml::Value>` - The value, `None` for types that no setting has
+pub fn to_toml_value(value: &Value) -> Option<toml::Value> {
+    let value = match value {
+        Value::Value(value) => return to_toml_value(value),
+        Value::Str(value) => toml::Value::from(value.as_str()),
+        Value::Bool(value) => toml::Value::from(*value),
+        Value::U8(value) => toml::Value::from(i64::from(*value)),
+        Value::I16(value) => toml::Value::from(i64::from(*value)),
+        Value::U16(value) => toml::Value::from(i64::from(*value)),
+        Value::I32(value) => toml::Value::from(i64::from(*value)),
+        Value::U32(value) => toml::Value::from(i64::from(*value)),
+        Value::I64(value) => toml::Value::from(*value),
+        Value::U64(value) => toml::Value::from(i64::try_from(*value).ok()?),
+        Value::F64(value) => toml::Value::from(*value),
+        Value::Array(values) => toml::Value::Array(
+            values
+                .iter()
+                .map(to_toml_value)
+                .collect::<Option<toml::value::Array>>()?,
+        ),
+        Value::Dict(dict) => {
+            let dict = HashMap::<String, OwnedValue>::try_from(dict.try_clone().ok()?).ok()?;
+            toml::Value::Table(to_toml_table(&dict))
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Converts a value received from a client to a config value
+///
+/// # Returns
+///
+/// * `Result<toml_edit::Value, String>` - The value, an error for dicts and other types that
+///   no setting has
+pub fn to_config_value(value: &Value) -> Result<toml_edit::Value, String> {
+    let value = match value {
+        Value::Value(value) => return to_config_value(value),
+        Value::Str(value) => toml_edit::Value::from(value.as_str()),
+        Value::Bool(value) => toml_edit::Value::from(*value),
+        Value::U8(value) => toml_edit::Value::from(i64::from(*value)),
+        Value::I16(value) => toml_edit::Value::from(i64::from(*value)),
+        Value::U16(value) => toml_edit::Value::from(i64::from(*value)),
+        Value::I32(value) => toml_edit::Value::from(i64::from(*value)),
+        Value::U32(value) => toml_edit::Value::from(i64::from(*value)),
+        Value::I64(value) => toml_edit::Value::from(*value),
+        Value::U64(value) => toml_edit::Value::from(
+            i64::try_from(*value).map_err(|_| format!("{} is too large", value))?,
+        ),
+        Value::F64(value) => toml_edit::Value::from(*value),
+        Value::Array(values) => toml_edit::Value::Array(
+            values
+                .iter()
+                .map(to_config_value)
+                .collect::<Result<toml_edit::Array, String>>()?,
+        ),
+        value => {
+            return Err(format!(
+                "Unsupported value type: {}",
+                value.value_signature()
+            ))
+        }
+    };
+    Ok(value)
+}
+
+/// Converts the properties of a category received from a client to a config table
+pub fn to_config_table(
+    properties: &HashMap<String, OwnedValue>,
+) -> Result<toml_edit::Table, String> {
+    let mut table = toml_edit::Table::new();
+    let mut keys = properties.keys().collect::<Vec<&String>>();
+    keys.sort();
+    for key in keys {
+        let value = to_config_value(&properties[key]).map_err(|e| format!("{}: {}", key, e))?;
+        table.insert(key, toml_edit::Item::Value(value));
+    }
+    Ok(table)
+}
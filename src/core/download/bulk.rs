@@ -0,0 +1,134 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
+
+use super::{DownloadEvent, DownloadStatus};
+
+/// Operation applied to many downloads with a single event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BulkAction {
+    Pause,
+    Resume,
+    Retry,
+    Restart,
+    Cancel,
+    Delete,
+}
+
+impl BulkAction {
+    pub fn get_string(&self) -> &str {
+        match self {
+            BulkAction::Pause => "pause",
+            BulkAction::Resume => "resume",
+            BulkAction::Retry => "retry",
+            BulkAction::Restart => "restart",
+            BulkAction::Cancel => "cancel",
+            BulkAction::Delete => "delete",
+        }
+    }
+
+    pub fn from_string(value: &str) -> Option<BulkAction> {
+        match value {
+            "pause" => Some(BulkAction::Pause),
+            "resume" => Some(BulkAction::Resume),
+            "retry" => Some(BulkAction::Retry),
+            "restart" => Some(BulkAction::Restart),
+            "cancel" => Some(BulkAction::Cancel),
+            "delete" => Some(BulkAction::Delete),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the downloader acts on a download with this status
+    pub fn applies_to(&self, status: &DownloadStatus) -> bool {
+        match self {
+            BulkAction::Pause => matches!(
+                status,
+                DownloadStatus::Pending
+                    | DownloadStatus::Starting
+                    | DownloadStatus::InProgress
+                    | DownloadStatus::Retrying
+            ),
+            BulkAction::Resume => status.is_resumable(),
+            BulkAction::Retry => status.is_failed(),
+            BulkAction::Restart => status.is_idle(),
+            BulkAction::Cancel => {
+                !matches!(status, DownloadStatus::Completed | DownloadStatus::Canceled)
+            }
+            BulkAction::Delete => status.is_idle() || matches!(status, DownloadStatus::Completed),
+        }
+    }
+
+    /// Returns the event applying the action to a single download
+    pub fn get_event(&self, download_id: i64) -> DownloadEvent {
+        match self {
+            BulkAction::Pause => DownloadEvent::PauseDownload(download_id),
+            BulkAction::Resume => DownloadEvent::ResumeDownload(download_id),
+            BulkAction::Retry => DownloadEvent::RetryDownload(download_id),
+            BulkAction::Restart => DownloadEvent::RestartDownload(download_id),
+            BulkAction::Cancel => DownloadEvent::CancelDownload(download_id),
+            BulkAction::Delete => DownloadEvent::DeleteDownload(download_id),
+        }
+    }
+}
+
+/// Outcome of a bulk action for one download
+#[derive(Debug, Clone, PartialEq, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct BulkResult {
+    pub id: i64,
+    /// `OK` once the downloader applied the action, or `ERROR`
+    pub result: String,
+    /// Why the action was refused, empty on success
+    pub error: String,
+}
+
+impl BulkResult {
+    pub fn ok(id: i64) -> BulkResult {
+        BulkResult {
+            id,
+            result: "OK".to_string(),
+            error: String::new(),
+        }
+    }
+
+    pub fn error(id: i64, error: &str) -> BulkResult {
+        BulkResult {
+            id,
+            result: "ERROR".to_string(),
+            error: error.to_string(),
+        }
+    }
+
+    /// Refuses the action for a download whose status it does not apply to
+    pub fn skipped(id: i64, action: BulkAction, status: &DownloadStatus) -> BulkResult {
+        BulkResult::error(
+            id,
+            &format!(
+                "Cannot {} a download with status: {}",
+                action.get_string(),
+                status.get_description()
+            ),
+        )
+    }
+}
+
+/// Where the downloader sends the results of a bulk action, shared by the clones of the event
+/// so that only the first one is sent
+#[derive(Debug, Clone, Default)]
+pub struct BulkReply(Arc<Mutex<Option<oneshot::Sender<Vec<BulkResult>>>>>);
+
+impl BulkReply {
+    pub fn new() -> (BulkReply, oneshot::Receiver<Vec<BulkResult>>) {
+        let (sender, receiver) = oneshot::channel();
+        (BulkReply(Arc::new(Mutex::new(Some(sender)))), receiver)
+    }
+
+    /// Sends the results, nothing happens if nobody waits for them
+    pub fn send(&self, results: Vec<BulkResult>) {
+        if let Some(sender) = self.0.lock().unwrap().take() {
+            _ = sender.send(results);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
+
+use super::{utils, Download};
+
+/// Selects downloads by their properties, unset fields match everything
+#[derive(Debug, Clone, Default, PartialEq, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct DownloadFilter {
+    /// Status as returned by `DownloadStatus::get_string`
+    pub status: Option<String>,
+    /// Statuses the download may have, any of them matches
+    pub statuses: Option<Vec<String>>,
+    /// Name of the category the download is sorted in
+    pub category: Option<String>,
+    /// Host of the download URL
+    pub host: Option<String>,
+    /// Unix timestamps bounding the date the download was added, both included
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    /// Bounds of the file size in bytes, downloads of unknown size are left out
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Words searched in the URL, file name, tags and note
+    pub text: Option<String>,
+}
+
+impl DownloadFilter {
+    /// Checks the download against the filter without the database, so the `text`
+    /// field is not checked
+    pub fn matches(&self, download: &Download) -> bool {
+        let status = download.status.get_string();
+        if self.status.as_ref().is_some_and(|s| s != status) {
+            return false;
+        }
+        if self
+            .statuses
+            .as_ref()
+            .is_some_and(|statuses| !statuses.iter().any(|s| s == status))
+        {
+            return false;
+        }
+        if self
+            .host
+            .as_ref()
+            .is_some_and(|host| !host.eq_ignore_ascii_case(&utils::get_url_host(&download.url)))
+        {
+            return false;
+        }
+        if self.date_from.is_some_and(|date| download.date_added < date)
+            || self.date_to.is_some_and(|date| download.date_added > date)
+        {
+            return false;
+        }
+        if self.min_size.is_some() || self.max_size.is_some() {
+            let Some(size) = download.size else {
+                return false;
+            };
+            if self.min_size.is_some_and(|min| size < min)
+                || self.max_size.is_some_and(|max| size > max)
+            {
+                return false;
+            }
+        }
+        self.category
+            .as_ref()
+            .is_none_or(|category| *category == download.category)
+    }
+}
+
+/// Order of listed downloads
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortKey {
+    #[default]
+    DateAdded,
+    DateCompleted,
+    Size,
+    Url,
+    /// Running downloads first, then waiting, stopped, failed, canceled and completed ones
+    Status,
+    /// Best matches of the text filter first, the direction is ignored
+    Relevance,
+    /// Order in which pending downloads start
+    Queue,
+}
+
+impl SortKey {
+    pub fn get_string(&self) -> &str {
+        match self {
+            SortKey::DateAdded => "date_added",
+            SortKey::DateCompleted => "date_completed",
+            SortKey::Size => "size",
+            SortKey::Url => "url",
+            SortKey::Status => "status",
+            SortKey::Relevance => "relevance",
+            SortKey::Queue => "queue",
+        }
+    }
+
+    pub fn from_string(value: &str) -> Option<SortKey> {
+        match value {
+            "date_added" => Some(SortKey::DateAdded),
+            "date_completed" => Some(SortKey::DateCompleted),
+            "size" => Some(SortKey::Size),
+            "url" => Some(SortKey::Url),
+            "status" => Some(SortKey::Status),
+            "relevance" => Some(SortKey::Relevance),
+            "queue" => Some(SortKey::Queue),
+            _ => None,
+        }
+    }
+}
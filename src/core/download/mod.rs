@@ -1,30 +1,48 @@
-use chrono::Local;
+use chrono::{DateTime, Local};
 use log;
-use reqwest::header::{HeaderMap, RANGE};
+use reqwest::header::{HeaderMap, HeaderValue, IF_RANGE, RANGE};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 use thiserror::Error;
 use tokio::fs::{self, OpenOptions};
-use tokio::io::AsyncWriteExt;
-use tokio::sync::broadcast::error::SendError;
+use tokio::io::{self, AsyncWriteExt};
+use tokio::sync::broadcast::error::{RecvError, SendError};
 use tokio::sync::broadcast::{Receiver, Sender};
 use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration, Instant};
+use tokio::time::{sleep, timeout, Duration, Instant};
 use zbus::fdo;
 use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
 
-use super::config::{self, Config};
-use super::db::{self, DBError};
+use super::category;
+use super::config::{self, Config, ConflictPolicy};
+use super::db::DBError;
+use super::group::{self, DownloadGroup};
+use super::store::DownloadStore;
+use crate::utils::fs as fs_utils;
 
+mod bulk;
+mod filter;
+mod progress;
 mod utils;
 
+pub use bulk::{BulkAction, BulkReply, BulkResult};
+pub use filter::{DownloadFilter, SortKey};
+pub use progress::{BandwidthUsage, ProgressInfo, ProgressTracker, SpeedSample};
+use progress::{LowSpeedDetector, SpeedLimiter, SpeedMeter};
+use utils::ResumeCheck;
+
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests;
 
-#[derive(Debug, Clone, Type, SerializeDict, DeserializeDict)]
+#[derive(Debug, Clone, Default, Type, SerializeDict, DeserializeDict)]
 #[zvariant(signature = "dict")]
 pub struct Download {
     pub id: i64,
@@ -33,16 +51,32 @@ pub struct Download {
     pub data_confirmed: bool,
     pub detected_output_file: Option<String>,
     pub output_file: Option<String>,
+    /// Overrides the category directory, the file keeps its detected name
+    pub output_directory: Option<String>,
     pub temp_file: String,
     pub resumable: bool,
     pub date_added: i64,
     pub date_completed: Option<i64>,
     pub size: Option<u64>,
+    /// Overrides the conflict policy from config
+    pub conflict_policy: Option<ConflictPolicy>,
+    /// Validators of the remote file used to check it did not change when resuming
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Bytes written to the temp file, saved periodically while downloading
+    pub downloaded_bytes: u64,
+    pub group_id: Option<i64>,
+    /// Name of the category the download is sorted in, empty for the default directory
+    pub category: String,
+    /// Pending downloads with a lower position start first, set by the store when added
+    pub queue_position: i64,
+    /// Content type sent by the server, used to re-evaluate the category
+    pub content_type: Option<String>,
 }
 
 impl Download {
-    async fn get_download_from_url(url: String, config: &Config) -> Download {
-        Download {
+    pub(crate) async fn get_download_from_url(url: String, config: &Config) -> Download {
+        let mut download = Download {
             id: 0,
             url,
             status: DownloadStatus::Pending,
@@ -50,15 +84,27 @@ impl Download {
             temp_file: utils::get_temp_file(config).await,
             detected_output_file: None,
             output_file: None,
+            output_directory: None,
             resumable: false,
             date_added: Local::now().timestamp(),
             date_completed: None,
             size: None,
-        }
+            conflict_policy: None,
+            etag: None,
+            last_modified: None,
+            downloaded_bytes: 0,
+            group_id: None,
+            category: String::new(),
+            queue_position: 0,
+            content_type: None,
+        };
+        // Guessed from the URL until the output file is detected
+        download.category = category::explain_download_category(&download, config).category;
+        download
     }
 
-    async fn refresh_data_from_db(&mut self) {
-        let download = db::get_download_by_id(self.id).await;
+    async fn refresh_data_from_db(&mut self, store: &dyn DownloadStore) {
+        let download = store.get_download_by_id(self.id).await;
 
         if let Err(e) = download {
             log::error!("Download #{}: {}", self.id, e);
@@ -72,38 +118,45 @@ impl Download {
         self.data_confirmed = download.data_confirmed;
         self.detected_output_file = download.detected_output_file;
         self.output_file = download.output_file;
+        self.output_directory = download.output_directory;
         self.temp_file = download.temp_file;
         self.resumable = download.resumable;
         self.date_added = download.date_added;
         self.date_completed = download.date_completed;
         self.size = download.size;
+        self.conflict_policy = download.conflict_policy;
+        self.etag = download.etag;
+        self.last_modified = download.last_modified;
+        self.downloaded_bytes = download.downloaded_bytes;
+        self.group_id = download.group_id;
+        self.category = download.category;
+        self.queue_position = download.queue_position;
+        self.content_type = download.content_type;
     }
 
-    async fn change_download_status(&mut self, new_status: DownloadStatus) -> Result<(), DBError> {
+    async fn change_download_status(
+        &mut self,
+        store: &dyn DownloadStore,
+        new_status: DownloadStatus,
+    ) -> Result<(), DBError> {
         self.status = new_status;
-        db::change_download_status(&self.id, &self.status).await?;
+        store.change_download_status(self.id, &self.status).await?;
         Ok(())
     }
 
-    async fn sync_to_db(&self) -> Result<(), DBError> {
-        db::update_download(self).await?;
+    async fn sync_to_db(&self, store: &dyn DownloadStore) -> Result<(), DBError> {
+        store.update_download(self).await?;
         Ok(())
     }
 
     fn is_idle(&self) -> bool {
-        match self.status {
-            DownloadStatus::Paused
-            | DownloadStatus::Canceled
-            | DownloadStatus::ClientError
-            | DownloadStatus::ServerError
-            | DownloadStatus::UnknownError => true,
-            _ => false,
-        }
+        self.status.is_idle()
     }
 }
 
-#[derive(Debug, Clone, Type, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Type, Serialize, Deserialize)]
 pub enum DownloadStatus {
+    #[default]
     Pending,
     Starting,
     InProgress,
@@ -113,9 +166,27 @@ pub enum DownloadStatus {
     ServerError,
     ClientError,
     UnknownError,
+    AwaitingConflictResolution,
+    InsufficientSpace,
+    Retrying,
 }
 
 impl DownloadStatus {
+    pub const ALL: [DownloadStatus; 12] = [
+        DownloadStatus::Pending,
+        DownloadStatus::Starting,
+        DownloadStatus::InProgress,
+        DownloadStatus::Paused,
+        DownloadStatus::Canceled,
+        DownloadStatus::Completed,
+        DownloadStatus::ServerError,
+        DownloadStatus::ClientError,
+        DownloadStatus::UnknownError,
+        DownloadStatus::AwaitingConflictResolution,
+        DownloadStatus::InsufficientSpace,
+        DownloadStatus::Retrying,
+    ];
+
     pub fn get_description(&self) -> &str {
         match self {
             DownloadStatus::Pending => "Pending",
@@ -127,6 +198,9 @@ impl DownloadStatus {
             DownloadStatus::ServerError => "Server error",
             DownloadStatus::ClientError => "Client error",
             DownloadStatus::UnknownError => "Unknown error",
+            DownloadStatus::AwaitingConflictResolution => "Awaiting conflict resolution",
+            DownloadStatus::InsufficientSpace => "Insufficient space",
+            DownloadStatus::Retrying => "Retrying",
         }
     }
 
@@ -141,23 +215,61 @@ impl DownloadStatus {
             DownloadStatus::ServerError => "server_error",
             DownloadStatus::ClientError => "client_error",
             DownloadStatus::UnknownError => "unknown_error",
+            DownloadStatus::AwaitingConflictResolution => "awaiting_conflict_resolution",
+            DownloadStatus::InsufficientSpace => "insufficient_space",
+            DownloadStatus::Retrying => "retrying",
         }
     }
 
-    pub fn from_string(value: &str) -> DownloadStatus {
+    pub fn from_string(value: &str) -> Option<DownloadStatus> {
         match value {
-            "pending" => DownloadStatus::Pending,
-            "starting" => DownloadStatus::Starting,
-            "in_progress" => DownloadStatus::InProgress,
-            "paused" => DownloadStatus::Paused,
-            "canceled" => DownloadStatus::Canceled,
-            "completed" => DownloadStatus::Completed,
-            "server_error" => DownloadStatus::ServerError,
-            "client_error" => DownloadStatus::ClientError,
-            "unknown_error" => DownloadStatus::UnknownError,
-            _ => panic!("Invalid download status"),
+            "pending" => Some(DownloadStatus::Pending),
+            "starting" => Some(DownloadStatus::Starting),
+            "in_progress" => Some(DownloadStatus::InProgress),
+            "paused" => Some(DownloadStatus::Paused),
+            "canceled" => Some(DownloadStatus::Canceled),
+            "completed" => Some(DownloadStatus::Completed),
+            "server_error" => Some(DownloadStatus::ServerError),
+            "client_error" => Some(DownloadStatus::ClientError),
+            "unknown_error" => Some(DownloadStatus::UnknownError),
+            "awaiting_conflict_resolution" => Some(DownloadStatus::AwaitingConflictResolution),
+            "insufficient_space" => Some(DownloadStatus::InsufficientSpace),
+            "retrying" => Some(DownloadStatus::Retrying),
+            _ => None,
         }
     }
+
+    /// Returns `true` if no task is working on a download with this status
+    pub fn is_idle(&self) -> bool {
+        matches!(
+            self,
+            DownloadStatus::Paused
+                | DownloadStatus::InsufficientSpace
+                | DownloadStatus::Retrying
+                | DownloadStatus::AwaitingConflictResolution
+                | DownloadStatus::Canceled
+                | DownloadStatus::ClientError
+                | DownloadStatus::ServerError
+                | DownloadStatus::UnknownError
+        )
+    }
+
+    /// Returns `true` if a download with this status was stopped and can continue from its
+    /// downloaded bytes
+    pub fn is_resumable(&self) -> bool {
+        matches!(
+            self,
+            DownloadStatus::Paused | DownloadStatus::InsufficientSpace | DownloadStatus::Retrying
+        )
+    }
+
+    /// Returns `true` if a download with this status stopped on an error
+    pub fn is_failed(&self) -> bool {
+        matches!(
+            self,
+            DownloadStatus::ClientError | DownloadStatus::ServerError | DownloadStatus::UnknownError
+        )
+    }
 }
 
 pub struct FileInfo {
@@ -165,6 +277,31 @@ pub struct FileInfo {
     content_length: Option<u64>,
     content_type: Option<String>,
     resumable: bool,
+    /// Remote modification time as a timestamp
+    last_modified: Option<i64>,
+    etag: Option<String>,
+    last_modified_header: Option<String>,
+}
+
+/// Changes made while applying a bulk action, signaled at once when it is done so that
+/// large actions do not overflow the events channel
+#[derive(Clone, Debug, Default)]
+pub struct BulkChanges {
+    pub updated: Vec<Download>,
+    pub deleted: Vec<i64>,
+}
+
+/// Signals held while a bulk action is applied
+#[derive(Default)]
+struct HeldSignals {
+    changes: BulkChanges,
+    group_ids: Vec<i64>,
+}
+
+/// How the output file path is finalized once the conflict policy is applied
+enum ConflictResolution {
+    MoveTo(String),
+    KeepExisting(String),
 }
 
 #[derive(Clone, Debug)]
@@ -173,14 +310,24 @@ pub enum DownloadEvent {
     NewDownload(String, bool),
     PauseDownload(i64),
     ResumeDownload(i64),
+    RetryDownload(i64),
     RestartDownload(i64),
     CancelDownload(i64),
     DeleteDownload(i64),
+    ResolveConflict(i64, ConflictPolicy),
+    /// Applies an action to many downloads, the results are sent to the reply
+    Bulk(BulkAction, Vec<i64>, BulkReply),
     // Signals
-    DownloadProgress(i64, u64, u64),
+    DownloadProgress(ProgressInfo),
+    GlobalProgress(u64, u32),
     DownloadUpdate(Download),
     DownloadError(Option<i64>, String),
-    DownloadDelete(i64)
+    DownloadDelete(i64),
+    BulkUpdate(BulkChanges),
+    DownloadConflict(i64, String),
+    MoveProgress(i64, u64, u64),
+    GroupUpdate(DownloadGroup),
+    ConfigChanged(Vec<String>),
 }
 
 #[derive(Debug, Error)]
@@ -189,33 +336,87 @@ pub enum DownloaderError {
     DBError(#[from] DBError),
 
     #[error("Channel error: {0}")]
-    ChannelError(#[from] SendError<DownloadEvent>),
+    ChannelError(String),
+}
+
+impl From<SendError<DownloadEvent>> for DownloaderError {
+    fn from(error: SendError<DownloadEvent>) -> Self {
+        DownloaderError::ChannelError(error.to_string())
+    }
 }
 
 pub struct Downloader {
     pause_requests: Arc<Mutex<HashSet<i64>>>,
     cancel_requests: Arc<Mutex<HashSet<i64>>>,
     downloading: Arc<Mutex<HashSet<i64>>>,
+    /// Retries in a row that downloaded nothing since the download was last started by the user
+    retry_counts: Arc<Mutex<HashMap<i64, u32>>>,
+    /// Downloads whose file conflict was resolved, they only need to be moved when started
+    resolved_conflicts: Arc<Mutex<HashSet<i64>>>,
+    /// Set while a bulk action is applied
+    held_signals: Arc<Mutex<Option<HeldSignals>>>,
     events_tx: Sender<DownloadEvent>,
     events_rx: Arc<Mutex<Receiver<DownloadEvent>>>,
+    progress_tracker: ProgressTracker,
+    store: Arc<dyn DownloadStore>,
 }
 
 impl Downloader {
-    pub fn new(tx: Sender<DownloadEvent>, rx: Receiver<DownloadEvent>) -> Downloader {
+    pub fn new(
+        tx: Sender<DownloadEvent>,
+        rx: Receiver<DownloadEvent>,
+        store: Arc<dyn DownloadStore>,
+    ) -> Downloader {
         Downloader {
             pause_requests: Arc::new(Mutex::new(HashSet::new())),
             cancel_requests: Arc::new(Mutex::new(HashSet::new())),
             downloading: Arc::new(Mutex::new(HashSet::new())),
+            retry_counts: Arc::new(Mutex::new(HashMap::new())),
+            resolved_conflicts: Arc::new(Mutex::new(HashSet::new())),
+            held_signals: Arc::new(Mutex::new(None)),
             events_tx: tx,
             events_rx: Arc::new(Mutex::new(rx)),
+            progress_tracker: ProgressTracker::default(),
+            store,
+        }
+    }
+
+    pub fn get_progress_tracker(&self) -> ProgressTracker {
+        self.progress_tracker.clone()
+    }
+
+    /// Periodically notifies the total speed and the count of running downloads
+    pub async fn report_global_progress(&self) {
+        let mut was_active = false;
+        loop {
+            let (total_speed, active_count) = self.progress_tracker.get_global_progress().await;
+            if active_count > 0 || was_active {
+                _ = self
+                    .events_tx
+                    .send(DownloadEvent::GlobalProgress(total_speed, active_count))
+                    .map_err(|e| {
+                        log::error!("{e}");
+                    });
+            }
+            was_active = active_count > 0;
+            sleep(Duration::from_secs(1)).await;
         }
     }
 
     pub async fn listen_to_dbus_events(&self) {
-        while let Ok(event) = self.events_rx.lock().await.recv().await {
-            _ = self.handle_event(event).await.map_err(|e| {
-                log::error!("Error handling event: {}", e);
-            });
+        loop {
+            let event = self.events_rx.lock().await.recv().await;
+            match event {
+                Ok(event) => {
+                    _ = self.handle_event(event).await.map_err(|e| {
+                        log::error!("Error handling event: {}", e);
+                    });
+                }
+                Err(RecvError::Lagged(count)) => {
+                    log::warn!("Missed {} events", count);
+                }
+                Err(RecvError::Closed) => break,
+            }
         }
     }
 
@@ -224,53 +425,152 @@ impl Downloader {
             DownloadEvent::NewDownload(url, confirm) => {
                 self.new_download(url, confirm).await?;
             }
+            DownloadEvent::PauseDownload(id) if self.downloading.lock().await.contains(&id) => {
+                self.request_pause(id).await;
+            }
             DownloadEvent::PauseDownload(id) => {
-                if self.downloading.lock().await.contains(&id) {
-                    self.request_pause(id).await;
+                let mut download = self.store.get_download_by_id(id).await?;
+
+                // Hold queued downloads, cancel scheduled retries and pending conflicts
+                if matches!(
+                    download.status,
+                    DownloadStatus::Pending
+                        | DownloadStatus::Retrying
+                        | DownloadStatus::AwaitingConflictResolution
+                ) {
+                    self.pause_download(&mut download).await?;
                 }
             }
             DownloadEvent::ResumeDownload(id) => {
-                let download = db::get_download_by_id(id).await?;
+                let download = self.store.get_download_by_id(id).await?;
 
-                if let DownloadStatus::Paused = download.status {
-                    db::change_download_status(&id, &DownloadStatus::Pending).await?;
+                if download.status.is_resumable() {
+                    self.retry_counts.lock().await.remove(&id);
+                    self.store
+                        .change_download_status(id, &DownloadStatus::Pending)
+                        .await?;
+                }
+            }
+            DownloadEvent::RetryDownload(id) => {
+                let download = self.store.get_download_by_id(id).await?;
+
+                // Failed downloads continue from their downloaded bytes
+                if download.status.is_failed() {
+                    self.retry_counts.lock().await.remove(&id);
+                    self.store
+                        .change_download_status(id, &DownloadStatus::Pending)
+                        .await?;
                 }
             }
             DownloadEvent::RestartDownload(id) => {
-                let download = db::get_download_by_id(id).await?;
+                let download = self.store.get_download_by_id(id).await?;
 
                 if download.is_idle() {
+                    self.retry_counts.lock().await.remove(&id);
                     if fs::try_exists(&download.temp_file).await.unwrap_or(false) {
                         _ = utils::empty_temp_file(&download.temp_file).await;
                     }
-                    db::change_download_status(&id, &DownloadStatus::Pending).await?;
+                    self.store.change_download_downloaded_bytes(id, 0).await?;
+                    self.store
+                        .change_download_status(id, &DownloadStatus::Pending)
+                        .await?;
                 }
             }
             DownloadEvent::CancelDownload(id) => {
                 if self.downloading.lock().await.contains(&id) {
                     self.request_cancel(id).await
                 } else {
-                    let mut download = db::get_download_by_id(id).await?;
+                    let mut download = self.store.get_download_by_id(id).await?;
                     self.cancel_download(&mut download).await?;
                 }
             }
             DownloadEvent::DeleteDownload(id) => {
-                let mut download = db::get_download_by_id(id).await?;
+                let mut download = self.store.get_download_by_id(id).await?;
 
-                if download.is_idle() {
+                if download.is_idle() || matches!(download.status, DownloadStatus::Completed) {
                     self.delete_download(&mut download).await?;
                 }
             }
+            DownloadEvent::Bulk(action, ids, reply) => {
+                log::info!("Applying {} to {} downloads", action.get_string(), ids.len());
+                *self.held_signals.lock().await = Some(HeldSignals::default());
+                let mut results = vec![];
+                for id in ids {
+                    // The status may have changed since the action was requested
+                    let result = match self.store.get_download_by_id(id).await {
+                        Ok(download) if !action.applies_to(&download.status) => {
+                            BulkResult::skipped(id, action, &download.status)
+                        }
+                        Ok(_) => match Box::pin(self.handle_event(action.get_event(id))).await {
+                            Ok(()) => BulkResult::ok(id),
+                            Err(e) => {
+                                log::error!("Download #{}: {}", id, e);
+                                BulkResult::error(id, &e.to_string())
+                            }
+                        },
+                        Err(e) => BulkResult::error(id, &e.to_string()),
+                    };
+                    results.push(result);
+                }
+
+                let held_signals = self.held_signals.lock().await.take().unwrap_or_default();
+                let changes = held_signals.changes;
+                if !changes.updated.is_empty() || !changes.deleted.is_empty() {
+                    _ = self
+                        .events_tx
+                        .send(DownloadEvent::BulkUpdate(changes))
+                        .map_err(|e| {
+                            log::error!("{e}");
+                        });
+                }
+                for group_id in held_signals.group_ids {
+                    self.update_group(group_id).await;
+                }
+                reply.send(results);
+            }
+            DownloadEvent::ResolveConflict(id, policy) => {
+                self.store
+                    .change_download_conflict_policy(id, policy)
+                    .await?;
+                let download = self.store.get_download_by_id(id).await?;
+
+                // Queue it again, the scheduler starts it and the downloaded file is moved
+                if let DownloadStatus::AwaitingConflictResolution = download.status {
+                    self.resolved_conflicts.lock().await.insert(id);
+                    self.store
+                        .change_download_status(id, &DownloadStatus::Pending)
+                        .await?;
+                }
+            }
             _ => {}
         }
         Ok(())
     }
 
+    /// Queue again the downloads that were waiting to be retried when the daemon stopped,
+    /// their retry timers are gone
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The count of queued downloads
+    pub async fn requeue_retrying_downloads(&self) -> Result<usize, DBError> {
+        let downloads = self
+            .store
+            .get_downloads_by_status(&DownloadStatus::Retrying)
+            .await?;
+        for download in &downloads {
+            self.store
+                .change_download_status(download.id, &DownloadStatus::Pending)
+                .await?;
+        }
+        Ok(downloads.len())
+    }
+
     pub async fn new_download(&self, url: String, confirm: bool) -> Result<(), DBError> {
         let config = config::get_config().await;
         let mut download_info = Download::get_download_from_url(url, &config).await;
         download_info.data_confirmed = confirm;
-        db::new_download(&download_info).await?;
+        self.store.new_download(&download_info).await?;
         Ok(())
     }
 
@@ -287,17 +587,31 @@ impl Downloader {
      * It is used to start a download that has been added to database
      */
     pub async fn download(&self, download_id: i64) -> fdo::Result<()> {
+        let result = self.run_download(download_id).await;
+        self.progress_tracker.remove(download_id).await;
+        result
+    }
+
+    async fn run_download(&self, download_id: i64) -> fdo::Result<()> {
         let config = config::get_config().await;
 
         log::info!("Starting download #{}", download_id);
 
-        let download = db::get_download_by_id(download_id).await;
+        let download = self.store.get_download_by_id(download_id).await;
         if let Err(e) = download {
             log::error!("{e}");
             return Ok(());
         }
         let mut download = download.unwrap();
 
+        // The file was downloaded before its conflict was resolved, it only has to be moved
+        if self.resolved_conflicts.lock().await.remove(&download_id)
+            && fs::try_exists(&download.temp_file).await.unwrap_or(false)
+        {
+            self.finish_resolved_download(&mut download, &config).await;
+            return Ok(());
+        }
+
         let mut start_byte: Option<u128> = None;
 
         self.prepare_download(&mut download, &mut start_byte).await;
@@ -309,7 +623,15 @@ impl Downloader {
                 log::error!("{e}");
             });
 
-        let client = self.create_client(start_byte, &config).await;
+        // Only a strong ETag can be used as a range validator
+        let if_range = download
+            .etag
+            .clone()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(download.last_modified.clone());
+        let client = self
+            .create_client(start_byte, if_range.as_deref(), &config)
+            .await;
         if let Err(e) = client {
             log::error!("{e}");
             _ = self
@@ -326,31 +648,104 @@ impl Downloader {
         log::debug!("Download #{}: Sending request...", &download_id);
 
         // Perform request
-        let mut resp = client.get(&download.url).send().await.unwrap();
+        let first_byte_timeout = Duration::from_secs(config.first_byte_timeout);
+        let mut resp = match timeout(first_byte_timeout, client.get(&download.url).send()).await {
+            Ok(Ok(resp)) => resp,
+            Ok(Err(e)) => {
+                self.retry_download(&mut download, &e.to_string(), &config)
+                    .await;
+                self.downloading.lock().await.remove(&download_id);
+                return Ok(());
+            }
+            Err(_) => {
+                self.retry_download(&mut download, "No response from server", &config)
+                    .await;
+                self.downloading.lock().await.remove(&download_id);
+                return Ok(());
+            }
+        };
+
+        // Make sure the response continues the temp file if resumed
+        let mut already_complete = false;
+        if let Some(start_byte) = start_byte {
+            match utils::check_resumed_response(
+                resp.status(),
+                resp.headers(),
+                start_byte as u64,
+                download.size,
+            ) {
+                ResumeCheck::Valid => {}
+                ResumeCheck::Complete => {
+                    log::info!("Download #{}: Already fully downloaded", &download_id);
+                    already_complete = true;
+                }
+                ResumeCheck::FullContent => {
+                    log::warn!(
+                        "Download #{}: Remote file cannot be resumed, restarting from zero",
+                        &download_id
+                    );
+                    _ = utils::empty_temp_file(&download.temp_file).await;
+                    download.size = None;
+                    download.etag = None;
+                    download.last_modified = None;
+                    download.downloaded_bytes = 0;
+                }
+                ResumeCheck::Mismatch => {
+                    log::warn!(
+                        "Download #{}: Remote file changed, restarting from zero",
+                        &download_id
+                    );
+                    _ = utils::empty_temp_file(&download.temp_file).await;
+                    download.size = None;
+                    download.etag = None;
+                    download.last_modified = None;
+                    download.downloaded_bytes = 0;
+                    download.status = DownloadStatus::Pending;
+                    _ = self.update_download_in_db_and_notify(&download).await;
+                    self.downloading.lock().await.remove(&download_id);
+                    return Ok(());
+                }
+            }
+        }
 
         // Check if request was successful
-        if let Err(err) = resp.error_for_status_ref() {
-            log::error!("Download #{}: Unsuccessful response: {}", &download_id, err);
+        if !already_complete {
+            if let Err(err) = resp.error_for_status_ref() {
+                log::error!("Download #{}: Unsuccessful response: {}", &download_id, err);
 
-            _ = self
-                .update_download_status_and_notify(&mut download, DownloadStatus::ServerError)
-                .await;
+                _ = self
+                    .update_download_status_and_notify(&mut download, DownloadStatus::ServerError)
+                    .await;
 
-            self.downloading.lock().await.remove(&download_id);
-            return Ok(());
+                self.downloading.lock().await.remove(&download_id);
+                return Ok(());
+            }
         }
 
         // Get file info
-        let file_info = utils::get_file_info_from_headers(&resp.url().as_str(), resp.headers());
+        let file_info = utils::get_file_info_from_headers(resp.url().as_str(), resp.headers());
+
+        // Members of a group go to the group directory unless told otherwise
+        if let (None, Some(group_id)) = (&download.output_directory, download.group_id) {
+            if let Ok(group) = self.store.get_group_by_id(group_id).await {
+                download.output_directory = group.directory;
+            }
+        }
 
         // Detect output file
-        if let None = download.detected_output_file {
+        if download.detected_output_file.is_none() {
             download.detected_output_file =
-                Some(utils::get_output_file_path(&file_info, &config).await);
+                Some(utils::get_output_file_path(&download, &file_info, &config).await);
+            download.category = utils::get_file_category(&download, &file_info, &config).category;
+            download.content_type = file_info.content_type.clone();
         }
-        if let None = download.size {
+        if download.size.is_none() {
             download.size = file_info.content_length;
         }
+        if download.etag.is_none() && download.last_modified.is_none() {
+            download.etag = file_info.etag.clone();
+            download.last_modified = file_info.last_modified_header.clone();
+        }
         _ = self.update_download_in_db_and_notify(&download).await;
 
         log::info!(
@@ -385,48 +780,214 @@ impl Downloader {
 
         // Get temp file size in case of resuming
         let mut progress = file.metadata().await.unwrap().len();
+        let attempt_start = progress;
+
+        // Make sure the rest of the file fits on disk before writing it
+        let output_file = download
+            .output_file
+            .clone()
+            .or(download.detected_output_file.clone())
+            .unwrap_or_default();
+        let remaining = download.size.unwrap_or(0).saturating_sub(progress);
+        // An earlier attempt may have preallocated the rest of the file already
+        let reserved_at_start = fs_utils::get_reserved_space(Path::new(&download.temp_file))
+            .unwrap_or(0)
+            .min(remaining);
+        if !utils::has_enough_space(
+            &download.temp_file,
+            &output_file,
+            remaining,
+            reserved_at_start,
+            download.size.unwrap_or(0),
+        )
+        .unwrap_or(true)
+        {
+            _ = self.pause_for_insufficient_space(&mut download).await;
+            self.downloading.lock().await.remove(&download_id);
+            return Ok(());
+        }
+        // The reserved blocks already count as used in the free space checks below
+        let mut preallocated = false;
+        if config.preallocate && remaining > 0 {
+            match fs_utils::preallocate(&file, progress, remaining) {
+                Ok(()) => preallocated = true,
+                Err(e) => {
+                    log::debug!("Download #{}: Could not preallocate: {}", &download_id, e);
+                }
+            }
+        }
+
         let mut progress_mark = Instant::now();
-        let initial_progress_mark = progress_mark.clone();
-        while let Some(chunk) = resp.chunk().await.unwrap() {
+        let initial_progress_mark = progress_mark;
+        let mut speed_meter = SpeedMeter::new(progress, progress_mark);
+        let mut space_check_mark = Instant::now();
+        let mut save_mark = Instant::now();
+        let mut sampled_bytes = progress;
+        let idle_timeout = Duration::from_secs(config.idle_timeout);
+        let mut low_speed_detector = LowSpeedDetector::new(
+            config.low_speed_limit,
+            Duration::from_secs(config.low_speed_time),
+            progress,
+            Instant::now(),
+        );
+        // Read on every chunk so that a new limit applies to running downloads
+        let shared_config = config::get_shared_config().await;
+        let mut speed_limiter = SpeedLimiter::new(Instant::now());
+        // Set when the transfer stopped early and should be retried
+        let mut stall_reason: Option<String> = None;
+        // There is nothing to read if the temp file already holds the whole file
+        let mut reading = !already_complete;
+        while reading {
+            let chunk = match timeout(idle_timeout, resp.chunk()).await {
+                Ok(Ok(Some(chunk))) => chunk,
+                Ok(Ok(None)) => {
+                    if download.size.is_some_and(|size| progress < size) {
+                        stall_reason =
+                            Some("Connection closed before the end of the file".to_string());
+                    }
+                    reading = false;
+                    continue;
+                }
+                Ok(Err(e)) => {
+                    stall_reason = Some(e.to_string());
+                    reading = false;
+                    continue;
+                }
+                Err(_) => {
+                    stall_reason = Some(format!("No data received for {} s", config.idle_timeout));
+                    reading = false;
+                    continue;
+                }
+            };
+
             if (Instant::now() - progress_mark) > Duration::from_millis(250)
                 || initial_progress_mark == progress_mark
             {
                 progress_mark = Instant::now();
+                speed_meter.record(progress, progress_mark);
+                let progress_info = speed_meter.get_progress(download_id, download.size);
+                self.progress_tracker.update(progress_info.clone()).await;
                 self.events_tx
-                    .send(DownloadEvent::DownloadProgress(
-                        download_id,
-                        progress,
-                        download.size.unwrap_or(0),
-                    ))
+                    .send(DownloadEvent::DownloadProgress(progress_info))
                     .unwrap();
             }
+            if save_mark.elapsed() > PROGRESS_SAVE_INTERVAL {
+                save_mark = Instant::now();
+                let speed = speed_meter.get_progress(download_id, download.size).speed;
+                self.save_progress(&mut download, progress, speed, &mut sampled_bytes)
+                    .await;
+            }
 
             // Check cancel requests
             if self.cancel_requests.lock().await.contains(&download_id) {
+                let speed = speed_meter.get_progress(download_id, download.size).speed;
+                self.save_progress(&mut download, progress, speed, &mut sampled_bytes)
+                    .await;
                 _ = self.cancel_download(&mut download).await;
                 self.downloading.lock().await.remove(&download_id);
                 return Ok(());
             }
             // Check pause requests
             if self.pause_requests.lock().await.contains(&download_id) {
+                let speed = speed_meter.get_progress(download_id, download.size).speed;
+                self.save_progress(&mut download, progress, speed, &mut sampled_bytes)
+                    .await;
                 _ = self.pause_download(&mut download).await;
                 self.downloading.lock().await.remove(&download_id);
                 return Ok(());
             }
+            // Check free space periodically as other programs write to the same disks
+            if space_check_mark.elapsed() > SPACE_CHECK_INTERVAL {
+                space_check_mark = Instant::now();
+                let remaining = download.size.unwrap_or(0).saturating_sub(progress);
+                let reserved = if preallocated {
+                    remaining
+                } else {
+                    reserved_at_start
+                        .saturating_sub(progress - attempt_start)
+                        .min(remaining)
+                };
+                if !utils::has_enough_space(
+                    &download.temp_file,
+                    &output_file,
+                    remaining,
+                    reserved,
+                    download.size.unwrap_or(0),
+                )
+                .unwrap_or(true)
+                {
+                    let speed = speed_meter.get_progress(download_id, download.size).speed;
+                    self.save_progress(&mut download, progress, speed, &mut sampled_bytes)
+                        .await;
+                    _ = self.pause_for_insufficient_space(&mut download).await;
+                    self.downloading.lock().await.remove(&download_id);
+                    return Ok(());
+                }
+            }
 
-            file.write_all(&chunk).await.unwrap();
+            if let Err(e) = file.write_all(&chunk).await {
+                log::error!(
+                    "Download #{}: Could not write to {}: {}",
+                    &download_id,
+                    &download.temp_file,
+                    e
+                );
+                let speed = speed_meter.get_progress(download_id, download.size).speed;
+                self.save_progress(&mut download, progress, speed, &mut sampled_bytes)
+                    .await;
+                if e.kind() == io::ErrorKind::StorageFull {
+                    _ = self.pause_for_insufficient_space(&mut download).await;
+                } else {
+                    _ = self
+                        .update_download_status_and_notify(
+                            &mut download,
+                            DownloadStatus::ClientError,
+                        )
+                        .await;
+                }
+                self.downloading.lock().await.remove(&download_id);
+                return Ok(());
+            }
             progress += chunk.len() as u64;
+
+            let speed_limit = shared_config.read().await.speed_limit;
+            let delay = speed_limiter.get_delay(speed_limit, chunk.len() as u64, Instant::now());
+            if !delay.is_zero() {
+                sleep(delay).await;
+            }
+
+            if low_speed_detector.is_stalled(progress, Instant::now()) {
+                stall_reason = Some(format!(
+                    "Speed under {} B/s for {} s",
+                    config.low_speed_limit, config.low_speed_time
+                ));
+                reading = false;
+            }
+        }
+        let speed = speed_meter.get_progress(download_id, download.size).speed;
+        self.save_progress(&mut download, progress, speed, &mut sampled_bytes)
+            .await;
+
+        // Keep the downloaded bytes and try again later
+        if let Some(reason) = stall_reason {
+            // Only attempts that download nothing count toward the retry limit
+            if progress > attempt_start {
+                self.retry_counts.lock().await.remove(&download_id);
+            }
+            self.retry_download(&mut download, &reason, &config).await;
+            self.downloading.lock().await.remove(&download_id);
+            return Ok(());
         }
 
         // Wait for file metadata confirmation
-        download.refresh_data_from_db().await;
+        download.refresh_data_from_db(&*self.store).await;
         while !&download.data_confirmed {
             log::info!(
                 "Download #{}: Waiting for download data confirmation...",
                 &download_id
             );
             sleep(Duration::from_secs(1)).await;
-            download.refresh_data_from_db().await;
+            download.refresh_data_from_db(&*self.store).await;
         }
 
         // Get output path
@@ -435,62 +996,249 @@ impl Downloader {
         } else {
             match &download.detected_output_file {
                 Some(output_file) => output_file.clone(),
-                None => utils::get_output_file_path(&file_info, &config).await,
+                None => utils::get_output_file_path(&download, &file_info, &config).await,
             }
         };
 
-        let file_output = utils::get_conflict_free_file_path(&file_output);
+        self.finish_download(&mut download, file_output, file_info.last_modified, &config)
+            .await;
 
-        log::info!(
-            "Download #{}: Moving file to {}",
-            &download_id,
-            &file_output
-        );
+        self.downloading.lock().await.remove(&download_id);
+        Ok(())
+    }
 
-        // Check if path exists
-        let output_path_parent = Path::new(&file_output).parent();
-        if output_path_parent.is_none() || !output_path_parent.unwrap().exists() {
-            log::error!(
-                "Download #{}: Output directory {} does not exist",
-                &download_id,
-                output_path_parent
-                    .unwrap_or(Path::new(""))
-                    .to_str()
-                    .unwrap()
-            );
-            _ = self
-                .update_download_status_and_notify(&mut download, DownloadStatus::ClientError)
+    /// Move a downloaded file to its output path, applying the conflict policy, and mark the
+    /// download as completed
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The download to finish
+    /// * `file_output` - The wanted output file path
+    /// * `remote_modified` - The remote modification time as a timestamp if known
+    /// * `config` - The configuration to get the default policy from
+    async fn finish_download(
+        &self,
+        download: &mut Download,
+        file_output: String,
+        remote_modified: Option<i64>,
+        config: &Config,
+    ) {
+        let download_id = download.id;
+        let Some(resolution) = self
+            .resolve_output_conflict(download, file_output, remote_modified, config)
+            .await
+        else {
+            return;
+        };
+
+        let file_output = match resolution {
+            ConflictResolution::KeepExisting(existing_file) => {
+                log::info!(
+                    "Download #{}: Keeping existing file {}",
+                    &download_id,
+                    &existing_file
+                );
+                _ = utils::delete_temp_file(&download.temp_file)
+                    .await
+                    .map_err(|e| {
+                        log::error!("{e}");
+                    });
+                existing_file
+            }
+            ConflictResolution::MoveTo(file_output) => {
+                log::info!(
+                    "Download #{}: Moving file to {}",
+                    &download_id,
+                    &file_output
+                );
+
+                // Create missing output directories
+                let output_path_parent =
+                    Path::new(&file_output).parent().unwrap_or(Path::new("/"));
+                if let Err(e) = fs::create_dir_all(output_path_parent).await {
+                    log::error!(
+                        "Download #{}: Could not create output directory {}: {}",
+                        &download_id,
+                        output_path_parent.to_string_lossy(),
+                        e
+                    );
+                    _ = self
+                        .update_download_status_and_notify(download, DownloadStatus::ClientError)
+                        .await;
+                    return;
+                }
+
+                // Move file from temp to output
+                let events_tx = self.events_tx.clone();
+                let mut move_progress_mark: Option<Instant> = None;
+                let move_result = utils::move_file(
+                    &download.temp_file,
+                    &file_output,
+                    |copied, total| {
+                        if move_progress_mark
+                            .is_none_or(|mark| mark.elapsed() > Duration::from_millis(250))
+                            || copied == total
+                        {
+                            move_progress_mark = Some(Instant::now());
+                            _ = events_tx.send(DownloadEvent::MoveProgress(
+                                download_id,
+                                copied,
+                                total,
+                            ));
+                        }
+                    },
+                )
                 .await;
-            return Ok(());
-        }
+                if let Err(e) = move_result {
+                    log::error!(
+                        "Download #{}: Could not move file to {}: {}",
+                        &download_id,
+                        &file_output,
+                        e
+                    );
+                    _ = self
+                        .update_download_status_and_notify(download, DownloadStatus::ClientError)
+                        .await;
+                    return;
+                }
 
-        // Move file from temp to output
-        tokio::fs::rename(&download.temp_file, &file_output)
-            .await
-            .unwrap();
+                file_output
+            }
+        };
 
         // Save conflict free path to database
-        if (!download.output_file.is_none()
+        if (download.output_file.is_some()
             && download.output_file.as_ref().unwrap() != &file_output)
             || (download.output_file.is_none()
                 && download.detected_output_file.as_ref().unwrap() != &file_output)
         {
             download.output_file = Some(file_output);
-            _ = self.update_download_in_db_and_notify(&download).await;
+            _ = self.update_download_in_db_and_notify(download).await;
         }
 
         log::info!("Download #{}: Completed", &download_id);
 
         download.date_completed = Some(Local::now().timestamp());
-        _ = self.update_download_in_db_and_notify(&download).await;
+        _ = self.update_download_in_db_and_notify(download).await;
 
         // Change download status to completed
         _ = self
-            .update_download_status_and_notify(&mut download, DownloadStatus::Completed)
+            .update_download_status_and_notify(download, DownloadStatus::Completed)
             .await;
+        self.retry_counts.lock().await.remove(&download_id);
+    }
 
-        self.downloading.lock().await.remove(&download_id);
-        Ok(())
+    /// Finish a download parked until its file conflict was resolved, its temp file already
+    /// holds the whole file
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The download to finish
+    /// * `config` - The configuration to get the default policy from
+    async fn finish_resolved_download(&self, download: &mut Download, config: &Config) {
+        log::info!("Download #{}: Finishing after conflict resolution", &download.id);
+        self.downloading.lock().await.insert(download.id);
+        _ = self
+            .update_download_status_and_notify(download, DownloadStatus::InProgress)
+            .await;
+
+        let file_output = download
+            .output_file
+            .clone()
+            .or(download.detected_output_file.clone())
+            .unwrap_or_default();
+        let remote_modified = download
+            .last_modified
+            .as_deref()
+            .and_then(|last_modified| DateTime::parse_from_rfc2822(last_modified).ok())
+            .map(|last_modified| last_modified.timestamp());
+        self.finish_download(download, file_output, remote_modified, config)
+            .await;
+
+        self.downloading.lock().await.remove(&download.id);
+    }
+
+    /// Apply the conflict policy if the output file already exists. With the `Ask` policy,
+    /// the download is parked until a policy is picked for it through DBus.
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The download being finalized
+    /// * `file_output` - The wanted output file path
+    /// * `remote_modified` - The remote modification time as a timestamp if known
+    /// * `config` - The configuration to get the default policy from
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ConflictResolution>` - The resolution, `None` if the download was parked
+    async fn resolve_output_conflict(
+        &self,
+        download: &mut Download,
+        file_output: String,
+        remote_modified: Option<i64>,
+        config: &Config,
+    ) -> Option<ConflictResolution> {
+        if !Path::new(&file_output).exists() {
+            return Some(ConflictResolution::MoveTo(file_output));
+        }
+
+        let policy = download.conflict_policy.unwrap_or(config.conflict_policy);
+        if let ConflictPolicy::Ask = policy {
+            // The temp file is kept and the slot is freed until the conflict is resolved
+            log::info!(
+                "Download #{}: Waiting for conflict resolution for {}",
+                &download.id,
+                &file_output
+            );
+            _ = self
+                .update_download_status_and_notify(
+                    download,
+                    DownloadStatus::AwaitingConflictResolution,
+                )
+                .await;
+            _ = self
+                .events_tx
+                .send(DownloadEvent::DownloadConflict(
+                    download.id,
+                    file_output.clone(),
+                ))
+                .map_err(|e| {
+                    log::error!("{e}");
+                });
+            return None;
+        }
+
+        let resolution = match policy {
+            ConflictPolicy::Overwrite => ConflictResolution::MoveTo(file_output),
+            ConflictPolicy::SkipIdentical => {
+                if utils::files_are_identical(&download.temp_file, &file_output)
+                    .await
+                    .unwrap_or(false)
+                {
+                    ConflictResolution::KeepExisting(file_output)
+                } else {
+                    ConflictResolution::MoveTo(utils::get_conflict_free_file_path(&file_output))
+                }
+            }
+            ConflictPolicy::KeepNewer => {
+                let existing_modified = fs::metadata(&file_output)
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|modified| modified.as_secs() as i64);
+                match (existing_modified, remote_modified) {
+                    (Some(existing), Some(remote)) if existing >= remote => {
+                        ConflictResolution::KeepExisting(file_output)
+                    }
+                    _ => ConflictResolution::MoveTo(file_output),
+                }
+            }
+            ConflictPolicy::Rename | ConflictPolicy::Ask => {
+                ConflictResolution::MoveTo(utils::get_conflict_free_file_path(&file_output))
+            }
+        };
+        Some(resolution)
     }
 
     /// Prepare download by checking if temp file has data and setting start byte
@@ -505,7 +1253,7 @@ impl Downloader {
         // Check if temp file has data
         if fs::try_exists(&download.temp_file).await.unwrap_or(false) {
             let temp_file = OpenOptions::new()
-                .read(true)
+                .write(true)
                 .open(&download.temp_file)
                 .await
                 .unwrap();
@@ -519,8 +1267,10 @@ impl Downloader {
                         downloaded_size
                     );
                     *start_byte = Some(downloaded_size as u128);
+                    download.downloaded_bytes = downloaded_size;
                 } else {
                     temp_file.set_len(0).await.unwrap();
+                    download.downloaded_bytes = 0;
                 }
             }
         }
@@ -531,6 +1281,7 @@ impl Downloader {
     /// # Arguments
     ///
     /// * `start_byte` - The byte to start from if resumed download
+    /// * `if_range` - The validator the remote file must match to be resumed
     /// * `config` - The configuration to get user agent from
     ///
     /// # Returns
@@ -539,21 +1290,139 @@ impl Downloader {
     async fn create_client(
         &self,
         start_byte: Option<u128>,
+        if_range: Option<&str>,
         config: &Config,
     ) -> reqwest::Result<Client> {
         // Create client
-        let mut client_builder = reqwest::Client::builder().user_agent(&config.user_agent);
+        let mut client_builder = reqwest::Client::builder()
+            .user_agent(&config.user_agent)
+            .connect_timeout(Duration::from_secs(config.connect_timeout));
 
         // Start from byte if resumed download
         if let Some(byte) = start_byte {
             let mut headers = HeaderMap::new();
             headers.insert(RANGE, format!("bytes={}-", byte).parse().unwrap());
+            if let Some(validator) = if_range.and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(IF_RANGE, validator);
+            }
             client_builder = client_builder.default_headers(headers);
         }
 
         client_builder.build()
     }
 
+    /// Save the downloaded byte count in database and record a speed sample
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The running download
+    /// * `downloaded` - Bytes written to the temp file
+    /// * `speed` - Current speed in bytes per second
+    /// * `sampled_bytes` - Byte count of the previous sample, updated when a sample is recorded
+    async fn save_progress(
+        &self,
+        download: &mut Download,
+        downloaded: u64,
+        speed: u64,
+        sampled_bytes: &mut u64,
+    ) {
+        download.downloaded_bytes = downloaded;
+        _ = self
+            .store
+            .change_download_downloaded_bytes(download.id, downloaded)
+            .await
+            .map_err(|e| {
+                log::error!("Download #{}: {}", &download.id, e);
+            });
+
+        if downloaded <= *sampled_bytes {
+            return;
+        }
+        let sample = SpeedSample {
+            download_id: download.id,
+            timestamp: Local::now().timestamp(),
+            speed,
+            bytes: downloaded - *sampled_bytes,
+        };
+        *sampled_bytes = downloaded;
+        _ = self
+            .store
+            .add_speed_sample(&utils::get_url_host(&download.url), &sample)
+            .await
+            .map_err(|e| {
+                log::error!("Download #{}: {}", &download.id, e);
+            });
+    }
+
+    /// Stop a download that stalled or lost its connection and schedule a retry that resumes
+    /// from the downloaded bytes. The download fails once the retries are exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The download to be retried
+    /// * `reason` - Why the transfer stopped
+    /// * `config` - The configuration to get the retry settings from
+    async fn retry_download(&self, download: &mut Download, reason: &str, config: &Config) {
+        let retries = {
+            let mut retry_counts = self.retry_counts.lock().await;
+            let retries = retry_counts.entry(download.id).or_insert(0);
+            *retries += 1;
+            *retries
+        };
+
+        if retries > config.max_retries {
+            log::error!(
+                "Download #{}: {}, giving up after {} retries",
+                &download.id,
+                reason,
+                config.max_retries
+            );
+            self.retry_counts.lock().await.remove(&download.id);
+            _ = self
+                .update_download_status_and_notify(download, DownloadStatus::ServerError)
+                .await;
+            _ = self.report_error(Some(download.id), reason);
+            return;
+        }
+
+        log::warn!(
+            "Download #{}: {}, retrying in {} s ({}/{})",
+            &download.id,
+            reason,
+            config.retry_delay,
+            retries,
+            config.max_retries
+        );
+        _ = self
+            .update_download_status_and_notify(download, DownloadStatus::Retrying)
+            .await;
+
+        let download_id = download.id;
+        let retry_delay = Duration::from_secs(config.retry_delay);
+        let events_tx = self.events_tx.clone();
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            sleep(retry_delay).await;
+            // The download may have been paused, canceled or deleted meanwhile
+            let Ok(mut download) = store.get_download_by_id(download_id).await else {
+                return;
+            };
+            if matches!(download.status, DownloadStatus::Retrying) {
+                _ = download
+                    .change_download_status(&*store, DownloadStatus::Pending)
+                    .await
+                    .map_err(|e| {
+                        log::error!("{e}");
+                    });
+                _ = events_tx
+                    .send(DownloadEvent::DownloadUpdate(download))
+                    .map_err(|e| {
+                        log::error!("{e}");
+                    });
+            }
+        });
+    }
+
     /// Pause download, save in database and notify in DBus
     ///
     /// # Arguments
@@ -562,24 +1431,41 @@ impl Downloader {
     async fn pause_download(&self, download: &mut Download) -> Result<(), DownloaderError> {
         log::info!("Download #{}: Paused", &download.id);
         download
-            .change_download_status(DownloadStatus::Paused)
+            .change_download_status(&*self.store, DownloadStatus::Paused)
             .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
-        self.events_tx
-            .send(DownloadEvent::DownloadUpdate(download.clone()))
+        self.send_download_update(download)
+            .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
 
+        self.notify_group_update(download).await;
         self.pause_requests.lock().await.remove(&download.id);
 
         Ok(())
     }
 
+    /// Pause download because the disk is running out of space and notify in DBus
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The download to be paused
+    async fn pause_for_insufficient_space(
+        &self,
+        download: &mut Download,
+    ) -> Result<(), DownloaderError> {
+        log::warn!("Download #{}: Paused, not enough disk space", &download.id);
+        self.update_download_status_and_notify(download, DownloadStatus::InsufficientSpace)
+            .await?;
+        self.report_error(Some(download.id), "Not enough disk space")?;
+        Ok(())
+    }
+
     /// Cancel download and delete temp file
     ///
     /// # Arguments
@@ -588,20 +1474,25 @@ impl Downloader {
     async fn cancel_download(&self, download: &mut Download) -> Result<(), DownloaderError> {
         log::info!("Download #{}: Cancelled", &download.id);
         download
-            .change_download_status(DownloadStatus::Canceled)
+            .change_download_status(&*self.store, DownloadStatus::Canceled)
             .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
-        self.events_tx
-            .send(DownloadEvent::DownloadUpdate(download.clone()))
+        download.downloaded_bytes = 0;
+        self.store
+            .change_download_downloaded_bytes(download.id, 0)
+            .await?;
+        self.send_download_update(download)
+            .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
 
         _ = utils::empty_temp_file(&download.temp_file).await;
+        self.notify_group_update(download).await;
 
         self.cancel_requests.lock().await.remove(&download.id);
 
@@ -616,7 +1507,7 @@ impl Downloader {
     async fn delete_download(&self, download: &mut Download) -> Result<(), DownloaderError> {
         log::info!("Download #{}: Deleted", &download.id);
 
-        db::delete_download(download.id).await?;
+        self.store.delete_download(download.id).await?;
 
         _ = utils::delete_temp_file(&download.temp_file)
             .await
@@ -624,12 +1515,13 @@ impl Downloader {
                 log::error!("{e}");
             });
 
-        self.events_tx
-            .send(DownloadEvent::DownloadDelete(download.id))
+        self.send_download_delete(download.id)
+            .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
+        self.notify_group_update(download).await;
 
         Ok(())
     }
@@ -646,18 +1538,19 @@ impl Downloader {
         new_status: DownloadStatus,
     ) -> Result<(), DownloaderError> {
         download
-            .change_download_status(new_status)
+            .change_download_status(&*self.store, new_status)
             .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
-        self.events_tx
-            .send(DownloadEvent::DownloadUpdate(download.clone()))
+        self.send_download_update(download)
+            .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
+        self.notify_group_update(download).await;
         Ok(())
     }
 
@@ -670,19 +1563,116 @@ impl Downloader {
         &self,
         download: &Download,
     ) -> Result<(), DownloaderError> {
-        download.sync_to_db().await.map_err(|e| {
+        download.sync_to_db(&*self.store).await.map_err(|e| {
             log::error!("{e}");
             e
         })?;
-        self.events_tx
-            .send(DownloadEvent::DownloadUpdate(download.clone()))
+        self.send_download_update(download)
+            .await
             .map_err(|e| {
                 log::error!("{e}");
                 e
             })?;
+        self.notify_group_update(download).await;
         Ok(())
     }
 
+    /// Notify the aggregate state of the group of the download in DBus and record when
+    /// its last member completes, held until the end of the bulk action being applied
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The download that changed
+    async fn notify_group_update(&self, download: &Download) {
+        let Some(group_id) = download.group_id else {
+            return;
+        };
+        if let Some(held_signals) = self.held_signals.lock().await.as_mut() {
+            if !held_signals.group_ids.contains(&group_id) {
+                held_signals.group_ids.push(group_id);
+            }
+            return;
+        }
+        self.update_group(group_id).await;
+    }
+
+    /// Notify the aggregate state of a group in DBus and record when its last member
+    /// completes
+    ///
+    /// # Arguments
+    ///
+    /// * `group_id` - The id of the group
+    async fn update_group(&self, group_id: i64) {
+        let mut group = match group::get_group(&*self.store, group_id).await {
+            Ok(group) => group,
+            // The group is being deleted with its members
+            Err(DBError::GroupNotFound(_)) => return,
+            Err(e) => {
+                log::error!("Group #{}: {}", group_id, e);
+                return;
+            }
+        };
+
+        let completed = group.status == DownloadStatus::Completed.get_string();
+        if completed != group.date_completed.is_some() {
+            group.date_completed = completed.then(|| Local::now().timestamp());
+            _ = self
+                .store
+                .change_group_date_completed(group_id, group.date_completed)
+                .await
+                .map_err(|e| {
+                    log::error!("Group #{}: {}", group_id, e);
+                });
+            if completed {
+                log::info!("Group #{}: Completed", group_id);
+            }
+        }
+
+        _ = self
+            .events_tx
+            .send(DownloadEvent::GroupUpdate(group))
+            .map_err(|e| {
+                log::error!("{e}");
+            });
+    }
+
+    /// Notify a changed download in DBus, held until the end of the bulk action being applied
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The changed download
+    async fn send_download_update(
+        &self,
+        download: &Download,
+    ) -> Result<(), SendError<DownloadEvent>> {
+        if let Some(held_signals) = self.held_signals.lock().await.as_mut() {
+            let updated = &mut held_signals.changes.updated;
+            updated.retain(|updated| updated.id != download.id);
+            updated.push(download.clone());
+            return Ok(());
+        }
+        self.events_tx
+            .send(DownloadEvent::DownloadUpdate(download.clone()))
+            .map(|_| ())
+    }
+
+    /// Notify a deleted download in DBus, held until the end of the bulk action being applied
+    ///
+    /// # Arguments
+    ///
+    /// * `download_id` - The id of the deleted download
+    async fn send_download_delete(&self, download_id: i64) -> Result<(), SendError<DownloadEvent>> {
+        if let Some(held_signals) = self.held_signals.lock().await.as_mut() {
+            let changes = &mut held_signals.changes;
+            changes.updated.retain(|updated| updated.id != download_id);
+            changes.deleted.push(download_id);
+            return Ok(());
+        }
+        self.events_tx
+            .send(DownloadEvent::DownloadDelete(download_id))
+            .map(|_| ())
+    }
+
     /// Report error through DBus
     ///
     /// # Arguments
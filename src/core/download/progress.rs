@@ -0,0 +1,206 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
+
+/// Time span used to compute the current speed
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct ProgressInfo {
+    pub id: i64,
+    pub downloaded: u64,
+    /// Full size of the file, 0 if unknown
+    pub size: u64,
+    /// Moving average of the speed in bytes per second
+    pub speed: u64,
+    /// Average speed since the download was started in bytes per second
+    pub average_speed: u64,
+    /// Estimated seconds left, -1 if unknown
+    pub eta: i64,
+    /// Seconds spent downloading since the download was started
+    pub elapsed: u64,
+}
+
+/// Speed of a download recorded periodically in database
+#[derive(Debug, Clone, PartialEq, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct SpeedSample {
+    pub download_id: i64,
+    /// Unix timestamp of the sample
+    pub timestamp: i64,
+    /// Speed in bytes per second
+    pub speed: u64,
+    /// Bytes downloaded since the previous sample
+    pub bytes: u64,
+}
+
+/// Bytes downloaded from a host during a day
+#[derive(Debug, Clone, PartialEq, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct BandwidthUsage {
+    /// Local date formatted as YYYY-MM-DD
+    pub day: String,
+    pub host: String,
+    pub bytes: u64,
+}
+
+/// Computes speeds and ETA from the downloaded byte count sampled over time
+pub struct SpeedMeter {
+    started_at: Instant,
+    initial_bytes: u64,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SpeedMeter {
+    pub fn new(initial_bytes: u64, now: Instant) -> SpeedMeter {
+        SpeedMeter {
+            started_at: now,
+            initial_bytes,
+            samples: VecDeque::from([(now, initial_bytes)]),
+        }
+    }
+
+    /// Records the downloaded byte count and drops samples out of the speed window
+    pub fn record(&mut self, downloaded: u64, now: Instant) {
+        self.samples.push_back((now, downloaded));
+        // Keep one sample older than the window as the reference point
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) > SPEED_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn get_progress(&self, id: i64, size: Option<u64>) -> ProgressInfo {
+        let initial_sample = (self.started_at, self.initial_bytes);
+        let (first_instant, first_bytes) = self.samples.front().copied().unwrap_or(initial_sample);
+        let (last_instant, downloaded) = self.samples.back().copied().unwrap_or(initial_sample);
+
+        let speed = bytes_per_second(
+            downloaded.saturating_sub(first_bytes),
+            last_instant.duration_since(first_instant),
+        );
+        let elapsed = last_instant.duration_since(self.started_at);
+        let average_speed =
+            bytes_per_second(downloaded.saturating_sub(self.initial_bytes), elapsed);
+        let eta = match size {
+            Some(size) if speed > 0 => (size.saturating_sub(downloaded) / speed) as i64,
+            _ => -1,
+        };
+
+        ProgressInfo {
+            id,
+            downloaded,
+            size: size.unwrap_or(0),
+            speed,
+            average_speed,
+            eta,
+            elapsed: elapsed.as_secs(),
+        }
+    }
+}
+
+/// Detects downloads that stay under a minimum speed for too long
+pub struct LowSpeedDetector {
+    limit: u64,
+    period: Duration,
+    mark: (Instant, u64),
+}
+
+impl LowSpeedDetector {
+    /// A `limit` of 0 or an empty `period` disables the detection
+    pub fn new(limit: u64, period: Duration, downloaded: u64, now: Instant) -> LowSpeedDetector {
+        LowSpeedDetector {
+            limit,
+            period,
+            mark: (now, downloaded),
+        }
+    }
+
+    /// Returns `true` if the average speed over the last full period is under the limit
+    pub fn is_stalled(&mut self, downloaded: u64, now: Instant) -> bool {
+        if self.limit == 0 || self.period.is_zero() {
+            return false;
+        }
+        let (mark_instant, mark_bytes) = self.mark;
+        let elapsed = now.duration_since(mark_instant);
+        if elapsed < self.period {
+            return false;
+        }
+        self.mark = (now, downloaded);
+        bytes_per_second(downloaded.saturating_sub(mark_bytes), elapsed) < self.limit
+    }
+}
+
+/// Slows a transfer down to a maximum speed, the limit can change during the transfer
+pub struct SpeedLimiter {
+    limit: u64,
+    mark: (Instant, u64),
+}
+
+impl SpeedLimiter {
+    pub fn new(now: Instant) -> SpeedLimiter {
+        SpeedLimiter {
+            limit: 0,
+            mark: (now, 0),
+        }
+    }
+
+    /// Returns how long to wait after receiving `bytes` to stay under `limit`, 0 meaning no limit
+    pub fn get_delay(&mut self, limit: u64, bytes: u64, now: Instant) -> Duration {
+        if limit != self.limit {
+            self.limit = limit;
+            self.mark = (now, 0);
+        }
+        if limit == 0 {
+            return Duration::ZERO;
+        }
+        let (mark_instant, mark_bytes) = self.mark;
+        let received = mark_bytes + bytes;
+        let expected = Duration::from_secs_f64(received as f64 / limit as f64);
+        let elapsed = now.duration_since(mark_instant);
+        if elapsed >= expected {
+            // Time spent under the limit is not saved for a later burst
+            self.mark = (now, 0);
+            return Duration::ZERO;
+        }
+        self.mark = (mark_instant, received);
+        expected - elapsed
+    }
+}
+
+fn bytes_per_second(bytes: u64, duration: Duration) -> u64 {
+    if duration.is_zero() {
+        return 0;
+    }
+    (bytes as f64 / duration.as_secs_f64()) as u64
+}
+
+/// Latest progress of the running downloads, shared with the DBus interface
+#[derive(Clone, Default)]
+pub struct ProgressTracker {
+    downloads: Arc<Mutex<HashMap<i64, ProgressInfo>>>,
+}
+
+impl ProgressTracker {
+    pub async fn update(&self, progress: ProgressInfo) {
+        self.downloads.lock().await.insert(progress.id, progress);
+    }
+
+    pub async fn remove(&self, download_id: i64) {
+        self.downloads.lock().await.remove(&download_id);
+    }
+
+    pub async fn get(&self, download_id: i64) -> Option<ProgressInfo> {
+        self.downloads.lock().await.get(&download_id).cloned()
+    }
+
+    /// Returns the total speed and the count of running downloads
+    pub async fn get_global_progress(&self) -> (u64, u32) {
+        let downloads = self.downloads.lock().await;
+        let total_speed = downloads.values().map(|progress| progress.speed).sum();
+        (total_speed, downloads.len() as u32)
+    }
+}
@@ -1,9 +1,33 @@
- use reqwest::header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_TYPE};
+use std::collections::HashMap;
+use std::path::Path;
 
+use tokio::time::Duration;
+
+use reqwest::header::{
+    HeaderMap, CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, LAST_MODIFIED,
+};
+use reqwest::StatusCode;
+
+use crate::core::config::{Category, Config, ConflictPolicy};
+use crate::core::db::{
+    get_fts_query, migrate, open_connection, query_download_list, query_downloads,
+    sync_download_categories, DBError, SCHEMA_VERSION,
+};
+use crate::core::group::{get_group_status, DownloadGroup};
+use crate::utils::fs::{get_available_space, get_reserved_space, preallocate};
 use crate::utils::tests::TestFile;
 
 use super::utils::get_file_info_from_headers;
 use super::utils::get_conflict_free_file_path;
+use super::utils::files_are_identical;
+use super::utils::{copy_to_destination, move_file};
+use super::utils::has_enough_space;
+use super::utils::{check_resumed_response, ResumeCheck};
+use super::progress::{LowSpeedDetector, SpeedLimiter, SpeedMeter};
+use super::utils::expand_path_template;
+use super::utils::get_output_file_path;
+use super::utils::get_url_host;
+use super::{BulkAction, Download, DownloadFilter, DownloadStatus, FileInfo, SortKey};
 
 #[test]
 fn test_get_conflict_free_file_path() {
@@ -44,6 +68,46 @@ fn test_get_conflict_free_file_path() {
             "10MB-TESTFILE.ORG (2).tar.gz"
         );
     }
+
+    {
+        let test_file = TestFile::new("10MB-TESTFILE");
+        let test = get_conflict_free_file_path(&test_file.file_path);
+        assert_eq!(
+            test,
+            "10MB-TESTFILE (1)"
+        );
+    }
+
+    {
+        let test_file = TestFile::new(".10MB-TESTFILE");
+        let test = get_conflict_free_file_path(&test_file.file_path);
+        assert_eq!(
+            test,
+            ".10MB-TESTFILE (1)"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_files_are_identical() {
+    let first_file = TestFile::new("IDENTICAL-TESTFILE-1.txt");
+    let second_file = TestFile::new("IDENTICAL-TESTFILE-2.txt");
+
+    std::fs::write(&first_file.file_path, "flowd").unwrap();
+    std::fs::write(&second_file.file_path, "flowd").unwrap();
+    assert!(files_are_identical(&first_file.file_path, &second_file.file_path)
+        .await
+        .unwrap());
+
+    std::fs::write(&second_file.file_path, "flows").unwrap();
+    assert!(!files_are_identical(&first_file.file_path, &second_file.file_path)
+        .await
+        .unwrap());
+
+    std::fs::write(&second_file.file_path, "flowd flowd").unwrap();
+    assert!(!files_are_identical(&first_file.file_path, &second_file.file_path)
+        .await
+        .unwrap());
 }
 
 #[test]
@@ -184,4 +248,888 @@ fn test_get_file_info_from_headers_no_path_segment_no_content_type() {
             test.content_type,
             None
         );
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_get_file_info_from_headers_last_modified() {
+
+    let url = "https://test.com/testfile";
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        LAST_MODIFIED,
+        "Tue, 14 Nov 2023 22:13:20 GMT".parse().unwrap()
+    );
+    let test = get_file_info_from_headers(url, &headers);
+
+    assert_eq!(
+        test.last_modified,
+        Some(1700000000)
+    );
+}
+
+#[tokio::test]
+async fn test_move_file() {
+    let destination = TestFile::new("MOVE-TESTFILE-DESTINATION.txt");
+    let source = TestFile::new("MOVE-TESTFILE-SOURCE.txt");
+    std::fs::write(&source.file_path, "flowd").unwrap();
+
+    move_file(&source.file_path, &destination.file_path, |_, _| {})
+        .await
+        .unwrap();
+
+    assert!(!std::path::Path::new(&source.file_path).exists());
+    assert_eq!(std::fs::read_to_string(&destination.file_path).unwrap(), "flowd");
+}
+
+#[tokio::test]
+async fn test_copy_to_destination() {
+    let destination = TestFile::new("COPY-TESTFILE-DESTINATION.txt");
+    let source = TestFile::new("COPY-TESTFILE-SOURCE.txt");
+    let _part_file = TestFile {
+        file_path: "COPY-TESTFILE-DESTINATION.txt.part".to_string(),
+    };
+    std::fs::write(&source.file_path, "flowd").unwrap();
+
+    let mut progress = vec![];
+    copy_to_destination(
+        &source.file_path,
+        &destination.file_path,
+        &mut |copied, total| progress.push((copied, total)),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(progress, vec![(5, 5)]);
+    assert!(!std::path::Path::new(&source.file_path).exists());
+    assert!(!std::path::Path::new("COPY-TESTFILE-DESTINATION.txt.part").exists());
+    assert_eq!(std::fs::read_to_string(&destination.file_path).unwrap(), "flowd");
+}
+
+#[tokio::test]
+async fn test_copy_to_destination_failure() {
+    let destination = TestFile {
+        file_path: "COPY-TESTFILE-MISSING-DESTINATION.txt".to_string(),
+    };
+    let part_file = TestFile {
+        file_path: "COPY-TESTFILE-MISSING-DESTINATION.txt.part".to_string(),
+    };
+    let result = copy_to_destination(
+        "COPY-TESTFILE-MISSING.txt",
+        &destination.file_path,
+        &mut |_, _| {},
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(!std::path::Path::new(&part_file.file_path).exists());
+}
+
+#[test]
+fn test_has_enough_space() {
+    let temp_file = "/tmp/flowd-test";
+    let output_file = "/tmp/flowd-test-output/file";
+
+    assert!(has_enough_space(temp_file, output_file, 0, 0, 0).unwrap());
+    assert!(!has_enough_space(temp_file, output_file, u64::MAX / 2, 0, 0).unwrap());
+
+    // Once preallocated, the rest of the file is no longer asked for on top of it
+    let available = get_available_space(std::path::Path::new("/tmp")).unwrap();
+    assert!(!has_enough_space(temp_file, output_file, available, 0, 0).unwrap());
+    assert!(has_enough_space(temp_file, output_file, available, available, 0).unwrap());
+}
+
+#[test]
+fn test_has_enough_space_resuming_preallocated() {
+    let temp_file = TestFile::new("/tmp/flowd-test-preallocated.part");
+    let output_file = "/tmp/flowd-test-output/file";
+    std::fs::write(&temp_file.file_path, "flowd").unwrap();
+
+    // The blocks reserved by an earlier attempt are still there when it resumes
+    let length = 64 * 1024 * 1024;
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&temp_file.file_path)
+        .unwrap();
+    if preallocate(&file, 5, length).is_err() {
+        // The filesystem cannot preallocate, there is nothing to count
+        return;
+    }
+    let reserved = get_reserved_space(Path::new(&temp_file.file_path)).unwrap();
+    assert!(reserved >= length);
+
+    let remaining = get_available_space(Path::new("/tmp")).unwrap() + length / 2;
+    assert!(!has_enough_space(&temp_file.file_path, output_file, remaining, 0, 0).unwrap());
+    assert!(
+        has_enough_space(&temp_file.file_path, output_file, remaining, reserved, 0).unwrap()
+    );
+}
+
+#[test]
+fn test_check_resumed_response() {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_RANGE, "bytes 100-199/200".parse().unwrap());
+    assert_eq!(
+        check_resumed_response(StatusCode::PARTIAL_CONTENT, &headers, 100, Some(200)),
+        ResumeCheck::Valid
+    );
+    assert_eq!(
+        check_resumed_response(StatusCode::PARTIAL_CONTENT, &headers, 50, Some(200)),
+        ResumeCheck::Mismatch
+    );
+    assert_eq!(
+        check_resumed_response(StatusCode::PARTIAL_CONTENT, &headers, 100, Some(300)),
+        ResumeCheck::Mismatch
+    );
+    assert_eq!(
+        check_resumed_response(StatusCode::PARTIAL_CONTENT, &HeaderMap::new(), 100, Some(200)),
+        ResumeCheck::Mismatch
+    );
+    assert_eq!(
+        check_resumed_response(StatusCode::OK, &HeaderMap::new(), 100, Some(200)),
+        ResumeCheck::FullContent
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_RANGE, "bytes */200".parse().unwrap());
+    assert_eq!(
+        check_resumed_response(StatusCode::RANGE_NOT_SATISFIABLE, &headers, 200, None),
+        ResumeCheck::Complete
+    );
+    assert_eq!(
+        check_resumed_response(StatusCode::RANGE_NOT_SATISFIABLE, &headers, 150, Some(150)),
+        ResumeCheck::Mismatch
+    );
+    assert_eq!(
+        check_resumed_response(
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            &HeaderMap::new(),
+            200,
+            Some(200)
+        ),
+        ResumeCheck::Complete
+    );
+}
+
+#[test]
+fn test_speed_meter() {
+    let start = tokio::time::Instant::now();
+    let mut speed_meter = SpeedMeter::new(1000, start);
+
+    let progress = speed_meter.get_progress(1, Some(11000));
+    assert_eq!(progress.speed, 0);
+    assert_eq!(progress.eta, -1);
+
+    for second in 1..=10 {
+        speed_meter.record(1000 + second * 1000, start + Duration::from_secs(second));
+    }
+    let progress = speed_meter.get_progress(1, Some(21000));
+    assert_eq!(progress.downloaded, 11000);
+    assert_eq!(progress.speed, 1000);
+    assert_eq!(progress.average_speed, 1000);
+    assert_eq!(progress.eta, 10);
+    assert_eq!(progress.elapsed, 10);
+
+    // The current speed only follows recent samples
+    speed_meter.record(31000, start + Duration::from_secs(20));
+    let progress = speed_meter.get_progress(1, None);
+    assert_eq!(progress.speed, 2000);
+    assert_eq!(progress.average_speed, 1500);
+    assert_eq!(progress.eta, -1);
+}
+
+#[test]
+fn test_low_speed_detector() {
+    let start = tokio::time::Instant::now();
+    let mut detector = LowSpeedDetector::new(1024, Duration::from_secs(60), 0, start);
+
+    // Nothing is decided before a full period
+    assert!(!detector.is_stalled(0, start + Duration::from_secs(30)));
+    assert!(!detector.is_stalled(120 * 1024, start + Duration::from_secs(60)));
+    assert!(detector.is_stalled(150 * 1024, start + Duration::from_secs(120)));
+
+    let mut disabled = LowSpeedDetector::new(0, Duration::from_secs(60), 0, start);
+    assert!(!disabled.is_stalled(0, start + Duration::from_secs(600)));
+}
+
+#[test]
+fn test_speed_limiter() {
+    let start = tokio::time::Instant::now();
+    let mut limiter = SpeedLimiter::new(start);
+    assert_eq!(limiter.get_delay(0, 1024 * 1024, start), Duration::ZERO);
+
+    // 3000 bytes at 1000 B/s take 3 s
+    assert_eq!(limiter.get_delay(1000, 1000, start), Duration::from_secs(1));
+    assert_eq!(
+        limiter.get_delay(1000, 2000, start + Duration::from_secs(1)),
+        Duration::from_secs(2)
+    );
+
+    // A new limit applies from the next chunk
+    let now = start + Duration::from_secs(3);
+    assert_eq!(limiter.get_delay(4000, 2000, now), Duration::from_millis(500));
+    assert_eq!(limiter.get_delay(0, 2000, now), Duration::ZERO);
+}
+
+fn test_config(categories: Vec<(&str, Category)>) -> Config {
+    Config {
+        default_directory: "/downloads".to_string(),
+        temp_directory: "/tmp".to_string(),
+        user_agent: "flowd".to_string(),
+        categories: categories
+            .into_iter()
+            .map(|(name, category)| (name.to_string(), category))
+            .collect::<HashMap<String, Category>>(),
+        max_sim_downloads: 1,
+        path_template: None,
+        conflict_policy: ConflictPolicy::Rename,
+        preallocate: false,
+        connect_timeout: 30,
+        first_byte_timeout: 60,
+        idle_timeout: 60,
+        low_speed_limit: 1024,
+        low_speed_time: 60,
+        max_retries: 5,
+        retry_delay: 10,
+        speed_limit: 0,
+    }
+}
+
+fn test_category(directory: &str, priority: i64) -> Category {
+    Category {
+        directory: directory.to_string(),
+        extensions: vec![],
+        mime_types: vec![],
+        domains: vec![],
+        url_patterns: vec![],
+        min_size: None,
+        max_size: None,
+        priority,
+        path_template: None,
+    }
+}
+
+fn test_download(url: &str) -> Download {
+    Download {
+        id: 1,
+        url: url.to_string(),
+        data_confirmed: true,
+        temp_file: "/tmp/test".to_string(),
+        ..Default::default()
+    }
+}
+
+fn test_file_info(
+    file_name: &str,
+    content_type: Option<&str>,
+    content_length: Option<u64>,
+) -> FileInfo {
+    FileInfo {
+        file_name: file_name.to_string(),
+        content_length,
+        content_type: content_type.map(str::to_string),
+        resumable: false,
+        last_modified: None,
+        etag: None,
+        last_modified_header: None,
+    }
+}
+
+#[tokio::test]
+async fn test_get_output_file_path_categories() {
+    let mut documents = test_category("/documents", 0);
+    documents.extensions = vec!["pdf".to_string()];
+    let mut builds = test_category("/builds", 10);
+    builds.domains = vec!["ci.example.com".to_string()];
+    let mut videos = test_category("/videos", 0);
+    videos.mime_types = vec!["video/*".to_string()];
+    let mut large = test_category("/large", -10);
+    large.min_size = Some(1000);
+    let config = test_config(vec![
+        ("documents", documents),
+        ("builds", builds),
+        ("videos", videos),
+        ("large", large),
+    ]);
+
+    let download = test_download("https://ci.example.com/artifacts/report.pdf");
+    let file_info = test_file_info("report.pdf", None, None);
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/builds/report.pdf"
+    );
+
+    let download = test_download("https://example.com/report.PDF");
+    let file_info = test_file_info("report.PDF", None, None);
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/documents/report.PDF"
+    );
+
+    let download = test_download("https://example.com/stream");
+    let file_info = test_file_info("stream", Some("video/mp4"), None);
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/videos/stream"
+    );
+
+    let download = test_download("https://example.com/blob");
+    let file_info = test_file_info("blob", None, Some(2000));
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/large/blob"
+    );
+
+    let file_info = test_file_info("blob", None, Some(10));
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/downloads/blob"
+    );
+}
+
+#[tokio::test]
+async fn test_get_output_file_path_category_priority() {
+    let mut archives = test_category("/archives", 0);
+    archives.extensions = vec!["zip".to_string()];
+    let mut nightly = test_category("/nightly", 5);
+    nightly.url_patterns = vec!["(".to_string(), r"/nightly/.*\.zip$".to_string()];
+    let config = test_config(vec![("archives", archives), ("nightly", nightly)]);
+
+    // Checked twice so the second match uses the compiled patterns, the invalid one is skipped
+    for _ in 0..2 {
+        let download = test_download("https://example.com/nightly/build.zip");
+        let file_info = test_file_info("build.zip", None, None);
+        assert_eq!(
+            get_output_file_path(&download, &file_info, &config).await,
+            "/nightly/build.zip"
+        );
+    }
+}
+
+#[test]
+fn test_expand_path_template() {
+    let mut download = test_download("https://ci.example.com/builds/app.tar.gz");
+    download.id = 42;
+    download.date_added = 1700000000;
+    let file_info = test_file_info("app.tar.gz", Some("application/gzip"), None);
+
+    assert_eq!(
+        expand_path_template(
+            "{category}/{host}/{date:%Y}/{filename}",
+            &download,
+            &file_info,
+            "builds"
+        ),
+        "builds/ci.example.com/2023/app.tar.gz"
+    );
+    assert_eq!(
+        expand_path_template("{mime}/{id}-{stem}.{ext}", &download, &file_info, ""),
+        "application/gzip/42-app.tar.gz"
+    );
+    assert_eq!(
+        expand_path_template("{category}/{filename}", &download, &file_info, ""),
+        "app.tar.gz"
+    );
+    assert_eq!(
+        expand_path_template("/archive/{unknown}/{filename}", &download, &file_info, ""),
+        "/archive/{unknown}/app.tar.gz"
+    );
+
+    let file_info = test_file_info("../../etc/passwd", None, None);
+    assert_eq!(
+        expand_path_template("{filename}", &download, &file_info, ""),
+        ".._.._etc_passwd"
+    );
+}
+
+#[tokio::test]
+async fn test_get_output_file_path_template() {
+    let mut builds = test_category("/builds", 0);
+    builds.domains = vec!["ci.example.com".to_string()];
+    builds.path_template = Some("{host}/{filename}".to_string());
+    let mut config = test_config(vec![("builds", builds)]);
+    config.path_template = Some("{category}/{filename}".to_string());
+
+    let download = test_download("https://ci.example.com/app.zip");
+    let file_info = test_file_info("app.zip", None, None);
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/builds/ci.example.com/app.zip"
+    );
+
+    let download = test_download("https://example.com/app.zip");
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/downloads/app.zip"
+    );
+
+    // Names sent by the server are not expanded and cannot leave the directory
+    for file_name in ["~", "~/.bashrc", "../app.zip"] {
+        let file_info = test_file_info(file_name, None, None);
+        let file_path = get_output_file_path(&download, &file_info, &config).await;
+        assert!(file_path.starts_with("/downloads/"), "{}", file_path);
+    }
+    let file_info = test_file_info("~", None, None);
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/downloads/~"
+    );
+
+    config.path_template = Some("../{filename}".to_string());
+    let file_info = test_file_info("app.zip", None, None);
+    assert_eq!(
+        get_output_file_path(&download, &file_info, &config).await,
+        "/downloads/app.zip"
+    );
+}
+
+#[test]
+fn test_get_url_host() {
+    assert_eq!(
+        get_url_host("https://cdn.example.com:8080/files/a.zip?x=1"),
+        "cdn.example.com"
+    );
+    assert_eq!(get_url_host("not a url"), "");
+}
+
+#[test]
+fn test_bulk_action_applies_to() {
+    assert!(BulkAction::Pause.applies_to(&DownloadStatus::InProgress));
+    assert!(!BulkAction::Pause.applies_to(&DownloadStatus::Completed));
+    assert!(BulkAction::Resume.applies_to(&DownloadStatus::Paused));
+    assert!(!BulkAction::Resume.applies_to(&DownloadStatus::ServerError));
+    assert!(BulkAction::Retry.applies_to(&DownloadStatus::ServerError));
+    assert!(!BulkAction::Retry.applies_to(&DownloadStatus::Paused));
+    assert!(!BulkAction::Resume.applies_to(&DownloadStatus::Canceled));
+    assert!(BulkAction::Delete.applies_to(&DownloadStatus::Completed));
+    assert!(!BulkAction::Delete.applies_to(&DownloadStatus::InProgress));
+    assert!(!BulkAction::Cancel.applies_to(&DownloadStatus::Canceled));
+}
+
+#[test]
+fn test_download_filter_matches() {
+    let mut download = test_download("https://media.example.com/clip.mp4");
+    download.category = "videos".to_string();
+
+    assert!(DownloadFilter::default().matches(&download));
+    let filter = DownloadFilter {
+        status: Some("pending".to_string()),
+        category: Some("videos".to_string()),
+        host: Some("Media.Example.com".to_string()),
+        ..Default::default()
+    };
+    assert!(filter.matches(&download));
+    let filter = DownloadFilter {
+        status: Some("paused".to_string()),
+        ..Default::default()
+    };
+    assert!(!filter.matches(&download));
+    let filter = DownloadFilter {
+        host: Some("example.com".to_string()),
+        ..Default::default()
+    };
+    assert!(!filter.matches(&download));
+    let filter = DownloadFilter {
+        category: Some(String::new()),
+        ..Default::default()
+    };
+    assert!(!filter.matches(&download));
+}
+
+#[test]
+fn test_get_group_status() {
+    use DownloadStatus::*;
+
+    assert_eq!(get_group_status(&[]).get_string(), "pending");
+    assert_eq!(
+        get_group_status(&[Completed, InProgress, Paused]).get_string(),
+        "in_progress"
+    );
+    assert_eq!(
+        get_group_status(&[Completed, Retrying, ServerError]).get_string(),
+        "pending"
+    );
+    assert_eq!(
+        get_group_status(&[Completed, ServerError, Paused]).get_string(),
+        "server_error"
+    );
+    assert_eq!(get_group_status(&[Completed, Paused]).get_string(), "paused");
+    assert_eq!(
+        get_group_status(&[Completed, Canceled]).get_string(),
+        "completed"
+    );
+    assert_eq!(get_group_status(&[Canceled]).get_string(), "canceled");
+}
+
+#[test]
+fn test_group_aggregate() {
+    let mut first = test_download("https://example.com/data.part1.rar");
+    first.status = DownloadStatus::Completed;
+    first.size = Some(100);
+    let mut second = test_download("https://example.com/data.part2.rar");
+    second.status = DownloadStatus::Paused;
+    second.size = Some(100);
+    second.downloaded_bytes = 40;
+    let third = test_download("https://example.com/data.part3.rar");
+
+    let mut group = DownloadGroup::new("data", None);
+    group.aggregate(&[first, second, third]);
+    assert_eq!(group.status, "pending");
+    assert_eq!(group.size, 200);
+    assert_eq!(group.downloaded, 140);
+    assert_eq!(group.download_count, 3);
+    assert_eq!(group.completed_count, 1);
+}
+
+#[test]
+fn test_get_fts_query() {
+    assert_eq!(get_fts_query(""), "");
+    assert_eq!(
+        get_fts_query("  dataset \"2024\" http://x "),
+        r#""dataset"* """2024"""* "http://x"*"#
+    );
+}
+
+/// Opens an in-memory database with every migration applied
+fn test_connection() -> rusqlite::Connection {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    migrate(&mut connection).unwrap();
+    connection
+}
+
+/// Applies the migrations from version `from` to version `to`, both included
+fn apply_test_migrations(connection: &rusqlite::Connection, from: u32, to: u32) {
+    let migrations_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/resources/db/migrations");
+    let mut migrations = std::fs::read_dir(migrations_dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let version = path.file_stem().unwrap().to_string_lossy().parse::<u32>().unwrap();
+            (version, path)
+        })
+        .filter(|(version, _)| (from..=to).contains(version))
+        .collect::<Vec<_>>();
+    migrations.sort();
+    for (_, migration) in migrations {
+        connection
+            .execute_batch(&std::fs::read_to_string(migration).unwrap())
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_migrate() {
+    let migrations_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/resources/db/migrations");
+    let files = std::fs::read_dir(migrations_dir).unwrap().count();
+    assert_eq!(SCHEMA_VERSION as usize, files);
+
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    assert_eq!(migrate(&mut connection).unwrap(), SCHEMA_VERSION);
+    assert_eq!(migrate(&mut connection).unwrap(), 0);
+
+    // Only the missing migrations are applied
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    apply_test_migrations(&connection, 1, 4);
+    assert_eq!(migrate(&mut connection).unwrap(), SCHEMA_VERSION - 4);
+
+    connection
+        .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+        .unwrap();
+    assert!(matches!(
+        migrate(&mut connection),
+        Err(DBError::NewerSchema(version, SCHEMA_VERSION)) if version == SCHEMA_VERSION + 1
+    ));
+}
+
+#[test]
+fn test_search_index_follows_downloads() {
+    let connection = test_connection();
+
+    let search = |query: &str| -> Vec<i64> {
+        let mut stmt = connection
+            .prepare("SELECT rowid FROM downloads_search WHERE downloads_search MATCH ?1")
+            .unwrap();
+        stmt.query_map([get_fts_query(query)], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    };
+
+    connection
+        .execute_batch(
+            "
+            INSERT INTO downloads (id, url, status, data_confirmed, detected_output_file,
+                output_file, temp_file, resumable, date_added)
+            VALUES (1, 'https://example.com/census.csv', 'pending', 1,
+                '/data/census.csv', NULL, '/tmp/a', 1, 0);
+            INSERT INTO download_tags (download_id, tag) VALUES (1, 'statistics');
+            INSERT INTO download_notes (download_id, note) VALUES (1, 'Requested by finance');
+            ",
+        )
+        .unwrap();
+    assert_eq!(search("census"), vec![1]);
+    assert_eq!(search("stat"), vec![1]);
+    assert_eq!(search("finance census"), vec![1]);
+    assert!(search("finance other").is_empty());
+
+    connection
+        .execute_batch(
+            "
+            DELETE FROM download_tags WHERE download_id = 1;
+            UPDATE download_notes SET note = 'Archived' WHERE download_id = 1;
+            UPDATE downloads SET output_file = '/data/people.csv' WHERE id = 1;
+            ",
+        )
+        .unwrap();
+    assert!(search("statistics").is_empty());
+    assert!(search("finance").is_empty());
+    assert_eq!(search("people archived"), vec![1]);
+
+    connection
+        .execute("DELETE FROM downloads WHERE id = 1", [])
+        .unwrap();
+    assert!(search("example").is_empty());
+}
+
+#[test]
+fn test_list_downloads() {
+    let mut connection = test_connection();
+    let mut documents = test_category("/documents", 0);
+    documents.extensions = vec!["pdf".to_string()];
+    let config = test_config(vec![("documents", documents)]);
+    connection
+        .execute_batch(
+            "
+            INSERT INTO downloads (id, url, status, data_confirmed, detected_output_file,
+                output_file, temp_file, resumable, date_added, size)
+            VALUES
+                (1, 'https://a.example.com/report.pdf', 'completed', 1,
+                    '/data/report.pdf', NULL, '/tmp/1', 1, 100, 5000),
+                (2, 'https://b.example.com:8080/video.mp4', 'in_progress', 1,
+                    '/data/video.mp4', NULL, '/tmp/2', 1, 200, 90000),
+                (3, 'https://a.example.com/notes.txt', 'paused', 1,
+                    '/data/notes.txt', NULL, '/tmp/3', 1, 300, NULL),
+                (4, 'https://a_example.com/report_draft.pdf', 'pending', 1,
+                    '/data/report_draft.pdf', NULL, '/tmp/4', 1, 400, 700);
+            ",
+        )
+        .unwrap();
+    sync_download_categories(&mut connection, &config).unwrap();
+
+    let list = |filter: &DownloadFilter, sort: SortKey, descending: bool, offset, limit| {
+        let (downloads, total) =
+            query_download_list(&connection, filter, sort, descending, offset, limit)
+                .unwrap();
+        let ids = downloads
+            .iter()
+            .map(|download| download.id)
+            .collect::<Vec<i64>>();
+        (ids, total)
+    };
+    let all = DownloadFilter::default();
+
+    assert_eq!(list(&all, SortKey::DateAdded, true, 0, 0), (vec![4, 3, 2, 1], 4));
+    assert_eq!(list(&all, SortKey::DateAdded, false, 1, 2), (vec![2, 3], 4));
+    assert_eq!(list(&all, SortKey::Status, false, 0, 0), (vec![2, 4, 3, 1], 4));
+    assert_eq!(list(&all, SortKey::Url, false, 0, 1), (vec![3], 4));
+
+    let filter = DownloadFilter {
+        statuses: Some(vec!["paused".to_string(), "completed".to_string()]),
+        ..Default::default()
+    };
+    assert_eq!(list(&filter, SortKey::DateAdded, true, 0, 0), (vec![3, 1], 2));
+
+    let filter = DownloadFilter {
+        host: Some("a.example.com".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(list(&filter, SortKey::DateAdded, true, 0, 0), (vec![3, 1], 2));
+    let filter = DownloadFilter {
+        host: Some("b.example.com".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(list(&filter, SortKey::DateAdded, true, 0, 0), (vec![2], 1));
+
+    let filter = DownloadFilter {
+        date_from: Some(200),
+        date_to: Some(300),
+        ..Default::default()
+    };
+    assert_eq!(list(&filter, SortKey::DateAdded, true, 0, 0), (vec![3, 2], 2));
+
+    // Downloads of unknown size are left out of size ranges
+    let filter = DownloadFilter {
+        min_size: Some(0),
+        max_size: Some(10000),
+        ..Default::default()
+    };
+    assert_eq!(list(&filter, SortKey::Size, false, 0, 0), (vec![4, 1], 2));
+
+    let filter = DownloadFilter {
+        text: Some("report".to_string()),
+        status: Some("pending".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(list(&filter, SortKey::Relevance, false, 0, 0), (vec![4], 1));
+
+    let filter = DownloadFilter {
+        category: Some("documents".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(list(&filter, SortKey::DateAdded, true, 1, 1), (vec![1], 2));
+}
+
+#[test]
+fn test_sync_download_categories() {
+    let mut connection = test_connection();
+    connection
+        .execute_batch(
+            "
+            INSERT INTO downloads (id, url, status, data_confirmed, detected_output_file,
+                output_file, temp_file, resumable, date_added, category, content_type)
+            VALUES
+                (1, 'https://example.com/a.pdf', 'completed', 1,
+                    '/data/a.pdf', NULL, '/tmp/1', 1, 0, '', NULL),
+                (2, 'https://example.com/b.mp4', 'pending', 1,
+                    NULL, NULL, '/tmp/2', 1, 0, '', NULL),
+                (3, 'https://example.com/get?id=3', 'completed', 1,
+                    '/data/report', NULL, '/tmp/3', 1, 0, 'documents', 'application/pdf');
+            ",
+        )
+        .unwrap();
+    let categories = |connection: &rusqlite::Connection| -> Vec<String> {
+        let mut stmt = connection
+            .prepare("SELECT category FROM downloads ORDER BY id")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    };
+
+    let mut documents = test_category("/documents", 0);
+    documents.extensions = vec!["pdf".to_string()];
+    documents.mime_types = vec!["application/pdf".to_string()];
+    let config = test_config(vec![("documents", documents.clone())]);
+    // The stored content type keeps the category picked from it at download time
+    assert_eq!(sync_download_categories(&mut connection, &config).unwrap(), 1);
+    assert_eq!(categories(&connection), vec!["documents", "", "documents"]);
+
+    // Unchanged categories are not evaluated again
+    connection
+        .execute("UPDATE downloads SET category = 'stale' WHERE id = 2", [])
+        .unwrap();
+    assert_eq!(sync_download_categories(&mut connection, &config).unwrap(), 0);
+    assert_eq!(categories(&connection), vec!["documents", "stale", "documents"]);
+
+    let mut videos = test_category("/videos", 0);
+    videos.extensions = vec!["mp4".to_string()];
+    let config = test_config(vec![("documents", documents), ("videos", videos)]);
+    assert_eq!(sync_download_categories(&mut connection, &config).unwrap(), 1);
+    assert_eq!(categories(&connection), vec!["documents", "videos", "documents"]);
+}
+
+#[test]
+fn test_typed_schema_migration() {
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    apply_test_migrations(&connection, 1, 8);
+    connection
+        .execute_batch(
+            "
+            INSERT INTO downloads (id, url, status, data_confirmed, detected_output_file,
+                output_file, temp_file, resumable, date_added, date_completed, size,
+                conflict_policy, etag, last_modified, output_directory, group_id)
+            VALUES
+                (1, 'https://example.com/a.zip', 'completed', 'true', '/data/a.zip', 'NULL',
+                    '/tmp/1', 'false', 10, '20', '300', 'rename', '\"abc\"', 'NULL',
+                    'NULL', 'NULL'),
+                (2, 'https://example.com/b.zip', 'lost', 'false', 'NULL', 'NULL',
+                    '/tmp/2', 'true', 30, 'NULL', 'NULL', 'NULL', 'NULL', 'NULL',
+                    '/data', '4');
+            ",
+        )
+        .unwrap();
+    apply_test_migrations(&connection, 9, u32::MAX);
+
+    let downloads = query_downloads(&connection, "SELECT * FROM downloads ORDER BY id", [])
+        .unwrap();
+    let first = &downloads[0];
+    assert!(matches!(first.status, DownloadStatus::Completed));
+    assert!(first.data_confirmed);
+    assert!(!first.resumable);
+    assert_eq!(first.detected_output_file.as_deref(), Some("/data/a.zip"));
+    assert_eq!(first.output_file, None);
+    assert_eq!(first.date_completed, Some(20));
+    assert_eq!(first.size, Some(300));
+    assert_eq!(first.conflict_policy, Some(ConflictPolicy::Rename));
+    assert_eq!(first.etag.as_deref(), Some("\"abc\""));
+    assert_eq!(first.last_modified, None);
+    assert_eq!(first.group_id, None);
+    let second = &downloads[1];
+    assert!(matches!(second.status, DownloadStatus::UnknownError));
+    assert!(!second.data_confirmed);
+    assert!(second.resumable);
+    assert_eq!(second.date_completed, None);
+    assert_eq!(second.size, None);
+    assert_eq!(second.output_directory.as_deref(), Some("/data"));
+    assert_eq!(second.group_id, Some(4));
+
+    assert!(connection
+        .execute("UPDATE downloads SET status = 'lost' WHERE id = 1", [])
+        .is_err());
+
+    // A corrupt value is reported instead of crashing
+    connection
+        .execute("UPDATE downloads SET conflict_policy = 'sometimes' WHERE id = 1", [])
+        .unwrap();
+    assert!(query_downloads(&connection, "SELECT * FROM downloads", []).is_err());
+}
+
+#[test]
+fn test_concurrent_writes() {
+    let db_file = TestFile::new(
+        &std::env::temp_dir()
+            .join("flowd-test-concurrent-writes.db")
+            .to_string_lossy(),
+    );
+    let mut connection = open_connection(&db_file.file_path).unwrap();
+    migrate(&mut connection).unwrap();
+    connection
+        .execute_batch(
+            "
+            INSERT INTO downloads (id, url, status, data_confirmed, temp_file, resumable,
+                date_added)
+            VALUES (1, 'https://example.com/a.iso', 'in_progress', 1, '/tmp/1', 1, 0);
+            ",
+        )
+        .unwrap();
+
+    // Each thread stands for a download saving its progress
+    let writers = (0..20)
+        .map(|writer| {
+            let path = db_file.file_path.clone();
+            std::thread::spawn(move || {
+                let connection = open_connection(&path).unwrap();
+                for bytes in 0..50 {
+                    connection
+                        .prepare_cached("UPDATE downloads SET downloaded_bytes = ?1 WHERE id = 1")
+                        .unwrap()
+                        .execute([writer * 1000 + bytes])
+                        .unwrap();
+                    query_downloads(&connection, "SELECT * FROM downloads", []).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let journal_mode: String = connection
+        .pragma_query_value(None, "journal_mode", |row| row.get(0))
+        .unwrap();
+    assert_eq!(journal_mode, "wal");
+    drop(connection);
+    for suffix in ["-wal", "-shm"] {
+        _ = std::fs::remove_file(format!("{}{}", db_file.file_path, suffix));
+    }
+}
@@ -1,15 +1,31 @@
-use std::path::Path;
+use std::path::{Component, Path};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, TimeZone};
 
 use mime_guess::get_mime_extensions_str;
 use rand::{distributions::Alphanumeric, Rng};
 use regex::Regex;
-use reqwest::{header::HeaderMap, Url};
-use tokio::{fs::{self, OpenOptions}, io};
+use reqwest::{header::HeaderMap, StatusCode, Url};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{self, AsyncReadExt, AsyncWriteExt},
+};
 use urlencoding::decode;
 
-use crate::{core::config::Config, utils::{self, path::expand}};
+use crate::{
+    core::{
+        category::{find_category, CategoryMatch, CategoryQuery},
+        config::Config,
+    },
+    utils::{
+        self,
+        fs::{get_available_space, is_same_filesystem},
+        path::{expand, sanitize_path_segment},
+    },
+};
 
-use super::FileInfo;
+use super::{Download, FileInfo};
 
 /// This function is used to extract file info from headers and fallbacks to url
 ///
@@ -27,7 +43,7 @@ pub fn get_file_info_from_headers(url: &str, headers: &HeaderMap) -> FileInfo {
     let content_type = headers.get("content-type").and_then(|ct| {
         ct.to_str()
             .ok()
-            .and_then(|ct| Some(ct.split(";").collect::<Vec<&str>>()[0].to_string()))
+            .map(|ct| ct.split(";").collect::<Vec<&str>>()[0].to_string())
     });
 
     // Get file name if available
@@ -38,13 +54,10 @@ pub fn get_file_info_from_headers(url: &str, headers: &HeaderMap) -> FileInfo {
                 if let Some(index) = ct.find("filename=\"") {
                     Some(index + 10)
                 } else {
-                    if let Some(index) = ct.find("filename=") {
-                        Some(index + 9)
-                    } else {
-                        None
-                    }
+                    ct.find("filename=").map(|index| index + 9)
                 }
-            }).and_then(|i| Some(ct.to_str().unwrap()[i..ct.len() - 1].to_string()))
+            })
+            .map(|i| ct.to_str().unwrap()[i..ct.len() - 1].to_string())
     });
 
     // Check if file is resumable
@@ -53,15 +66,15 @@ pub fn get_file_info_from_headers(url: &str, headers: &HeaderMap) -> FileInfo {
         .and_then(|ar| {
             ar.to_str()
                 .ok()
-                .and_then(|ar| Some(ar.to_lowercase() == "bytes"))
+                .map(|ar| ar.to_lowercase() == "bytes")
         })
         .unwrap_or(false);
 
     let ct_extension = match &content_type {
         Some(ct) => {
-            get_mime_extensions_str(&ct)
+            get_mime_extensions_str(ct)
                 .and_then(|ext| {
-                    if ext.len() > 0 {
+                    if !ext.is_empty() {
                         Some(ext[0])
                     } else { 
                         None 
@@ -79,10 +92,10 @@ pub fn get_file_info_from_headers(url: &str, headers: &HeaderMap) -> FileInfo {
                 Url::parse(url)
                     .unwrap()
                     .path_segments()
-                    .and_then(|segments| {
-                        let last_segment = segments.last();
+                    .and_then(|mut segments| {
+                        let last_segment = segments.next_back();
                         if let Some(seg) = last_segment {
-                            if seg == "" {
+                            if seg.is_empty() {
                                 return None;
                             }
                             last_segment
@@ -113,45 +126,294 @@ pub fn get_file_info_from_headers(url: &str, headers: &HeaderMap) -> FileInfo {
         }),
         content_type,
         resumable,
+        last_modified: headers.get("last-modified").and_then(|last_modified| {
+            last_modified
+                .to_str()
+                .ok()
+                .and_then(|last_modified| DateTime::parse_from_rfc2822(last_modified).ok())
+                .map(|last_modified| last_modified.timestamp())
+        }),
+        etag: get_header_string(headers, "etag"),
+        last_modified_header: get_header_string(headers, "last-modified"),
+    }
+}
+
+fn get_header_string(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Outcome of the validation of a response to a resumed download request
+#[derive(Debug, PartialEq)]
+pub enum ResumeCheck {
+    /// The response continues the temp file
+    Valid,
+    /// The temp file already holds the whole file
+    Complete,
+    /// The server sent the whole file, it should be written from the start
+    FullContent,
+    /// The response does not match the temp file
+    Mismatch,
+}
+
+/// Checks that a response to a range request can be appended to the temp file
+///
+/// # Arguments
+///
+/// * `status` - The response status
+/// * `headers` - The headers of the response
+/// * `start_byte` - The requested start byte
+/// * `size` - The full size of the file if known
+///
+/// # Returns
+///
+/// * `ResumeCheck` - How the response should be handled
+pub fn check_resumed_response(
+    status: StatusCode,
+    headers: &HeaderMap,
+    start_byte: u64,
+    size: Option<u64>,
+) -> ResumeCheck {
+    let content_range = get_header_string(headers, "content-range")
+        .as_deref()
+        .and_then(parse_content_range);
+    match status {
+        StatusCode::PARTIAL_CONTENT => match content_range {
+            Some((Some(range_start), total))
+                if range_start == start_byte
+                    && (total.is_none() || size.is_none() || total == size) =>
+            {
+                ResumeCheck::Valid
+            }
+            _ => ResumeCheck::Mismatch,
+        },
+        StatusCode::OK => ResumeCheck::FullContent,
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            let total = content_range.and_then(|(_, total)| total).or(size);
+            if total == Some(start_byte) {
+                ResumeCheck::Complete
+            } else {
+                ResumeCheck::Mismatch
+            }
+        }
+        _ => ResumeCheck::Valid,
     }
 }
 
+/// Parses a `Content-Range` header like `bytes 100-199/200` or `bytes */200`
+///
+/// # Returns
+///
+/// * `Option<(Option<u64>, Option<u64>)>` - The range start and the total size when known
+fn parse_content_range(content_range: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let (range, total) = content_range.strip_prefix("bytes ")?.split_once('/')?;
+    let start = match range {
+        "*" => None,
+        range => Some(range.split_once('-')?.0.parse::<u64>().ok()?),
+    };
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse::<u64>().ok()?),
+    };
+    Some((start, total))
+}
+
 /// This function detects the file category and gets the output file path according to config
 ///
 /// # Arguments
 ///
+/// * `download` - The download the file belongs to
 /// * `file_info` - The file info
 /// * `config` - The config
 ///
 /// # Returns
 ///
 /// * `String` - The output file path
-pub async fn get_output_file_path(file_info: &FileInfo, config: &Config) -> String {
-    let file_extension = file_info
-        .file_name
-        .split(".")
-        .last()
-        .and_then(|ext| Some(ext))
-        .unwrap_or("");
-
-    let mut file_directory = "".to_string();
-    if file_extension != "" {
-        'outer: for (_, category) in &config.categories {
-            let mut extensions_iter = category.extensions.iter();
-            while let Some(extension) = extensions_iter.next() {
-                if file_info.file_name.ends_with(extension) {
-                    file_directory = category.directory.clone();
-                    break 'outer;
-                }
+pub async fn get_output_file_path(
+    download: &Download,
+    file_info: &FileInfo,
+    config: &Config,
+) -> String {
+    // A directory picked by the user keeps the file out of the category tree
+    if let Some(directory) = &download.output_directory {
+        let file_path = Path::new(&utils::path::expand(directory))
+            .join(sanitize_path_segment(&file_info.file_name));
+        return file_path.to_str().unwrap().to_string();
+    }
+
+    let category_match = get_file_category(download, file_info, config);
+
+    let path_template = config
+        .categories
+        .get(&category_match.category)
+        .and_then(|category| category.path_template.as_deref())
+        .or(config.path_template.as_deref())
+        .unwrap_or(DEFAULT_PATH_TEMPLATE);
+    // Only the configured template is expanded, a `~` sent by the server stays a plain name
+    let path_template = utils::path::expand(path_template);
+    let relative_path =
+        expand_path_template(&path_template, download, file_info, &category_match.category);
+
+    let directory = utils::path::expand(&category_match.directory);
+    let file_path = Path::new(&directory).join(&relative_path);
+    let leaves_directory = file_path
+        .components()
+        .any(|component| component == Component::ParentDir)
+        || !(path_template.starts_with('/') || file_path.starts_with(&directory));
+    if leaves_directory {
+        log::warn!(
+            "Output path {} is outside of {}, using the file name only",
+            file_path.display(),
+            directory
+        );
+        let file_path = Path::new(&directory).join(sanitize_path_segment(&file_info.file_name));
+        return file_path.to_str().unwrap().to_string();
+    }
+    file_path.to_str().unwrap().to_string()
+}
+
+/// Finds the category of the remote file of a download
+pub fn get_file_category(
+    download: &Download,
+    file_info: &FileInfo,
+    config: &Config,
+) -> CategoryMatch {
+    find_category(
+        &CategoryQuery {
+            url: &download.url,
+            file_name: &file_info.file_name,
+            content_type: file_info.content_type.as_deref(),
+            size: file_info.content_length.or(download.size),
+        },
+        config,
+    )
+}
+
+const DEFAULT_PATH_TEMPLATE: &str = "{filename}";
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Expands the placeholders of an output path template
+///
+/// Supported placeholders are `{filename}`, `{stem}`, `{ext}`, `{host}`, `{category}`,
+/// `{mime}`, `{id}` and `{date}`, which takes an optional strftime format like
+/// `{date:%Y-%m}`. Unknown placeholders are kept as is and empty path segments are dropped.
+///
+/// # Arguments
+///
+/// * `template` - The template to expand
+/// * `download` - The download the file belongs to
+/// * `file_info` - The file info
+/// * `category` - The category name of the file, empty if it has none
+///
+/// # Returns
+///
+/// * `String` - The expanded path
+pub fn expand_path_template(
+    template: &str,
+    download: &Download,
+    file_info: &FileInfo,
+    category: &str,
+) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let placeholder = &rest[start + 1..start + length];
+        match get_placeholder_value(placeholder, download, file_info, category) {
+            Some(value) => expanded.push_str(&value),
+            None => {
+                log::warn!("Unknown path template placeholder `{{{}}}`", placeholder);
+                expanded.push_str(&rest[start..=start + length]);
             }
         }
+        rest = &rest[start + length + 1..];
     }
-    if file_directory.len() == 0 {
-        file_directory = config.default_directory.clone();
+    expanded.push_str(rest);
+
+    let is_absolute = template.starts_with('/');
+    let segments = expanded
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<&str>>()
+        .join("/");
+    if is_absolute {
+        format!("/{}", segments)
+    } else {
+        segments
     }
+}
+
+fn get_placeholder_value(
+    placeholder: &str,
+    download: &Download,
+    file_info: &FileInfo,
+    category: &str,
+) -> Option<String> {
+    let (name, argument) = match placeholder.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (placeholder, None),
+    };
+    let file_path = Path::new(&file_info.file_name);
+
+    let value = match name {
+        "filename" => sanitize_path_segment(&file_info.file_name),
+        "stem" => sanitize_path_segment(
+            &file_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        ),
+        "ext" => sanitize_path_segment(
+            &file_path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        ),
+        "host" => sanitize_path_segment(&get_url_host(&download.url)),
+        "category" => sanitize_path_segment(category),
+        // MIME types keep their slash so `video/mp4` becomes two directories
+        "mime" => file_info
+            .content_type
+            .as_deref()
+            .unwrap_or_default()
+            .split('/')
+            .map(sanitize_path_segment)
+            .collect::<Vec<String>>()
+            .join("/"),
+        "id" => download.id.to_string(),
+        "date" => format_date(download.date_added, argument.unwrap_or(DEFAULT_DATE_FORMAT)),
+        _ => return None,
+    };
+    Some(value)
+}
 
-    let file_path = Path::new(&file_directory).join(&file_info.file_name);
-    utils::path::expand(file_path.to_str().unwrap())
+/// Returns the host of the URL or an empty string if it has none
+pub fn get_url_host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn format_date(timestamp: i64, format: &str) -> String {
+    let format = if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        log::warn!("Invalid date format `{}` in path template", format);
+        DEFAULT_DATE_FORMAT
+    } else {
+        format
+    };
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|date| date.format(format).to_string())
+        .unwrap_or_default()
 }
 
 pub async fn get_temp_file(config: &Config) -> String {
@@ -193,40 +455,205 @@ pub fn get_conflict_free_file_path(file_path: &str) -> String {
     ];
 
     let file_path = Path::new(file_path);
-    let file_path_parent = file_path.parent().unwrap();
+    let file_path_parent = file_path.parent().unwrap_or(Path::new(""));
+    let file_name = file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
 
-    let mut special_extension = "";
-    let ends_with_special_extension = special_extensions.iter().any(|ext| {
-        special_extension = ext;
-        file_path.to_str().unwrap().ends_with(ext)
-    });
-    
-    let mut file_path_split = if ends_with_special_extension {
-        let file_stem = file_path.to_str().unwrap().split(special_extension).collect::<Vec<&str>>();
-        let file_stem = file_stem[0];
-        vec![file_stem, &special_extension[1..]]
-    } else {
-        vec![file_path.file_stem().unwrap().to_str().unwrap(), file_path.extension().unwrap().to_str().unwrap()]
+    // Dotfiles and files without extension keep an empty extension
+    let special_extension = special_extensions
+        .iter()
+        .find(|ext| file_name.len() > ext.len() && file_name.ends_with(*ext));
+    let (mut file_stem, file_extension) = match special_extension {
+        Some(ext) => file_name.split_at(file_name.len() - ext.len()),
+        None => match file_name.rfind('.') {
+            Some(index) if index > 0 => file_name.split_at(index),
+            _ => (file_name.as_str(), ""),
+        },
     };
 
-    if Regex::new(r" \(\d+\)$").unwrap().is_match(file_path_split[0]) {
-        let conflict_number_index = file_path_split[0].rfind(" (").unwrap();
-        file_path_split[0] = &file_path_split[0][..conflict_number_index];
+    if Regex::new(r" \(\d+\)$").unwrap().is_match(file_stem) {
+        let conflict_number_index = file_stem.rfind(" (").unwrap();
+        file_stem = &file_stem[..conflict_number_index];
     }
 
     let mut i = 1;
-    
-    let mut new_file_path = file_path_parent.join(file_path_split.join("."));
+
+    let mut new_file_path = file_path_parent.join(format!("{}{}", file_stem, file_extension));
     while new_file_path.exists() {
-        let file_stem = file_path_split[0];
-        let file_extension = file_path_split[1];
-        new_file_path = file_path_parent.join(format!("{} ({}).{}", file_stem, i, file_extension));
+        new_file_path = file_path_parent.join(format!("{} ({}){}", file_stem, i, file_extension));
         i += 1;
     }
 
     new_file_path.to_str().unwrap().to_string()
 }
 
+/// Checks if two files have the same size and content
+///
+/// # Arguments
+///
+/// * `first_path` - The path of the first file
+/// * `second_path` - The path of the second file
+///
+/// # Returns
+///
+/// * `bool` - Whether the files are identical
+pub async fn files_are_identical(first_path: &str, second_path: &str) -> Result<bool, io::Error> {
+    if fs::metadata(first_path).await?.len() != fs::metadata(second_path).await?.len() {
+        return Ok(false);
+    }
+
+    let mut first_file = File::open(first_path).await?;
+    let mut second_file = File::open(second_path).await?;
+    let mut first_buffer = vec![0; 64 * 1024];
+    let mut second_buffer = vec![0; 64 * 1024];
+    loop {
+        let read = first_file.read(&mut first_buffer).await?;
+        if read == 0 {
+            return Ok(true);
+        }
+        second_file.read_exact(&mut second_buffer[..read]).await?;
+        if first_buffer[..read] != second_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Moves a file and syncs it with its new directory to disk. When the destination is on
+/// another filesystem, the file is copied instead (see `copy_to_destination`). Only a failure
+/// to deliver the file is returned, a failed sync of the directory is logged.
+///
+/// # Arguments
+///
+/// * `source` - The path of the file to move
+/// * `destination` - The path to move the file to
+/// * `on_progress` - Called with the copied and total bytes when copying
+pub async fn move_file<F>(
+    source: &str,
+    destination: &str,
+    mut on_progress: F,
+) -> Result<(), io::Error>
+where
+    F: FnMut(u64, u64),
+{
+    File::open(source).await?.sync_all().await?;
+
+    match fs::rename(source, destination).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            log::debug!("Copying {} to {} across filesystems", source, destination);
+            copy_to_destination(source, destination, &mut on_progress).await?;
+        }
+        Err(e) => return Err(e),
+    }
+
+    // The file is delivered at this point, a failed directory sync only weakens durability
+    let destination_directory = Path::new(destination)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let sync_result = match File::open(destination_directory).await {
+        Ok(directory) => directory.sync_all().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = sync_result {
+        log::warn!(
+            "Could not sync directory {}: {}",
+            destination_directory.to_string_lossy(),
+            e
+        );
+    }
+    Ok(())
+}
+
+/// Copies a file to a `.part` file next to the destination, syncs it, renames it to the
+/// destination and removes the source. The `.part` file is removed if any step fails so
+/// the destination never holds a partial file.
+///
+/// # Arguments
+///
+/// * `source` - The path of the file to copy
+/// * `destination` - The path to copy the file to
+/// * `on_progress` - Called with the copied and total bytes after each chunk
+pub async fn copy_to_destination<F>(
+    source: &str,
+    destination: &str,
+    on_progress: &mut F,
+) -> Result<(), io::Error>
+where
+    F: FnMut(u64, u64),
+{
+    let part_file_path = format!("{}.part", destination);
+
+    let copy_result = async {
+        let mut source_file = File::open(source).await?;
+        let total = source_file.metadata().await?.len();
+        let mut part_file = File::create(&part_file_path).await?;
+
+        let mut buffer = vec![0; 1024 * 1024];
+        let mut copied = 0;
+        loop {
+            let read = source_file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            part_file.write_all(&buffer[..read]).await?;
+            copied += read as u64;
+            on_progress(copied, total);
+        }
+
+        part_file.sync_all().await?;
+        fs::rename(&part_file_path, destination).await
+    }
+    .await;
+
+    if let Err(e) = copy_result {
+        _ = fs::remove_file(&part_file_path).await;
+        return Err(e);
+    }
+
+    fs::remove_file(source).await
+}
+
+/// Space kept free on filesystems downloads are written to
+const MIN_FREE_SPACE: u64 = 16 * 1024 * 1024;
+
+/// Checks if there is enough free space to finish a download. The output filesystem needs
+/// room for the whole file when it differs from the temp one since the file is copied there.
+///
+/// # Arguments
+///
+/// * `temp_file` - The temp file path
+/// * `output_file` - The output file path
+/// * `remaining` - The bytes left to download
+/// * `reserved` - The bytes of the remaining ones already preallocated in the temp file
+/// * `size` - The full size of the file
+///
+/// # Returns
+///
+/// * `bool` - Whether there is enough space
+pub fn has_enough_space(
+    temp_file: &str,
+    output_file: &str,
+    remaining: u64,
+    reserved: u64,
+    size: u64,
+) -> Result<bool, io::Error> {
+    let temp_path = Path::new(temp_file);
+    let output_path = Path::new(output_file);
+
+    // Preallocated blocks are no longer reported as available
+    let temp_available = get_available_space(temp_path)?.saturating_add(reserved);
+    if temp_available < remaining + MIN_FREE_SPACE {
+        return Ok(false);
+    }
+    if is_same_filesystem(temp_path, output_path)? {
+        return Ok(true);
+    }
+    Ok(get_available_space(output_path)? >= size + MIN_FREE_SPACE)
+}
+
 pub async fn empty_temp_file(temp_file_path: &str) -> Result<(), io::Error> {
     OpenOptions::new()
         .write(true)
@@ -0,0 +1,111 @@
+use chrono::Local;
+use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
+
+use super::db::DBError;
+use super::download::{Download, DownloadStatus};
+use super::store::DownloadStore;
+
+/// Downloads handled as one unit, like the parts of an archive or a dataset batch
+#[derive(Debug, Clone, Default, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct DownloadGroup {
+    pub id: i64,
+    pub name: String,
+    /// Output directory of the members that do not have their own
+    pub directory: Option<String>,
+    pub date_added: i64,
+    /// Set when every member is completed
+    pub date_completed: Option<i64>,
+    /// Aggregate status of the members as returned by `DownloadStatus::get_string`
+    pub status: String,
+    /// Sum of the known member sizes
+    pub size: u64,
+    /// Sum of the saved member progress
+    pub downloaded: u64,
+    pub download_count: u32,
+    pub completed_count: u32,
+}
+
+impl DownloadGroup {
+    pub fn new(name: &str, directory: Option<String>) -> DownloadGroup {
+        DownloadGroup {
+            name: name.to_string(),
+            directory,
+            date_added: Local::now().timestamp(),
+            status: DownloadStatus::Pending.get_string().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Fills the aggregate fields from the members of the group
+    pub fn aggregate(&mut self, downloads: &[Download]) {
+        let statuses = downloads
+            .iter()
+            .map(|download| download.status.clone())
+            .collect::<Vec<DownloadStatus>>();
+        self.status = get_group_status(&statuses).get_string().to_string();
+        self.size = downloads.iter().filter_map(|download| download.size).sum();
+        self.downloaded = downloads
+            .iter()
+            .map(|download| match download.status {
+                DownloadStatus::Completed => download.size.unwrap_or(download.downloaded_bytes),
+                _ => download.downloaded_bytes,
+            })
+            .sum();
+        self.download_count = downloads.len() as u32;
+        self.completed_count = statuses
+            .iter()
+            .filter(|status| matches!(status, DownloadStatus::Completed))
+            .count() as u32;
+    }
+}
+
+/// Returns the status that best describes a group, the most active member wins
+///
+/// An empty group is pending. Canceled members are left out unless all members are canceled.
+pub fn get_group_status(statuses: &[DownloadStatus]) -> DownloadStatus {
+    let any = |f: fn(&DownloadStatus) -> bool| statuses.iter().any(f);
+
+    if statuses.is_empty() {
+        DownloadStatus::Pending
+    } else if any(|s| matches!(s, DownloadStatus::Starting | DownloadStatus::InProgress)) {
+        DownloadStatus::InProgress
+    } else if any(|s| matches!(s, DownloadStatus::AwaitingConflictResolution)) {
+        DownloadStatus::AwaitingConflictResolution
+    } else if any(|s| matches!(s, DownloadStatus::Pending | DownloadStatus::Retrying)) {
+        DownloadStatus::Pending
+    } else if let Some(error) = statuses.iter().find(|s| {
+        matches!(
+            s,
+            DownloadStatus::ServerError | DownloadStatus::ClientError | DownloadStatus::UnknownError
+        )
+    }) {
+        error.clone()
+    } else if any(|s| matches!(s, DownloadStatus::InsufficientSpace)) {
+        DownloadStatus::InsufficientSpace
+    } else if any(|s| matches!(s, DownloadStatus::Paused)) {
+        DownloadStatus::Paused
+    } else if any(|s| matches!(s, DownloadStatus::Completed)) {
+        DownloadStatus::Completed
+    } else {
+        DownloadStatus::Canceled
+    }
+}
+
+/// Returns the group with its aggregate state
+pub async fn get_group(store: &dyn DownloadStore, group_id: i64) -> Result<DownloadGroup, DBError> {
+    let mut group = store.get_group_by_id(group_id).await?;
+    let downloads = store.get_group_downloads(group_id).await?;
+    group.aggregate(&downloads);
+    Ok(group)
+}
+
+/// Returns all groups with their aggregate state
+pub async fn get_all_groups(store: &dyn DownloadStore) -> Result<Vec<DownloadGroup>, DBError> {
+    let mut groups = store.get_all_groups().await?;
+    for group in groups.iter_mut() {
+        let downloads = store.get_group_downloads(group.id).await?;
+        group.aggregate(&downloads);
+    }
+    Ok(groups)
+}
@@ -0,0 +1,454 @@
+use std::collections::HashSet;
+use std::path::{Component, Path};
+
+use regex::Regex;
+use reqwest::Url;
+use thiserror::Error;
+use tokio::fs;
+use zbus::zvariant::{DeserializeDict, SerializeDict, Type};
+
+use crate::utils;
+
+use super::category::{self, CategoryQuery};
+use super::config::{self, Config};
+use super::db::DBError;
+use super::download::Download;
+use super::group::DownloadGroup;
+use super::store::DownloadStore;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("Could not read {0}: {1}")]
+    ReadError(String, std::io::Error),
+
+    #[error("Could not fetch {0}: {1}")]
+    RequestError(String, reqwest::Error),
+
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("Invalid file name: {0}")]
+    InvalidFileName(String),
+
+    #[error("Invalid link pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+
+    #[error("Database error: {0}")]
+    DBError(#[from] DBError),
+}
+
+/// How the entries of an import are picked and where they are saved
+#[derive(Debug, Clone, Default, Type, SerializeDict, DeserializeDict)]
+#[zvariant(signature = "dict")]
+pub struct ImportOptions {
+    /// Regular expression the links of an HTML page must match
+    pub pattern: Option<String>,
+    /// Extensions the links of an HTML page must end with
+    pub extensions: Option<Vec<String>>,
+    /// Base of relative links, defaults to the URL of the imported page
+    pub base_url: Option<String>,
+    /// Directory of the entries that do not set `dir=`, `dir=` is ignored in fetched lists
+    pub directory: Option<String>,
+    /// Name of a new group holding the imported downloads
+    pub group: Option<String>,
+}
+
+/// A URL to download with its optional output name and directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportEntry {
+    pub url: String,
+    pub out: Option<String>,
+    pub dir: Option<String>,
+}
+
+impl ImportEntry {
+    fn new(url: String) -> ImportEntry {
+        ImportEntry {
+            url,
+            out: None,
+            dir: None,
+        }
+    }
+}
+
+/// Queues every entry of a URL list or HTML page as a new download
+///
+/// # Arguments
+///
+/// * `store` - Where the downloads are added
+/// * `source` - Path of a local file or URL of a page to import
+/// * `options` - The link filter, default directory and group
+///
+/// # Returns
+///
+/// * `Vec<i64>` - The ids of the new downloads
+pub async fn import_downloads(
+    store: &dyn DownloadStore,
+    source: &str,
+    options: &ImportOptions,
+) -> Result<Vec<i64>, ImportError> {
+    let config = config::get_config().await;
+
+    let source_content = read_source(source, &config).await?;
+    let entries = get_source_entries(&source_content, options)?;
+    log::info!("Importing {} downloads from {}", entries.len(), source);
+
+    let mut downloads = vec![];
+    for entry in entries {
+        downloads.push(get_download_from_entry(entry, options, &config).await);
+    }
+    match &options.group {
+        Some(name) => {
+            let directory = options
+                .directory
+                .as_ref()
+                .map(|directory| utils::path::expand(directory));
+            let group = DownloadGroup::new(name, directory);
+            let (_, ids) = store.new_downloads_in_group(&group, &downloads).await?;
+            Ok(ids)
+        }
+        None => Ok(store.new_downloads(&downloads).await?),
+    }
+}
+
+/// Queues a single download with its optional output name and directory
+///
+/// # Arguments
+///
+/// * `store` - Where the download is added
+/// * `entry` - The URL, the name and the directory of the download
+///
+/// # Returns
+///
+/// * `i64` - The id of the new download
+pub async fn add_download(
+    store: &dyn DownloadStore,
+    entry: ImportEntry,
+) -> Result<i64, ImportError> {
+    if Url::parse(&entry.url).is_err() {
+        return Err(ImportError::InvalidUrl(entry.url));
+    }
+    if let Some(out) = entry.out.as_deref().filter(|out| get_safe_file_name(out).is_none()) {
+        return Err(ImportError::InvalidFileName(out.to_string()));
+    }
+    let config = config::get_config().await;
+    let download = get_download_from_entry(entry, &ImportOptions::default(), &config).await;
+    Ok(store.new_download(&download).await?)
+}
+
+/// The content of an imported file or page
+struct ImportSource {
+    content: String,
+    /// Final URL of a fetched page, `None` for a local file
+    url: Option<String>,
+    /// MIME type of a fetched page
+    content_type: Option<String>,
+}
+
+/// Reads a local file or fetches a page
+async fn read_source(source: &str, config: &Config) -> Result<ImportSource, ImportError> {
+    if !source.starts_with("http://") && !source.starts_with("https://") {
+        let content = fs::read_to_string(utils::path::expand(source))
+            .await
+            .map_err(|e| ImportError::ReadError(source.to_string(), e))?;
+        return Ok(ImportSource {
+            content,
+            url: None,
+            content_type: None,
+        });
+    }
+
+    let request_error = |e| ImportError::RequestError(source.to_string(), e);
+    let client = reqwest::Client::builder()
+        .user_agent(&config.user_agent)
+        .build()
+        .map_err(request_error)?;
+    let resp = client
+        .get(source)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(request_error)?;
+    let url = resp.url().to_string();
+    let content_type = resp
+        .headers()
+        .get("content-type")
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(str::to_string);
+    let content = resp.text().await.map_err(request_error)?;
+    Ok(ImportSource {
+        content,
+        url: Some(url),
+        content_type,
+    })
+}
+
+/// Parses the entries of an imported file or page
+///
+/// A fetched list cannot pick the directories its files are saved to, its `dir=` options are
+/// dropped.
+fn get_source_entries(
+    source: &ImportSource,
+    options: &ImportOptions,
+) -> Result<Vec<ImportEntry>, ImportError> {
+    let base_url = options.base_url.as_deref().or(source.url.as_deref());
+    let mut entries = parse_entries(
+        &source.content,
+        source.content_type.as_deref(),
+        base_url,
+        options,
+    )?;
+    if source.url.is_some() {
+        for entry in &mut entries {
+            if let Some(dir) = entry.dir.take() {
+                log::warn!("Ignoring directory {} of fetched entry {}", dir, entry.url);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Returns an output name as a single path segment, `None` if it leads out of its directory
+fn get_safe_file_name(out: &str) -> Option<String> {
+    let path = Path::new(out);
+    if path.is_absolute()
+        || out.starts_with('~')
+        || path
+            .components()
+            .any(|component| matches!(component, Component::ParentDir))
+    {
+        return None;
+    }
+    Some(utils::path::sanitize_path_segment(out))
+}
+
+async fn get_download_from_entry(
+    entry: ImportEntry,
+    options: &ImportOptions,
+    config: &Config,
+) -> Download {
+    let mut download = Download::get_download_from_url(entry.url, config).await;
+    download.data_confirmed = true;
+
+    let out = entry.out.as_deref().and_then(get_safe_file_name);
+    let directory = entry
+        .dir
+        .or(options.directory.clone())
+        .map(|directory| utils::path::expand(&directory));
+    match (out, directory) {
+        (Some(out), Some(directory)) => {
+            download.output_file = Some(
+                Path::new(&directory)
+                    .join(out)
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+        (Some(out), None) => {
+            // The name is known, so the category can be picked right away
+            let category_match = category::find_category(
+                &CategoryQuery {
+                    url: &download.url,
+                    file_name: &out,
+                    content_type: None,
+                    size: None,
+                },
+                config,
+            );
+            let directory = utils::path::expand(&category_match.directory);
+            download.category = category_match.category;
+            download.output_file = Some(
+                Path::new(&directory)
+                    .join(out)
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+        (None, directory) => download.output_directory = directory,
+    }
+    download
+}
+
+/// Parses an HTML page or a URL list depending on the content type or the content
+pub fn parse_entries(
+    content: &str,
+    content_type: Option<&str>,
+    base_url: Option<&str>,
+    options: &ImportOptions,
+) -> Result<Vec<ImportEntry>, ImportError> {
+    if is_html(content, content_type) {
+        let pattern = options.pattern.as_deref().map(Regex::new).transpose()?;
+        let extensions = options.extensions.clone().unwrap_or_default();
+        Ok(parse_html_links(
+            content,
+            base_url,
+            pattern.as_ref(),
+            &extensions,
+        ))
+    } else {
+        Ok(parse_url_list(content, base_url))
+    }
+}
+
+/// A page is HTML if its server says so, a local file or a page of another type if it holds
+/// HTML tags
+fn is_html(content: &str, content_type: Option<&str>) -> bool {
+    let mime_type = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime_type| mime_type.trim().to_lowercase());
+    match mime_type.as_deref() {
+        Some("text/html" | "application/xhtml+xml") => true,
+        Some("text/plain" | "text/uri-list") => false,
+        _ => {
+            let tag_regex = Regex::new(
+                r"(?i)<(?:!doctype|!--|/?(?:html|head|body|title|meta|base|dl|dt|a)[\s>/])",
+            )
+            .unwrap();
+            tag_regex.is_match(content)
+        }
+    }
+}
+
+/// Parses a list of URLs in the aria2c input file format
+///
+/// Each URL starts a new entry. The lines below it that start with a space set its options,
+/// `out=` and `dir=` are supported. Blank lines and lines starting with `#` are skipped.
+/// When a line holds tab separated mirrors, only the first one is used.
+pub fn parse_url_list(content: &str, base_url: Option<&str>) -> Vec<ImportEntry> {
+    let mut entries: Vec<ImportEntry> = vec![];
+    // Options of an invalid URL must not be given to the previous entry
+    let mut skipping = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            if skipping {
+                continue;
+            }
+            let Some(entry) = entries.last_mut() else {
+                continue;
+            };
+            match trimmed.split_once('=') {
+                Some(("out", value)) if !value.trim().is_empty() => {
+                    match get_safe_file_name(value.trim()) {
+                        Some(out) => entry.out = Some(out),
+                        None => log::warn!("Ignoring unsafe output name: {}", value.trim()),
+                    }
+                }
+                Some(("dir", value)) if !value.trim().is_empty() => {
+                    entry.dir = Some(value.trim().to_string())
+                }
+                _ => log::debug!("Ignoring import option: {}", trimmed),
+            }
+            continue;
+        }
+
+        let url = trimmed.split('\t').next().unwrap_or_default();
+        match resolve_url(url, base_url) {
+            Some(url) => {
+                skipping = false;
+                entries.push(ImportEntry::new(url));
+            }
+            None => {
+                log::warn!("Ignoring invalid URL: {}", url);
+                skipping = true;
+            }
+        }
+    }
+    entries
+}
+
+/// Collects the links of an HTML page, bookmark exports of browsers included
+///
+/// # Arguments
+///
+/// * `html` - The page
+/// * `base_url` - The URL relative links are resolved against
+/// * `pattern` - Regular expression the absolute link must match
+/// * `extensions` - Extensions the link path must end with, any if empty
+pub fn parse_html_links(
+    html: &str,
+    base_url: Option<&str>,
+    pattern: Option<&Regex>,
+    extensions: &[String],
+) -> Vec<ImportEntry> {
+    let href_regex =
+        Regex::new(r#"(?i)<a\s[^>]*?href\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#).unwrap();
+    let base_url = base_url.or_else(|| get_base_href(html));
+
+    let mut seen = HashSet::new();
+    let mut entries = vec![];
+    for captures in href_regex.captures_iter(html) {
+        let href = captures
+            .get(1)
+            .or(captures.get(2))
+            .or(captures.get(3))
+            .map(|href| decode_html_entities(href.as_str().trim()))
+            .unwrap_or_default();
+        if href.is_empty() || href.starts_with('#') {
+            continue;
+        }
+        let Some(url) = resolve_url(&href, base_url) else {
+            continue;
+        };
+        if pattern.is_some_and(|pattern| !pattern.is_match(&url)) {
+            continue;
+        }
+        if !extensions.is_empty() && !has_extension(&url, extensions) {
+            continue;
+        }
+        if seen.insert(url.clone()) {
+            entries.push(ImportEntry::new(url));
+        }
+    }
+    entries
+}
+
+fn get_base_href(html: &str) -> Option<&str> {
+    let base_regex = Regex::new(r#"(?i)<base\s[^>]*?href\s*=\s*["']([^"']+)["']"#).unwrap();
+    base_regex
+        .captures(html)
+        .and_then(|captures| captures.get(1))
+        .map(|href| href.as_str())
+}
+
+fn decode_html_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Returns the absolute URL without fragment if it can be downloaded
+fn resolve_url(url: &str, base_url: Option<&str>) -> Option<String> {
+    let mut url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => Url::parse(base_url?).ok()?.join(url).ok()?,
+    };
+    // Downloads only go through HTTP
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    url.set_fragment(None);
+    Some(url.to_string())
+}
+
+fn has_extension(url: &str, extensions: &[String]) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    let path = url.path().to_lowercase();
+    extensions.iter().any(|extension| {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        path.ends_with(&format!(".{}", extension))
+    })
+}
@@ -0,0 +1,196 @@
+use regex::Regex;
+
+use crate::core::store::{DownloadStore, MemoryStore};
+
+use super::{
+    add_download, get_source_entries, parse_entries, parse_html_links, parse_url_list,
+    ImportEntry, ImportError, ImportOptions, ImportSource,
+};
+
+#[test]
+fn test_parse_url_list() {
+    let content = "\
+# Dataset files
+https://example.com/a.csv
+  out=first.csv
+  dir=/data
+  split=4
+
+https://mirror1.example.com/b.csv\thttps://mirror2.example.com/b.csv
+mailto:admin@example.com
+  out=ignored.csv
+ftp://ftp.example.com/d.csv
+c.csv
+";
+    let entries = parse_url_list(content, Some("https://example.com/files/"));
+    assert_eq!(
+        entries,
+        vec![
+            ImportEntry {
+                url: "https://example.com/a.csv".to_string(),
+                out: Some("first.csv".to_string()),
+                dir: Some("/data".to_string()),
+            },
+            ImportEntry {
+                url: "https://mirror1.example.com/b.csv".to_string(),
+                out: None,
+                dir: None,
+            },
+            ImportEntry {
+                url: "https://example.com/files/c.csv".to_string(),
+                out: None,
+                dir: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_html_links() {
+    let html = r##"<!DOCTYPE html>
+<html><body>
+<a href="data/part1.zip">Part 1</a>
+<A HREF='/data/part2.ZIP#top'>Part 2</A>
+<a class="x" href=https://cdn.example.com/part3.zip?a=1&amp;b=2>Part 3</a>
+<a href="data/part1.zip">Part 1 again</a>
+<a href="readme.txt">Readme</a>
+<a href="mailto:admin@example.com">Contact</a>
+<a href="#section">Section</a>
+</body></html>"##;
+
+    let urls = |pattern: Option<&Regex>, extensions: &[String]| {
+        parse_html_links(
+            html,
+            Some("https://example.com/index.html"),
+            pattern,
+            extensions,
+        )
+        .into_iter()
+        .map(|entry| entry.url)
+        .collect::<Vec<String>>()
+    };
+
+    assert_eq!(
+        urls(None, &["zip".to_string()]),
+        vec![
+            "https://example.com/data/part1.zip",
+            "https://example.com/data/part2.ZIP",
+            "https://cdn.example.com/part3.zip?a=1&b=2",
+        ]
+    );
+    let pattern = Regex::new(r"^https://example\.com/").unwrap();
+    assert_eq!(
+        urls(Some(&pattern), &[]),
+        vec![
+            "https://example.com/data/part1.zip",
+            "https://example.com/data/part2.ZIP",
+            "https://example.com/readme.txt",
+        ]
+    );
+}
+
+#[test]
+fn test_parse_entries_detects_html() {
+    let options = ImportOptions {
+        extensions: Some(vec![".iso".to_string()]),
+        ..Default::default()
+    };
+    let html = r#"<html><a href="https://example.com/os.iso">ISO</a></html>"#;
+    let entries = parse_entries(html, None, None, &options).unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let list = "https://example.com/os.iso\nhttps://example.com/os.txt\n";
+    let entries = parse_entries(list, None, None, &options).unwrap();
+    assert_eq!(entries.len(), 2);
+
+    let options = ImportOptions {
+        pattern: Some("(".to_string()),
+        ..Default::default()
+    };
+    assert!(parse_entries(html, None, None, &options).is_err());
+}
+
+#[test]
+fn test_parse_entries_detects_html_anywhere() {
+    let options = ImportOptions::default();
+    let base_url = Some("https://example.com/");
+    for html in [
+        "<!-- Mirror list -->\n<html><a href=\"os.iso\">ISO</a></html>",
+        "<head><title>Files</title></head>\n<a href=\"os.iso\">ISO</a>",
+    ] {
+        let entries = parse_entries(html, None, base_url, &options).unwrap();
+        assert_eq!(
+            entries,
+            vec![ImportEntry {
+                url: "https://example.com/os.iso".to_string(),
+                out: None,
+                dir: None,
+            }]
+        );
+    }
+
+    // The content type of a fetched page wins over the content
+    let list = "https://example.com/os.iso\n";
+    let entries = parse_entries(list, Some("text/html; charset=utf-8"), None, &options).unwrap();
+    assert!(entries.is_empty());
+    let html = "<a href=\"https://example.com/os.iso\">ISO</a>\n";
+    let entries = parse_entries(html, Some("text/plain"), None, &options).unwrap();
+    assert!(entries.is_empty());
+}
+
+#[test]
+fn test_unsafe_entry_paths() {
+    let content = "\
+https://example.com/a.csv
+  out=/home/user/.bashrc
+https://example.com/b.csv
+  out=../../b.csv
+https://example.com/c.csv
+  out=~/.profile
+https://example.com/d.csv
+  out=nested/d.csv
+  dir=~/.config/autostart
+";
+    let entries = parse_url_list(content, None);
+    let names = entries
+        .iter()
+        .map(|entry| entry.out.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![None, None, None, Some("nested_d.csv")]);
+
+    // A fetched list cannot pick where its files are saved
+    let options = ImportOptions {
+        directory: Some("/downloads".to_string()),
+        ..Default::default()
+    };
+    let mut source = ImportSource {
+        content: content.to_string(),
+        url: None,
+        content_type: None,
+    };
+    let entries = get_source_entries(&source, &options).unwrap();
+    assert_eq!(entries[3].dir.as_deref(), Some("~/.config/autostart"));
+    source.url = Some("https://example.com/list.txt".to_string());
+    let entries = get_source_entries(&source, &options).unwrap();
+    assert!(entries.iter().all(|entry| entry.dir.is_none()));
+}
+
+#[tokio::test]
+async fn test_add_download_file_name() {
+    let store = MemoryStore::new();
+    let entry = |out: &str| ImportEntry {
+        url: "https://example.com/report.pdf".to_string(),
+        out: Some(out.to_string()),
+        dir: Some("/data".to_string()),
+    };
+
+    for out in ["/home/user/.bashrc", "../report.pdf"] {
+        assert!(matches!(
+            add_download(&store, entry(out)).await,
+            Err(ImportError::InvalidFileName(_))
+        ));
+    }
+    let id = add_download(&store, entry("report.pdf")).await.unwrap();
+    let download = store.get_download_by_id(id).await.unwrap();
+    assert_eq!(download.output_file.as_deref(), Some("/data/report.pdf"));
+}
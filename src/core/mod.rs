@@ -1,4 +1,9 @@
+pub mod category;
 pub mod config;
 pub mod db;
 pub mod download;
+pub mod group;
+pub mod import;
+pub mod queue;
+pub mod store;
 pub mod dbus;
@@ -0,0 +1,63 @@
+use thiserror::Error;
+
+use super::db::DBError;
+use super::download::{Download, DownloadStatus};
+use super::store::DownloadStore;
+
+#[derive(Error, Debug)]
+pub enum QueueError {
+    #[error("Download #{0} is not pending")]
+    NotPending(i64),
+
+    #[error("Database error: {0}")]
+    DBError(#[from] DBError),
+}
+
+/// Moves a pending download up or down the queue, the daemon starts the first ones
+///
+/// # Arguments
+///
+/// * `store` - Where the downloads are kept
+/// * `id` - The download to move
+/// * `steps` - The count of pending downloads it passes, toward the start if negative
+///
+/// # Returns
+///
+/// * `Vec<Download>` - The downloads whose position changed
+pub async fn move_download(
+    store: &dyn DownloadStore,
+    id: i64,
+    steps: i64,
+) -> Result<Vec<Download>, QueueError> {
+    let mut queue = store
+        .get_downloads_by_status(&DownloadStatus::Pending)
+        .await?;
+    let Some(index) = queue.iter().position(|download| download.id == id) else {
+        // Report a missing download as such
+        store.get_download_by_id(id).await?;
+        return Err(QueueError::NotPending(id));
+    };
+
+    let positions = queue
+        .iter()
+        .map(|download| download.queue_position)
+        .collect::<Vec<i64>>();
+    let new_index = (index as i64 + steps).clamp(0, queue.len() as i64 - 1) as usize;
+    let download = queue.remove(index);
+    queue.insert(new_index, download);
+
+    // The downloads take the positions of the ones they replace
+    let mut moved = vec![];
+    for (mut download, position) in queue.into_iter().zip(positions) {
+        if download.queue_position != position {
+            download.queue_position = position;
+            moved.push(download);
+        }
+    }
+    let positions = moved
+        .iter()
+        .map(|download| (download.id, download.queue_position))
+        .collect::<Vec<(i64, i64)>>();
+    store.change_queue_positions(&positions).await?;
+    Ok(moved)
+}
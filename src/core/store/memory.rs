@@ -0,0 +1,450 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use chrono::{Local, TimeZone};
+
+use crate::core::category;
+use crate::core::config::Config;
+use crate::core::db::{self, DBError};
+use crate::core::download::{
+    BandwidthUsage, Download, DownloadFilter, DownloadStatus, SortKey, SpeedSample,
+};
+use crate::core::group::DownloadGroup;
+
+use super::DownloadStore;
+
+/// Downloads kept in memory, lost when the store is dropped
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<MemoryData>,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    downloads: BTreeMap<i64, Download>,
+    groups: BTreeMap<i64, DownloadGroup>,
+    tags: HashMap<i64, BTreeSet<String>>,
+    notes: HashMap<i64, String>,
+    /// Samples with the host they were downloaded from
+    samples: Vec<(String, SpeedSample)>,
+    last_download_id: i64,
+    last_group_id: i64,
+    categories_fingerprint: Option<String>,
+}
+
+impl MemoryData {
+    fn insert_download(&mut self, download: &Download) -> i64 {
+        self.last_download_id += 1;
+        let mut download = download.clone();
+        download.id = self.last_download_id;
+        download.queue_position = self
+            .downloads
+            .values()
+            .map(|download| download.queue_position)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        self.downloads.insert(download.id, download);
+        self.last_download_id
+    }
+
+    fn insert_group(&mut self, group: &DownloadGroup) -> i64 {
+        self.last_group_id += 1;
+        let mut group = group.clone();
+        group.id = self.last_group_id;
+        self.groups.insert(group.id, group);
+        self.last_group_id
+    }
+
+    fn get_download(&mut self, download_id: i64) -> Result<&mut Download, DBError> {
+        self.downloads
+            .get_mut(&download_id)
+            .ok_or(DBError::DownloadNotFound(download_id))
+    }
+
+    /// Returns `true` if every word of the query starts a word of the searched fields, like
+    /// the full-text index of `SqliteStore`
+    fn matches_text(&self, download: &Download, query: &str) -> bool {
+        let mut fields = vec![download.url.clone()];
+        if let Some(file) = download
+            .output_file
+            .as_ref()
+            .or(download.detected_output_file.as_ref())
+        {
+            fields.push(file.clone());
+        }
+        if let Some(tags) = self.tags.get(&download.id) {
+            fields.extend(tags.iter().cloned());
+        }
+        if let Some(note) = self.notes.get(&download.id) {
+            fields.push(note.clone());
+        }
+        let words = fields
+            .iter()
+            .flat_map(|field| split_words(field))
+            .collect::<Vec<String>>();
+        split_words(query)
+            .iter()
+            .all(|prefix| words.iter().any(|word| word.starts_with(prefix.as_str())))
+    }
+}
+
+fn split_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Position of the status when sorting by status, the same as in `SqliteStore`
+fn get_status_rank(status: &DownloadStatus) -> usize {
+    db::STATUS_ORDER
+        .iter()
+        .position(|s| s.get_string() == status.get_string())
+        .unwrap_or(db::STATUS_ORDER.len())
+}
+
+fn compare_downloads(a: &Download, b: &Download, sort: SortKey) -> Ordering {
+    match sort {
+        SortKey::DateAdded | SortKey::Relevance => a.date_added.cmp(&b.date_added),
+        SortKey::DateCompleted => a.date_completed.cmp(&b.date_completed),
+        SortKey::Size => a.size.cmp(&b.size),
+        SortKey::Url => a.url.cmp(&b.url),
+        SortKey::Status => get_status_rank(&a.status).cmp(&get_status_rank(&b.status)),
+        SortKey::Queue => a.queue_position.cmp(&b.queue_position),
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryData> {
+        self.data.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl DownloadStore for MemoryStore {
+    async fn new_download(&self, download: &Download) -> Result<i64, DBError> {
+        Ok(self.lock().insert_download(download))
+    }
+
+    async fn new_downloads(&self, downloads: &[Download]) -> Result<Vec<i64>, DBError> {
+        let mut data = self.lock();
+        Ok(downloads
+            .iter()
+            .map(|download| data.insert_download(download))
+            .collect())
+    }
+
+    async fn new_downloads_in_group(
+        &self,
+        group: &DownloadGroup,
+        downloads: &[Download],
+    ) -> Result<(i64, Vec<i64>), DBError> {
+        let mut data = self.lock();
+        let group_id = data.insert_group(group);
+        let ids = downloads
+            .iter()
+            .map(|download| {
+                let mut download = download.clone();
+                download.group_id = Some(group_id);
+                data.insert_download(&download)
+            })
+            .collect();
+        Ok((group_id, ids))
+    }
+
+    async fn get_all_downloads(&self) -> Result<Vec<Download>, DBError> {
+        Ok(self.lock().downloads.values().cloned().collect())
+    }
+
+    async fn get_download_by_id(&self, id: i64) -> Result<Download, DBError> {
+        Ok(self.lock().get_download(id)?.clone())
+    }
+
+    async fn get_downloads_by_status(
+        &self,
+        status: &DownloadStatus,
+    ) -> Result<Vec<Download>, DBError> {
+        let mut downloads = self
+            .lock()
+            .downloads
+            .values()
+            .filter(|download| download.status.get_string() == status.get_string())
+            .cloned()
+            .collect::<Vec<Download>>();
+        downloads.sort_by_key(|download| (download.queue_position, download.id));
+        Ok(downloads)
+    }
+
+    async fn update_download(&self, download: &Download) -> Result<(), DBError> {
+        // The queue position has its own method, as in `SqliteStore`
+        if let Some(stored) = self.lock().downloads.get_mut(&download.id) {
+            let queue_position = stored.queue_position;
+            *stored = download.clone();
+            stored.queue_position = queue_position;
+        }
+        Ok(())
+    }
+
+    async fn delete_download(&self, download_id: i64) -> Result<(), DBError> {
+        let mut data = self.lock();
+        data.downloads.remove(&download_id);
+        data.tags.remove(&download_id);
+        data.notes.remove(&download_id);
+        Ok(())
+    }
+
+    async fn change_download_status(
+        &self,
+        download_id: i64,
+        status: &DownloadStatus,
+    ) -> Result<(), DBError> {
+        if let Some(download) = self.lock().downloads.get_mut(&download_id) {
+            download.status = status.clone();
+        }
+        Ok(())
+    }
+
+    async fn change_download_downloaded_bytes(
+        &self,
+        download_id: i64,
+        downloaded_bytes: u64,
+    ) -> Result<(), DBError> {
+        if let Some(download) = self.lock().downloads.get_mut(&download_id) {
+            download.downloaded_bytes = downloaded_bytes;
+        }
+        Ok(())
+    }
+
+    async fn change_queue_positions(&self, positions: &[(i64, i64)]) -> Result<(), DBError> {
+        let mut data = self.lock();
+        for (download_id, position) in positions {
+            if let Some(download) = data.downloads.get_mut(download_id) {
+                download.queue_position = *position;
+            }
+        }
+        Ok(())
+    }
+
+    async fn refresh_categories(&self, config: &Config) -> Result<usize, DBError> {
+        let fingerprint = db::get_categories_fingerprint(config);
+        let mut data = self.lock();
+        if data.categories_fingerprint.as_ref() == Some(&fingerprint) {
+            return Ok(0);
+        }
+
+        let mut changed = 0;
+        for download in data.downloads.values_mut() {
+            let category = category::explain_download_category(download, config).category;
+            if category != download.category {
+                download.category = category;
+                changed += 1;
+            }
+        }
+        data.categories_fingerprint = Some(fingerprint);
+        Ok(changed)
+    }
+
+    async fn get_category_counts(&self) -> Result<HashMap<String, u32>, DBError> {
+        let mut counts = HashMap::new();
+        for download in self.lock().downloads.values() {
+            *counts.entry(download.category.clone()).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    async fn list_downloads(
+        &self,
+        filter: &DownloadFilter,
+        sort: SortKey,
+        descending: bool,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<Download>, u64), DBError> {
+        let data = self.lock();
+        let text = filter.text.as_deref().unwrap_or_default().trim();
+        let mut downloads = data
+            .downloads
+            .values()
+            .filter(|download| filter.matches(download))
+            .filter(|download| text.is_empty() || data.matches_text(download, text))
+            .cloned()
+            .collect::<Vec<Download>>();
+        // Nothing ranks the matches, so the most recent come first like without a query
+        let descending = descending || sort == SortKey::Relevance;
+        downloads.sort_by(|a, b| {
+            let order = compare_downloads(a, b, sort);
+            let order = if descending { order.reverse() } else { order };
+            order
+                .then(b.date_added.cmp(&a.date_added))
+                .then(b.id.cmp(&a.id))
+        });
+
+        let total = downloads.len() as u64;
+        let limit = if limit == 0 { total } else { limit };
+        let page = downloads
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        Ok((page, total))
+    }
+
+    async fn add_speed_sample(&self, host: &str, sample: &SpeedSample) -> Result<(), DBError> {
+        self.lock().samples.push((host.to_string(), sample.clone()));
+        Ok(())
+    }
+
+    async fn get_speed_samples(&self, download_id: i64) -> Result<Vec<SpeedSample>, DBError> {
+        let mut samples = self
+            .lock()
+            .samples
+            .iter()
+            .filter(|(_, sample)| sample.download_id == download_id)
+            .map(|(_, sample)| sample.clone())
+            .collect::<Vec<SpeedSample>>();
+        samples.sort_by_key(|sample| sample.timestamp);
+        Ok(samples)
+    }
+
+    async fn get_bandwidth_usage(
+        &self,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<BandwidthUsage>, DBError> {
+        let mut usage = BTreeMap::new();
+        for (host, sample) in self.lock().samples.iter() {
+            if sample.timestamp < from || sample.timestamp > to {
+                continue;
+            }
+            let Some(date) = Local.timestamp_opt(sample.timestamp, 0).single() else {
+                continue;
+            };
+            let day = date.format("%Y-%m-%d").to_string();
+            *usage.entry((day, host.clone())).or_insert(0) += sample.bytes;
+        }
+        Ok(usage
+            .into_iter()
+            .map(|((day, host), bytes)| BandwidthUsage { day, host, bytes })
+            .collect())
+    }
+
+    async fn new_group(&self, group: &DownloadGroup) -> Result<i64, DBError> {
+        Ok(self.lock().insert_group(group))
+    }
+
+    async fn get_all_groups(&self) -> Result<Vec<DownloadGroup>, DBError> {
+        Ok(self.lock().groups.values().cloned().collect())
+    }
+
+    async fn get_group_by_id(&self, group_id: i64) -> Result<DownloadGroup, DBError> {
+        self.lock()
+            .groups
+            .get(&group_id)
+            .cloned()
+            .ok_or(DBError::GroupNotFound(group_id))
+    }
+
+    async fn get_group_downloads(&self, group_id: i64) -> Result<Vec<Download>, DBError> {
+        Ok(self
+            .lock()
+            .downloads
+            .values()
+            .filter(|download| download.group_id == Some(group_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn change_group_date_completed(
+        &self,
+        group_id: i64,
+        date_completed: Option<i64>,
+    ) -> Result<(), DBError> {
+        if let Some(group) = self.lock().groups.get_mut(&group_id) {
+            group.date_completed = date_completed;
+        }
+        Ok(())
+    }
+
+    async fn change_downloads_group(
+        &self,
+        ids: &[i64],
+        group_id: Option<i64>,
+    ) -> Result<(), DBError> {
+        let mut data = self.lock();
+        // Nothing moves if one of the downloads is missing
+        if let Some(id) = ids.iter().find(|id| !data.downloads.contains_key(id)) {
+            return Err(DBError::DownloadNotFound(*id));
+        }
+        for id in ids {
+            data.get_download(*id)?.group_id = group_id;
+        }
+        Ok(())
+    }
+
+    async fn delete_group(&self, group_id: i64) -> Result<(), DBError> {
+        let mut data = self.lock();
+        for download in data.downloads.values_mut() {
+            if download.group_id == Some(group_id) {
+                download.group_id = None;
+            }
+        }
+        data.groups.remove(&group_id);
+        Ok(())
+    }
+
+    async fn get_download_tags(&self, download_id: i64) -> Result<Vec<String>, DBError> {
+        Ok(self
+            .lock()
+            .tags
+            .get(&download_id)
+            .map(|tags| tags.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn set_download_tags(&self, download_id: i64, tags: &[String]) -> Result<(), DBError> {
+        let mut data = self.lock();
+        data.get_download(download_id)?;
+        data.tags
+            .insert(download_id, tags.iter().cloned().collect());
+        Ok(())
+    }
+
+    async fn add_download_tag(&self, download_id: i64, tag: &str) -> Result<(), DBError> {
+        let mut data = self.lock();
+        data.get_download(download_id)?;
+        data.tags
+            .entry(download_id)
+            .or_default()
+            .insert(tag.to_string());
+        Ok(())
+    }
+
+    async fn remove_download_tag(&self, download_id: i64, tag: &str) -> Result<(), DBError> {
+        if let Some(tags) = self.lock().tags.get_mut(&download_id) {
+            tags.remove(tag);
+        }
+        Ok(())
+    }
+
+    async fn get_download_note(&self, download_id: i64) -> Result<Option<String>, DBError> {
+        Ok(self.lock().notes.get(&download_id).cloned())
+    }
+
+    async fn set_download_note(&self, download_id: i64, note: &str) -> Result<(), DBError> {
+        let mut data = self.lock();
+        data.get_download(download_id)?;
+        if note.is_empty() {
+            data.notes.remove(&download_id);
+        } else {
+            data.notes.insert(download_id, note.to_string());
+        }
+        Ok(())
+    }
+}
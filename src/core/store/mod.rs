@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use super::config::{Config, ConflictPolicy};
+use super::db::DBError;
+use super::download::{
+    BandwidthUsage, Download, DownloadFilter, DownloadStatus, SortKey, SpeedSample,
+};
+use super::group::DownloadGroup;
+
+mod memory;
+
+pub use super::db::SqliteStore;
+pub use memory::MemoryStore;
+
+#[cfg(test)]
+mod tests;
+
+/// Where downloads, groups, tags and notes are kept
+///
+/// The daemon uses `SqliteStore`, `MemoryStore` keeps everything in memory so the downloader
+/// can run without touching the history of the user.
+#[async_trait]
+pub trait DownloadStore: Send + Sync {
+    /// # Returns
+    ///
+    /// * `i64` - The id of the new download
+    async fn new_download(&self, download: &Download) -> Result<i64, DBError>;
+
+    /// Adds the downloads at once, none are added if one fails
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<i64>` - The ids of the new downloads, in the same order
+    async fn new_downloads(&self, downloads: &[Download]) -> Result<Vec<i64>, DBError>;
+
+    /// Adds a group and its downloads at once
+    ///
+    /// # Returns
+    ///
+    /// * `(i64, Vec<i64>)` - The id of the new group and the ids of the new downloads
+    async fn new_downloads_in_group(
+        &self,
+        group: &DownloadGroup,
+        downloads: &[Download],
+    ) -> Result<(i64, Vec<i64>), DBError>;
+
+    async fn get_all_downloads(&self) -> Result<Vec<Download>, DBError>;
+
+    async fn get_download_by_id(&self, id: i64) -> Result<Download, DBError>;
+
+    async fn get_downloads_by_status(
+        &self,
+        status: &DownloadStatus,
+    ) -> Result<Vec<Download>, DBError>;
+
+    /// Saves every field of the download but its queue position, nothing happens if it was
+    /// deleted
+    async fn update_download(&self, download: &Download) -> Result<(), DBError>;
+
+    /// Deletes the download with its tags and note
+    async fn delete_download(&self, download_id: i64) -> Result<(), DBError>;
+
+    async fn change_download_status(
+        &self,
+        download_id: i64,
+        status: &DownloadStatus,
+    ) -> Result<(), DBError>;
+
+    async fn change_download_downloaded_bytes(
+        &self,
+        download_id: i64,
+        downloaded_bytes: u64,
+    ) -> Result<(), DBError>;
+
+    /// Moves downloads in the queue at once
+    ///
+    /// # Arguments
+    ///
+    /// * `positions` - The ids of the downloads with their new positions
+    async fn change_queue_positions(&self, positions: &[(i64, i64)]) -> Result<(), DBError>;
+
+    async fn change_download_conflict_policy(
+        &self,
+        download_id: i64,
+        conflict_policy: ConflictPolicy,
+    ) -> Result<(), DBError> {
+        let mut download = self.get_download_by_id(download_id).await?;
+        download.conflict_policy = Some(conflict_policy);
+        self.update_download(&download).await
+    }
+
+    async fn confirm_download_data(&self, download_id: i64) -> Result<(), DBError> {
+        let mut download = self.get_download_by_id(download_id).await?;
+        download.data_confirmed = true;
+        self.update_download(&download).await
+    }
+
+    /// Re-evaluates the category of every download when the categories in config differ from
+    /// the ones the stored categories were computed with
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The count of downloads whose category changed
+    async fn refresh_categories(&self, config: &Config) -> Result<usize, DBError>;
+
+    /// Counts the downloads of each category
+    ///
+    /// # Returns
+    ///
+    /// * `HashMap<String, u32>` - The count of downloads by category name, the default
+    ///   directory is the empty name
+    async fn get_category_counts(&self) -> Result<HashMap<String, u32>, DBError>;
+
+    /// Lists the downloads matching the filter, page by page
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter the downloads must match
+    /// * `sort` - The order of the downloads, ties are broken by the most recent first
+    /// * `descending` - Whether the order is reversed
+    /// * `offset` - The count of matching downloads to skip
+    /// * `limit` - The maximum count of downloads to return, all if 0
+    ///
+    /// # Returns
+    ///
+    /// * `(Vec<Download>, u64)` - The page of downloads and the total count of matching downloads
+    async fn list_downloads(
+        &self,
+        filter: &DownloadFilter,
+        sort: SortKey,
+        descending: bool,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<Download>, u64), DBError>;
+
+    async fn add_speed_sample(&self, host: &str, sample: &SpeedSample) -> Result<(), DBError>;
+
+    /// Returns the samples of the download, oldest first
+    async fn get_speed_samples(&self, download_id: i64) -> Result<Vec<SpeedSample>, DBError>;
+
+    /// Sums the downloaded bytes per local day and host
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Unix timestamp of the start of the report
+    /// * `to` - Unix timestamp of the end of the report
+    async fn get_bandwidth_usage(&self, from: i64, to: i64)
+        -> Result<Vec<BandwidthUsage>, DBError>;
+
+    /// # Returns
+    ///
+    /// * `i64` - The id of the new group
+    async fn new_group(&self, group: &DownloadGroup) -> Result<i64, DBError>;
+
+    /// Returns the groups without their aggregate state, see `group::get_all_groups`
+    async fn get_all_groups(&self) -> Result<Vec<DownloadGroup>, DBError>;
+
+    /// Returns the group without its aggregate state, see `group::get_group`
+    async fn get_group_by_id(&self, group_id: i64) -> Result<DownloadGroup, DBError>;
+
+    async fn get_group_downloads(&self, group_id: i64) -> Result<Vec<Download>, DBError>;
+
+    async fn change_group_date_completed(
+        &self,
+        group_id: i64,
+        date_completed: Option<i64>,
+    ) -> Result<(), DBError>;
+
+    /// Moves the downloads to a group, or out of their group if `group_id` is `None`
+    async fn change_downloads_group(
+        &self,
+        ids: &[i64],
+        group_id: Option<i64>,
+    ) -> Result<(), DBError>;
+
+    /// Deletes the group, its downloads are kept without a group
+    async fn delete_group(&self, group_id: i64) -> Result<(), DBError>;
+
+    /// Returns the tags of the download in alphabetical order
+    async fn get_download_tags(&self, download_id: i64) -> Result<Vec<String>, DBError>;
+
+    /// Replaces all tags of the download
+    async fn set_download_tags(&self, download_id: i64, tags: &[String]) -> Result<(), DBError>;
+
+    async fn add_download_tag(&self, download_id: i64, tag: &str) -> Result<(), DBError>;
+
+    async fn remove_download_tag(&self, download_id: i64, tag: &str) -> Result<(), DBError>;
+
+    async fn get_download_note(&self, download_id: i64) -> Result<Option<String>, DBError>;
+
+    /// Sets the note of the download, an empty note removes it
+    async fn set_download_note(&self, download_id: i64, note: &str) -> Result<(), DBError>;
+}
@@ -0,0 +1,440 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+use crate::core::config::ConflictPolicy;
+use crate::core::db::DBError;
+use crate::core::download::{
+    BulkAction, BulkReply, Download, DownloadEvent, DownloadFilter, DownloadStatus, Downloader,
+    SortKey,
+};
+use crate::core::group::{self, DownloadGroup};
+use crate::core::queue::{self, QueueError};
+use crate::utils::tests::TestFile;
+
+use super::{DownloadStore, MemoryStore, SqliteStore};
+
+fn test_download(url: &str, date_added: i64) -> Download {
+    Download {
+        url: url.to_string(),
+        data_confirmed: true,
+        temp_file: format!("/tmp/flowd-store-test-{}", date_added),
+        date_added,
+        ..Default::default()
+    }
+}
+
+fn ids(downloads: &[Download]) -> Vec<i64> {
+    downloads.iter().map(|download| download.id).collect()
+}
+
+/// Checks the behavior every store must share
+async fn check_store(store: &dyn DownloadStore) {
+    let ids_added = store
+        .new_downloads(&[
+            test_download("https://example.com/report.pdf", 1),
+            test_download("https://files.example.org/movie.mkv", 2),
+            test_download("https://example.com/notes.txt", 3),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(ids_added.len(), 3);
+    let (report, movie, notes) = (ids_added[0], ids_added[1], ids_added[2]);
+
+    // Updates and status transitions
+    let mut download = store.get_download_by_id(movie).await.unwrap();
+    assert_eq!(download.url, "https://files.example.org/movie.mkv");
+    download.size = Some(4000);
+    download.category = "videos".to_string();
+    store.update_download(&download).await.unwrap();
+    store
+        .change_download_status(movie, &DownloadStatus::InProgress)
+        .await
+        .unwrap();
+    store
+        .change_download_downloaded_bytes(movie, 1000)
+        .await
+        .unwrap();
+    let download = store.get_download_by_id(movie).await.unwrap();
+    assert!(matches!(download.status, DownloadStatus::InProgress));
+    assert_eq!(
+        (download.size, download.downloaded_bytes),
+        (Some(4000), 1000)
+    );
+    let pending = store
+        .get_downloads_by_status(&DownloadStatus::Pending)
+        .await
+        .unwrap();
+    assert_eq!(ids(&pending), vec![report, notes]);
+
+    // Pending downloads start in queue order, which updates keep
+    let moved = queue::move_download(store, notes, -5).await.unwrap();
+    assert_eq!(ids(&moved), vec![notes, report]);
+    store.update_download(&pending[1]).await.unwrap();
+    let pending = store
+        .get_downloads_by_status(&DownloadStatus::Pending)
+        .await
+        .unwrap();
+    assert_eq!(ids(&pending), vec![notes, report]);
+    assert!(matches!(
+        queue::move_download(store, movie, 1).await,
+        Err(QueueError::NotPending(id)) if id == movie
+    ));
+    assert!(matches!(
+        queue::move_download(store, 1000, 1).await,
+        Err(QueueError::DBError(DBError::DownloadNotFound(1000)))
+    ));
+    assert!(matches!(
+        store.get_download_by_id(1000).await,
+        Err(DBError::DownloadNotFound(1000))
+    ));
+
+    // Tags and notes are searched with the URL
+    store
+        .set_download_tags(report, &["work".to_string(), "2024".to_string()])
+        .await
+        .unwrap();
+    store.add_download_tag(report, "urgent").await.unwrap();
+    store.remove_download_tag(report, "2024").await.unwrap();
+    assert_eq!(
+        store.get_download_tags(report).await.unwrap(),
+        vec!["urgent", "work"]
+    );
+    store
+        .set_download_note(notes, "Meeting minutes")
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_download_note(notes).await.unwrap().as_deref(),
+        Some("Meeting minutes")
+    );
+    assert!(store.add_download_tag(1000, "work").await.is_err());
+
+    let list = |filter: DownloadFilter, sort: SortKey, descending: bool| async move {
+        let (downloads, total) = store
+            .list_downloads(&filter, sort, descending, 0, 0)
+            .await
+            .unwrap();
+        assert_eq!(downloads.len() as u64, total);
+        ids(&downloads)
+    };
+    assert_eq!(
+        list(DownloadFilter::default(), SortKey::DateAdded, true).await,
+        vec![notes, movie, report]
+    );
+    assert_eq!(
+        list(DownloadFilter::default(), SortKey::Status, false).await,
+        vec![movie, notes, report]
+    );
+    assert_eq!(
+        list(DownloadFilter::default(), SortKey::Queue, false).await,
+        vec![notes, movie, report]
+    );
+    let search = |text: &str| DownloadFilter {
+        text: Some(text.to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        list(search("urg"), SortKey::Relevance, false).await,
+        vec![report]
+    );
+    assert_eq!(
+        list(search("meeting"), SortKey::Relevance, false).await,
+        vec![notes]
+    );
+    assert_eq!(
+        list(search("example pdf"), SortKey::Relevance, false).await,
+        vec![report]
+    );
+    let filter = DownloadFilter {
+        host: Some("example.com".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(list(filter, SortKey::Url, false).await, vec![notes, report]);
+    let filter = DownloadFilter {
+        category: Some("videos".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(list(filter, SortKey::DateAdded, true).await, vec![movie]);
+    let (page, total) = store
+        .list_downloads(&DownloadFilter::default(), SortKey::DateAdded, false, 1, 1)
+        .await
+        .unwrap();
+    assert_eq!((ids(&page), total), (vec![movie], 3));
+    assert_eq!(
+        store.get_category_counts().await.unwrap().get("videos"),
+        Some(&1)
+    );
+
+    // Groups
+    let (group_id, members) = store
+        .new_downloads_in_group(
+            &DownloadGroup::new("Dataset", None),
+            &[test_download("https://example.com/part1.zip", 4)],
+        )
+        .await
+        .unwrap();
+    store
+        .change_downloads_group(&[report], Some(group_id))
+        .await
+        .unwrap();
+    assert!(store
+        .change_downloads_group(&[notes, 1000], Some(group_id))
+        .await
+        .is_err());
+    let group = group::get_group(store, group_id).await.unwrap();
+    assert_eq!((group.name.as_str(), group.download_count), ("Dataset", 2));
+    store.delete_group(group_id).await.unwrap();
+    assert!(matches!(
+        store.get_group_by_id(group_id).await,
+        Err(DBError::GroupNotFound(_))
+    ));
+    let member = store.get_download_by_id(members[0]).await.unwrap();
+    assert_eq!(member.group_id, None);
+    let notes_download = store.get_download_by_id(notes).await.unwrap();
+    assert_eq!(notes_download.group_id, None);
+
+    // Deleting a download removes its tags
+    store.delete_download(report).await.unwrap();
+    assert!(store.get_download_by_id(report).await.is_err());
+    assert!(store.get_download_tags(report).await.unwrap().is_empty());
+    assert_eq!(store.get_all_downloads().await.unwrap().len(), 3);
+}
+
+/// Checks that every status has its own rank when sorting by status
+async fn check_status_order(store: &dyn DownloadStore) {
+    for (date_added, status) in DownloadStatus::ALL.iter().rev().enumerate() {
+        let id = store
+            .new_download(&test_download("https://example.com/file", date_added as i64))
+            .await
+            .unwrap();
+        store.change_download_status(id, status).await.unwrap();
+    }
+    let (downloads, _) = store
+        .list_downloads(&DownloadFilter::default(), SortKey::Status, false, 0, 0)
+        .await
+        .unwrap();
+    let statuses = downloads
+        .iter()
+        .map(|download| download.status.get_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        vec![
+            "in_progress",
+            "starting",
+            "retrying",
+            "awaiting_conflict_resolution",
+            "pending",
+            "paused",
+            "insufficient_space",
+            "server_error",
+            "client_error",
+            "unknown_error",
+            "canceled",
+            "completed",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_memory_store() {
+    check_store(&MemoryStore::new()).await;
+    check_status_order(&MemoryStore::new()).await;
+}
+
+#[tokio::test]
+async fn test_sqlite_store() {
+    let db_file = TestFile::new(
+        &std::env::temp_dir()
+            .join("flowd-test-sqlite-store.db")
+            .to_string_lossy(),
+    );
+    let store = SqliteStore::open(&db_file.file_path).await.unwrap();
+    check_store(&store).await;
+    drop(store);
+    for suffix in ["-wal", "-shm"] {
+        _ = std::fs::remove_file(format!("{}{}", db_file.file_path, suffix));
+    }
+}
+
+#[tokio::test]
+async fn test_sqlite_status_order() {
+    let db_file = TestFile::new(
+        &std::env::temp_dir()
+            .join("flowd-test-sqlite-status-order.db")
+            .to_string_lossy(),
+    );
+    let store = SqliteStore::open(&db_file.file_path).await.unwrap();
+    check_status_order(&store).await;
+    drop(store);
+    for suffix in ["-wal", "-shm"] {
+        _ = std::fs::remove_file(format!("{}{}", db_file.file_path, suffix));
+    }
+}
+
+#[tokio::test]
+async fn test_downloader_with_memory_store() {
+    let store = Arc::new(MemoryStore::new());
+    let id = store
+        .new_download(&test_download("https://example.com/os.iso", 1))
+        .await
+        .unwrap();
+    let (tx, mut rx) = broadcast::channel(16);
+    let downloader = Downloader::new(tx.clone(), tx.subscribe(), store.clone());
+
+    downloader
+        .handle_event(DownloadEvent::PauseDownload(id))
+        .await
+        .unwrap();
+    let download = store.get_download_by_id(id).await.unwrap();
+    assert!(matches!(download.status, DownloadStatus::Paused));
+    assert!(matches!(
+        rx.recv().await.unwrap(),
+        DownloadEvent::DownloadUpdate(download) if download.id == id
+    ));
+
+    downloader
+        .handle_event(DownloadEvent::ResumeDownload(id))
+        .await
+        .unwrap();
+    let download = store.get_download_by_id(id).await.unwrap();
+    assert!(matches!(download.status, DownloadStatus::Pending));
+
+    // Retry timers do not outlive the daemon, waiting downloads are queued on start
+    store
+        .change_download_status(id, &DownloadStatus::Retrying)
+        .await
+        .unwrap();
+    assert_eq!(downloader.requeue_retrying_downloads().await.unwrap(), 1);
+    let download = store.get_download_by_id(id).await.unwrap();
+    assert!(matches!(download.status, DownloadStatus::Pending));
+
+    downloader
+        .handle_event(DownloadEvent::CancelDownload(id))
+        .await
+        .unwrap();
+    downloader
+        .handle_event(DownloadEvent::DeleteDownload(id))
+        .await
+        .unwrap();
+    assert!(store.get_all_downloads().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_downloader_bulk_signals() {
+    let store = Arc::new(MemoryStore::new());
+    let mut added = vec![];
+    for date_added in 0..40 {
+        let download = test_download("https://example.com/file.zip", date_added);
+        added.push(store.new_download(&download).await.unwrap());
+    }
+    let (tx, mut rx) = broadcast::channel(8);
+    let downloader = Downloader::new(tx.clone(), tx.subscribe(), store.clone());
+
+    // More changes than the channel holds are signaled with a single event
+    let (reply, results) = BulkReply::new();
+    downloader
+        .handle_event(DownloadEvent::Bulk(BulkAction::Pause, added.clone(), reply))
+        .await
+        .unwrap();
+    let results = results.await.unwrap();
+    assert_eq!(
+        results.iter().map(|result| result.id).collect::<Vec<i64>>(),
+        added
+    );
+    assert!(results.iter().all(|result| result.result == "OK"));
+    let DownloadEvent::BulkUpdate(changes) = rx.recv().await.unwrap() else {
+        panic!("Expected a bulk update");
+    };
+    assert_eq!(ids(&changes.updated), added);
+    assert!(changes
+        .updated
+        .iter()
+        .all(|download| matches!(download.status, DownloadStatus::Paused)));
+    assert!(rx.try_recv().is_err());
+
+    // Downloads whose status no longer fits the action get an error result
+    let (reply, results) = BulkReply::new();
+    downloader
+        .handle_event(DownloadEvent::Bulk(
+            BulkAction::Pause,
+            vec![added[0], -1],
+            reply,
+        ))
+        .await
+        .unwrap();
+    let results = results.await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.result == "ERROR"));
+    assert!(rx.try_recv().is_err());
+
+    downloader
+        .handle_event(DownloadEvent::Bulk(
+            BulkAction::Delete,
+            added.clone(),
+            BulkReply::default(),
+        ))
+        .await
+        .unwrap();
+    let DownloadEvent::BulkUpdate(changes) = rx.recv().await.unwrap() else {
+        panic!("Expected a bulk update");
+    };
+    assert!(changes.updated.is_empty());
+    assert_eq!(changes.deleted, added);
+    assert!(store.get_all_downloads().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_downloader_resolves_parked_conflict() {
+    let test_path = |name: &str| std::env::temp_dir().join(name).to_string_lossy().to_string();
+    let existing = TestFile::new(&test_path("flowd-test-conflict.txt"));
+    std::fs::write(&existing.file_path, "old").unwrap();
+    let temp_file = TestFile::new(&test_path("flowd-test-conflict.part"));
+    std::fs::write(&temp_file.file_path, "new").unwrap();
+    let renamed = TestFile {
+        file_path: test_path("flowd-test-conflict (1).txt"),
+    };
+
+    let store = Arc::new(MemoryStore::new());
+    let id = store
+        .new_download(&Download {
+            status: DownloadStatus::AwaitingConflictResolution,
+            detected_output_file: Some(existing.file_path.clone()),
+            temp_file: temp_file.file_path.clone(),
+            size: Some(3),
+            downloaded_bytes: 3,
+            ..test_download("https://example.com/conflict.txt", 1)
+        })
+        .await
+        .unwrap();
+    let (tx, _rx) = broadcast::channel(64);
+    let downloader = Downloader::new(tx.clone(), tx.subscribe(), store.clone());
+
+    // A parked download can be paused, it holds no download slot
+    downloader
+        .handle_event(DownloadEvent::PauseDownload(id))
+        .await
+        .unwrap();
+    let download = store.get_download_by_id(id).await.unwrap();
+    assert!(matches!(download.status, DownloadStatus::Paused));
+    store
+        .change_download_status(id, &DownloadStatus::AwaitingConflictResolution)
+        .await
+        .unwrap();
+
+    // Resolving queues it again and starting it only moves the downloaded file
+    downloader
+        .handle_event(DownloadEvent::ResolveConflict(id, ConflictPolicy::Rename))
+        .await
+        .unwrap();
+    let download = store.get_download_by_id(id).await.unwrap();
+    assert!(matches!(download.status, DownloadStatus::Pending));
+    downloader.download(id).await.unwrap();
+
+    let download = store.get_download_by_id(id).await.unwrap();
+    assert!(matches!(download.status, DownloadStatus::Completed));
+    assert_eq!(download.output_file.as_deref(), Some(renamed.file_path.as_str()));
+    assert_eq!(std::fs::read_to_string(&renamed.file_path).unwrap(), "new");
+    assert_eq!(std::fs::read_to_string(&existing.file_path).unwrap(), "old");
+}
@@ -1,3 +1,4 @@
+pub mod client;
 pub mod core;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,5 @@
+-- Pending downloads start by ascending queue position, the oldest first until reordered
+ALTER TABLE downloads ADD COLUMN queue_position INTEGER NOT NULL DEFAULT 0;
+UPDATE downloads SET queue_position = id;
+CREATE INDEX IF NOT EXISTS downloads_queue_position ON downloads (status, queue_position);
+PRAGMA user_version = 10;
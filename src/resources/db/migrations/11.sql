@@ -0,0 +1,3 @@
+-- Content type sent by the server, kept so categories can be re-evaluated with it
+ALTER TABLE downloads ADD COLUMN content_type TEXT;
+PRAGMA user_version = 11;
@@ -0,0 +1,2 @@
+ALTER TABLE downloads ADD COLUMN conflict_policy TEXT;
+PRAGMA user_version = 2;
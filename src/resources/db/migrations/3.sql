@@ -0,0 +1,3 @@
+ALTER TABLE downloads ADD COLUMN etag TEXT;
+ALTER TABLE downloads ADD COLUMN last_modified TEXT;
+PRAGMA user_version = 3;
@@ -0,0 +1,12 @@
+ALTER TABLE downloads ADD COLUMN downloaded_bytes INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS download_speed_samples (
+    id INTEGER PRIMARY KEY,
+    download_id INTEGER NOT NULL,
+    host TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    speed INTEGER NOT NULL,
+    bytes INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS download_speed_samples_download_id ON download_speed_samples (download_id, timestamp);
+CREATE INDEX IF NOT EXISTS download_speed_samples_timestamp ON download_speed_samples (timestamp);
+PRAGMA user_version = 4;
@@ -0,0 +1,2 @@
+ALTER TABLE downloads ADD COLUMN output_directory TEXT;
+PRAGMA user_version = 5;
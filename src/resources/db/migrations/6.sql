@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS groups (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    directory TEXT,
+    date_added INTEGER NOT NULL,
+    date_completed INTEGER
+);
+ALTER TABLE downloads ADD COLUMN group_id INTEGER;
+CREATE INDEX IF NOT EXISTS downloads_group_id ON downloads (group_id);
+PRAGMA user_version = 6;
@@ -0,0 +1,74 @@
+CREATE TABLE IF NOT EXISTS download_tags (
+    download_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (download_id, tag)
+);
+CREATE INDEX IF NOT EXISTS download_tags_tag ON download_tags (tag);
+CREATE TABLE IF NOT EXISTS download_notes (
+    download_id INTEGER PRIMARY KEY,
+    note TEXT NOT NULL
+);
+
+-- Full-text index of downloads, the rowid is the download id
+CREATE VIRTUAL TABLE IF NOT EXISTS downloads_search USING fts5(url, file_name, tags, note);
+INSERT INTO downloads_search (rowid, url, file_name, tags, note)
+SELECT
+    id,
+    url,
+    coalesce(nullif(output_file, 'NULL'), nullif(detected_output_file, 'NULL'), ''),
+    '',
+    ''
+FROM downloads;
+
+CREATE TRIGGER IF NOT EXISTS downloads_search_insert AFTER INSERT ON downloads BEGIN
+    INSERT INTO downloads_search (rowid, url, file_name, tags, note)
+    VALUES (
+        new.id,
+        new.url,
+        coalesce(nullif(new.output_file, 'NULL'), nullif(new.detected_output_file, 'NULL'), ''),
+        '',
+        ''
+    );
+END;
+CREATE TRIGGER IF NOT EXISTS downloads_search_update
+AFTER UPDATE OF url, output_file, detected_output_file ON downloads BEGIN
+    UPDATE downloads_search
+    SET
+        url = new.url,
+        file_name = coalesce(
+            nullif(new.output_file, 'NULL'),
+            nullif(new.detected_output_file, 'NULL'),
+            ''
+        )
+    WHERE rowid = new.id;
+END;
+CREATE TRIGGER IF NOT EXISTS downloads_search_delete AFTER DELETE ON downloads BEGIN
+    DELETE FROM downloads_search WHERE rowid = old.id;
+    DELETE FROM download_tags WHERE download_id = old.id;
+    DELETE FROM download_notes WHERE download_id = old.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS download_tags_insert AFTER INSERT ON download_tags BEGIN
+    UPDATE downloads_search
+    SET tags = (SELECT group_concat(tag, ' ') FROM download_tags WHERE download_id = new.download_id)
+    WHERE rowid = new.download_id;
+END;
+CREATE TRIGGER IF NOT EXISTS download_tags_delete AFTER DELETE ON download_tags BEGIN
+    UPDATE downloads_search
+    SET tags = coalesce(
+        (SELECT group_concat(tag, ' ') FROM download_tags WHERE download_id = old.download_id),
+        ''
+    )
+    WHERE rowid = old.download_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS download_notes_insert AFTER INSERT ON download_notes BEGIN
+    UPDATE downloads_search SET note = new.note WHERE rowid = new.download_id;
+END;
+CREATE TRIGGER IF NOT EXISTS download_notes_update AFTER UPDATE ON download_notes BEGIN
+    UPDATE downloads_search SET note = new.note WHERE rowid = new.download_id;
+END;
+CREATE TRIGGER IF NOT EXISTS download_notes_delete AFTER DELETE ON download_notes BEGIN
+    UPDATE downloads_search SET note = '' WHERE rowid = old.download_id;
+END;
+PRAGMA user_version = 7;
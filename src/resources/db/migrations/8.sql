@@ -0,0 +1,7 @@
+ALTER TABLE downloads ADD COLUMN category TEXT NOT NULL DEFAULT '';
+CREATE INDEX IF NOT EXISTS downloads_category ON downloads (category);
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+PRAGMA user_version = 8;
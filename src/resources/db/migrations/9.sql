@@ -0,0 +1,103 @@
+-- Rebuild the downloads table with real NULLs, integer booleans and a checked status
+DROP TRIGGER IF EXISTS downloads_search_insert;
+DROP TRIGGER IF EXISTS downloads_search_update;
+DROP TRIGGER IF EXISTS downloads_search_delete;
+DROP INDEX IF EXISTS downloads_group_id;
+DROP INDEX IF EXISTS downloads_category;
+
+CREATE TABLE downloads_typed (
+    id INTEGER PRIMARY KEY,
+    url TEXT NOT NULL,
+    status TEXT NOT NULL CHECK (status IN (
+        'pending',
+        'starting',
+        'in_progress',
+        'paused',
+        'canceled',
+        'completed',
+        'server_error',
+        'client_error',
+        'unknown_error',
+        'awaiting_conflict_resolution',
+        'insufficient_space',
+        'retrying'
+    )),
+    data_confirmed INTEGER NOT NULL CHECK (data_confirmed IN (0, 1)),
+    detected_output_file TEXT,
+    output_file TEXT,
+    temp_file TEXT NOT NULL,
+    resumable INTEGER NOT NULL CHECK (resumable IN (0, 1)),
+    date_added INTEGER NOT NULL,
+    date_completed INTEGER,
+    size INTEGER,
+    conflict_policy TEXT,
+    etag TEXT,
+    last_modified TEXT,
+    downloaded_bytes INTEGER NOT NULL DEFAULT 0,
+    output_directory TEXT,
+    group_id INTEGER,
+    category TEXT NOT NULL DEFAULT ''
+);
+
+INSERT INTO downloads_typed
+SELECT
+    id,
+    url,
+    CASE
+        WHEN status IN (
+            'pending',
+            'starting',
+            'in_progress',
+            'paused',
+            'canceled',
+            'completed',
+            'server_error',
+            'client_error',
+            'unknown_error',
+            'awaiting_conflict_resolution',
+            'insufficient_space',
+            'retrying'
+        ) THEN status
+        ELSE 'unknown_error'
+    END,
+    CASE WHEN data_confirmed IN ('true', 1) THEN 1 ELSE 0 END,
+    nullif(detected_output_file, 'NULL'),
+    nullif(output_file, 'NULL'),
+    coalesce(temp_file, ''),
+    CASE WHEN resumable IN ('true', 1) THEN 1 ELSE 0 END,
+    CAST(date_added AS INTEGER),
+    CAST(nullif(date_completed, 'NULL') AS INTEGER),
+    CAST(nullif(size, 'NULL') AS INTEGER),
+    nullif(conflict_policy, 'NULL'),
+    nullif(etag, 'NULL'),
+    nullif(last_modified, 'NULL'),
+    CAST(downloaded_bytes AS INTEGER),
+    nullif(output_directory, 'NULL'),
+    CAST(nullif(group_id, 'NULL') AS INTEGER),
+    category
+FROM downloads;
+
+DROP TABLE downloads;
+ALTER TABLE downloads_typed RENAME TO downloads;
+
+CREATE INDEX IF NOT EXISTS downloads_group_id ON downloads (group_id);
+CREATE INDEX IF NOT EXISTS downloads_category ON downloads (category);
+
+CREATE TRIGGER IF NOT EXISTS downloads_search_insert AFTER INSERT ON downloads BEGIN
+    INSERT INTO downloads_search (rowid, url, file_name, tags, note)
+    VALUES (new.id, new.url, coalesce(new.output_file, new.detected_output_file, ''), '', '');
+END;
+CREATE TRIGGER IF NOT EXISTS downloads_search_update
+AFTER UPDATE OF url, output_file, detected_output_file ON downloads BEGIN
+    UPDATE downloads_search
+    SET
+        url = new.url,
+        file_name = coalesce(new.output_file, new.detected_output_file, '')
+    WHERE rowid = new.id;
+END;
+CREATE TRIGGER IF NOT EXISTS downloads_search_delete AFTER DELETE ON downloads BEGIN
+    DELETE FROM downloads_search WHERE rowid = old.id;
+    DELETE FROM download_tags WHERE download_id = old.id;
+    DELETE FROM download_notes WHERE download_id = old.id;
+END;
+PRAGMA user_version = 9;
@@ -1 +1,42 @@
-//use crate::core::download;
+use crate::core::config::{Config, DEFAULT_CONFIG};
+
+#[test]
+fn test_update_config_from_map() {
+    let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+    let default_config = config.clone();
+
+    config
+        .update_from_map("max_sim_downloads = 2\n[categories.videos]\ndirectory = \"~/Videos\"")
+        .unwrap();
+    assert_eq!(config.max_sim_downloads, 2);
+    assert_eq!(config.categories["videos"].directory, "~/Videos");
+    assert_eq!(config.max_retries, default_config.max_retries);
+    assert_eq!(
+        default_config.get_changed_keys(&config),
+        vec!["categories", "max_sim_downloads"]
+    );
+
+    // Nothing changes when a setting has the wrong type
+    assert!(config
+        .update_from_map("max_retries = \"ten\"\nidle_timeout = 5")
+        .is_err());
+    assert!(config.update_from_map("max_sim_downloads = -1").is_err());
+    assert_eq!(config.idle_timeout, default_config.idle_timeout);
+    assert_eq!(config.max_sim_downloads, 2);
+}
+
+#[test]
+fn test_validate_config() {
+    let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+    assert!(config.validate().is_ok());
+
+    config.update_from_map("speed_limit = 512").unwrap();
+    assert!(config.validate().is_err());
+    config.update_from_map("speed_limit = 1048576").unwrap();
+    assert!(config.validate().is_ok());
+
+    config
+        .update_from_map("[categories.builds]\ndirectory = \"~/Builds\"\nurl_patterns = [\"(\"]")
+        .unwrap();
+    assert!(config.validate().is_err());
+}
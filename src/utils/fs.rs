@@ -0,0 +1,56 @@
+use std::ffi::CString;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Gets the space available to unprivileged users on the filesystem of a path.
+/// Paths that do not exist yet are checked through their closest existing parent.
+pub fn get_available_space(path: &Path) -> io::Result<u64> {
+    let path = get_existing_ancestor(path);
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Checks if two paths are on the same filesystem
+pub fn is_same_filesystem(first_path: &Path, second_path: &Path) -> io::Result<bool> {
+    let first_device = get_existing_ancestor(first_path).metadata()?.dev();
+    let second_device = get_existing_ancestor(second_path).metadata()?.dev();
+    Ok(first_device == second_device)
+}
+
+/// Reserves disk blocks for a file without changing its size
+pub fn preallocate(file: &impl AsRawFd, offset: u64, length: u64) -> io::Result<()> {
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            length as libc::off_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Gets the bytes allocated past the end of a file, like the blocks preallocated for the
+/// rest of a download
+pub fn get_reserved_space(path: &Path) -> io::Result<u64> {
+    let metadata = path.metadata()?;
+    Ok((metadata.blocks() * 512).saturating_sub(metadata.len()))
+}
+
+fn get_existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."))
+}
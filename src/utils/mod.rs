@@ -1,3 +1,4 @@
 pub mod path;
 pub mod tests;
-pub mod fs;
\ No newline at end of file
+pub mod fs;
+pub mod xdg;
\ No newline at end of file
@@ -3,3 +3,13 @@ use shellexpand::tilde;
 pub fn expand(path: &str) -> String {
     String::from(tilde(path))
 }
+
+/// Makes a value safe to be used as a single path segment
+pub fn sanitize_path_segment(value: &str) -> String {
+    let value = value.replace(['/', '\\', '\0'], "_");
+    if value == "." || value == ".." {
+        "_".to_string()
+    } else {
+        value
+    }
+}
@@ -1,5 +1,8 @@
 use std::fs;
 
+#[cfg(test)]
+use super::xdg::{parse_dir_list, parse_user_dir, resolve_base_dir};
+
 /// Test file that will be removed when its instance is dropped.
 /// 
 /// Useful for creating temporary files for testing that get cleaned up when a test fails for.
@@ -7,6 +10,8 @@ use std::fs;
 /// # Example
 /// 
 /// ```
+/// use flow_lib::utils::tests::TestFile;
+///
 /// // When this variable gets dropped, the file will be removed
 /// let test_file = TestFile::new("10MB-TESTFILE.ORG.pdf");
 /// ```
@@ -23,7 +28,8 @@ impl TestFile {
     }
 
     fn remove(&self) {
-        fs::remove_file(&self.file_path).unwrap();
+        // The code under test may have moved it already
+        _ = fs::remove_file(&self.file_path);
     }
 }
 
@@ -31,4 +37,38 @@ impl Drop for TestFile {
     fn drop(&mut self) {
         self.remove();
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_parse_user_dir() {
+    let content = r#"
+# This file is written by xdg-user-dirs-update
+XDG_DESKTOP_DIR="$HOME/Desktop"
+XDG_DOWNLOAD_DIR="$HOME/Téléchargements"
+XDG_MUSIC_DIR="/data/music"
+XDG_VIDEOS_DIR="videos"
+"#;
+    assert_eq!(
+        parse_user_dir(content, "XDG_DOWNLOAD_DIR", "/home/user"),
+        Some("/home/user/Téléchargements".to_string())
+    );
+    assert_eq!(
+        parse_user_dir(content, "XDG_MUSIC_DIR", "/home/user"),
+        Some("/data/music".to_string())
+    );
+    assert_eq!(parse_user_dir(content, "XDG_VIDEOS_DIR", "/home/user"), None);
+    assert_eq!(parse_user_dir(content, "XDG_PICTURES_DIR", "/home/user"), None);
+}
+
+#[test]
+fn test_xdg_base_dirs() {
+    assert_eq!(resolve_base_dir(Some("/srv/data"), "/fallback"), "/srv/data");
+    assert_eq!(resolve_base_dir(Some("relative"), "/fallback"), "/fallback");
+    assert_eq!(resolve_base_dir(None, "/fallback"), "/fallback");
+
+    assert_eq!(
+        parse_dir_list(Some("/usr/etc/xdg:relative::/etc/xdg"), "/fallback"),
+        vec!["/usr/etc/xdg", "/etc/xdg"]
+    );
+    assert_eq!(parse_dir_list(Some(""), "/etc/xdg"), vec!["/etc/xdg"]);
+}
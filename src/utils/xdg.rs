@@ -0,0 +1,88 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use super::path;
+
+/// Directory of the user config files, `$XDG_CONFIG_HOME` or `~/.config`
+pub fn get_config_home() -> String {
+    resolve_base_dir(env::var("XDG_CONFIG_HOME").ok().as_deref(), "~/.config")
+}
+
+/// Directory of the user data files, `$XDG_DATA_HOME` or `~/.local/share`
+pub fn get_data_home() -> String {
+    resolve_base_dir(env::var("XDG_DATA_HOME").ok().as_deref(), "~/.local/share")
+}
+
+/// Directory of the user files that can be recreated, `$XDG_CACHE_HOME` or `~/.cache`
+pub fn get_cache_home() -> String {
+    resolve_base_dir(env::var("XDG_CACHE_HOME").ok().as_deref(), "~/.cache")
+}
+
+/// Directories of the system config files, the most important first
+pub fn get_config_dirs() -> Vec<String> {
+    parse_dir_list(env::var("XDG_CONFIG_DIRS").ok().as_deref(), "/etc/xdg")
+}
+
+/// Download directory of the user set in `user-dirs.dirs`, `~/Downloads` if not set
+pub fn get_download_dir() -> String {
+    let user_dirs_path = Path::new(&get_config_home()).join("user-dirs.dirs");
+    fs::read_to_string(user_dirs_path)
+        .ok()
+        .and_then(|content| parse_user_dir(&content, "XDG_DOWNLOAD_DIR", &path::expand("~")))
+        .unwrap_or_else(|| path::expand("~/Downloads"))
+}
+
+/// Returns the value of a base directory variable, relative paths are ignored as the
+/// specification requires
+pub fn resolve_base_dir(value: Option<&str>, fallback: &str) -> String {
+    match value {
+        Some(value) if Path::new(value).is_absolute() => value.to_string(),
+        _ => path::expand(fallback),
+    }
+}
+
+/// Splits a colon separated list of directories, relative paths are ignored
+pub fn parse_dir_list(value: Option<&str>, fallback: &str) -> Vec<String> {
+    let dirs = value
+        .unwrap_or_default()
+        .split(':')
+        .filter(|dir| Path::new(dir).is_absolute())
+        .map(str::to_string)
+        .collect::<Vec<String>>();
+    if dirs.is_empty() {
+        vec![fallback.to_string()]
+    } else {
+        dirs
+    }
+}
+
+/// Reads a directory from the content of `user-dirs.dirs`
+///
+/// # Arguments
+///
+/// * `content` - Lines like `XDG_DOWNLOAD_DIR="$HOME/Downloads"`
+/// * `name` - The variable to read
+/// * `home` - The value of `$HOME`
+///
+/// # Returns
+///
+/// * `Option<String>` - The absolute directory, `None` if not set or invalid
+pub fn parse_user_dir(content: &str, name: &str, home: &str) -> Option<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            if key.trim() != name {
+                return None;
+            }
+            let value = value.trim().trim_matches('"');
+            let value = match value.strip_prefix("$HOME") {
+                Some(rest) => format!("{}{}", home, rest),
+                None => value.to_string(),
+            };
+            Path::new(&value).is_absolute().then_some(value)
+        })
+}